@@ -1,42 +1,13 @@
 // Ki Shogi - AI engine
 
+use std::collections::HashMap;
 use crate::types::{Owner, Cube, Face};
-use crate::state::{GameState, LegalMove};
-use crate::rules::{cheb_dist, is_in_check, is_piece_supported, get_legal_moves, get_legal_drops};
-
-// ---------------------------------------------------------------------------
-// Action
-// ---------------------------------------------------------------------------
-
-#[derive(Debug, Clone)]
-pub enum Action {
-    Move {
-        piece_idx: usize,
-        mv: LegalMove,
-        promote_to: Option<Face>,
-    },
-    Drop {
-        piece_idx: usize,
-        face: Face,
-        pos: (i32, i32),
-    },
-}
-
-// ---------------------------------------------------------------------------
-// Undo info for apply/undo
-// ---------------------------------------------------------------------------
-
-struct Undo {
-    px: Option<i32>,
-    py: Option<i32>,
-    pface: Face,
-    // capture restore
-    cx: Option<i32>,
-    cy: Option<i32>,
-    cowner: Option<Owner>,
-    cface: Option<Face>,
-    cap_idx: Option<usize>,
-}
+use crate::state::{Action, GameState, PositionRecord};
+use crate::rng::Rng;
+use crate::rules::{
+    cheb_dist, is_in_check, is_piece_supported, get_legal_moves, get_legal_drops,
+    get_white_gyoku_positions, make, unmake,
+};
 
 // ---------------------------------------------------------------------------
 // Evaluate
@@ -74,79 +45,6 @@ pub fn evaluate(state: &GameState, ai_owner: Owner) -> i32 {
     score
 }
 
-// ---------------------------------------------------------------------------
-// Apply / Undo
-// ---------------------------------------------------------------------------
-
-fn apply_action(state: &mut GameState, action: &Action) -> Undo {
-    match action {
-        Action::Move { piece_idx, mv, promote_to } => {
-            let pi = *piece_idx;
-            let undo = Undo {
-                px: state.pieces[pi].x, py: state.pieces[pi].y,
-                pface: state.pieces[pi].face,
-                cx: None, cy: None, cowner: None, cface: None, cap_idx: None,
-            };
-            if let Some(ci) = mv.capture {
-                let mut u = undo;
-                u.cx = state.pieces[ci].x;
-                u.cy = state.pieces[ci].y;
-                u.cowner = Some(state.pieces[ci].owner);
-                u.cface = Some(state.pieces[ci].face);
-                u.cap_idx = Some(ci);
-                state.pieces[ci].x = None;
-                state.pieces[ci].y = None;
-                state.pieces[ci].owner = state.pieces[pi].owner;
-                state.pieces[pi].x = Some(mv.x);
-                state.pieces[pi].y = Some(mv.y);
-                if let Some(pt) = promote_to {
-                    state.pieces[pi].face = *pt;
-                }
-                u
-            } else {
-                state.pieces[pi].x = Some(mv.x);
-                state.pieces[pi].y = Some(mv.y);
-                let cube = state.pieces[pi].cube;
-                if cube != Cube::Gyoku {
-                    if let Some(opp) = state.pieces[pi].face.opposite() {
-                        state.pieces[pi].face = opp;
-                    }
-                }
-                undo
-            }
-        }
-        Action::Drop { piece_idx, face, pos } => {
-            let pi = *piece_idx;
-            let undo = Undo {
-                px: state.pieces[pi].x, py: state.pieces[pi].y,
-                pface: state.pieces[pi].face,
-                cx: None, cy: None, cowner: None, cface: None, cap_idx: None,
-            };
-            state.pieces[pi].face = *face;
-            state.pieces[pi].x = Some(pos.0);
-            state.pieces[pi].y = Some(pos.1);
-            undo
-        }
-    }
-}
-
-fn undo_action(state: &mut GameState, action: &Action, undo: Undo) {
-    match action {
-        Action::Move { piece_idx, .. } | Action::Drop { piece_idx, .. } => {
-            let pi = *piece_idx;
-            state.pieces[pi].x = undo.px;
-            state.pieces[pi].y = undo.py;
-            state.pieces[pi].face = undo.pface;
-            if let Some(ci) = undo.cap_idx {
-                state.pieces[ci].x = undo.cx;
-                state.pieces[ci].y = undo.cy;
-                if let Some(o) = undo.cowner { state.pieces[ci].owner = o; }
-                if let Some(f) = undo.cface { state.pieces[ci].face = f; }
-            }
-        }
-    }
-}
-
 // ---------------------------------------------------------------------------
 // Get all actions
 // ---------------------------------------------------------------------------
@@ -162,7 +60,9 @@ pub fn get_all_actions(state: &mut GameState, owner: Owner) -> Vec<Action> {
         let face = state.pieces[pidx].face;
         let cube = state.pieces[pidx].cube;
         for mv in moves {
-            if mv.capture.is_some() && cube != Cube::Gyoku {
+            // A Gyoku never promotes, and taking a Gyoku ends the game
+            let takes_gyoku = mv.capture.is_some_and(|ci| state.pieces[ci].cube == Cube::Gyoku);
+            if mv.capture.is_some() && cube != Cube::Gyoku && !takes_gyoku {
                 let promos = face.promotions();
                 if !promos.is_empty() {
                     for &pf in promos {
@@ -225,7 +125,7 @@ fn minimax(state: &mut GameState, depth: i32, mut alpha: i32, mut beta: i32, is_
     if actions.is_empty() { return if is_max { -9999 } else { 9999 }; }
 
     // Sort by move order score descending, then truncate to limit branching
-    actions.sort_by(|a, b| move_order_score(b, state).cmp(&move_order_score(a, state)));
+    actions.sort_by_key(|a| std::cmp::Reverse(move_order_score(a, state)));
     actions.truncate(MAX_MOVES);
 
     if is_max {
@@ -236,9 +136,9 @@ fn minimax(state: &mut GameState, depth: i32, mut alpha: i32, mut beta: i32, is_
                     if state.pieces[ci].cube == Cube::Gyoku { return 9999; }
                 }
             }
-            let u = apply_action(state, action);
+            let undo = make(state, action);
             let val = minimax(state, depth - 1, alpha, beta, false, ai_owner);
-            undo_action(state, action, u);
+            unmake(state, undo);
             best = best.max(val);
             alpha = alpha.max(val);
             if beta <= alpha { break; }
@@ -252,9 +152,9 @@ fn minimax(state: &mut GameState, depth: i32, mut alpha: i32, mut beta: i32, is_
                     if state.pieces[ci].cube == Cube::Gyoku { return -9999; }
                 }
             }
-            let u = apply_action(state, action);
+            let undo = make(state, action);
             let val = minimax(state, depth - 1, alpha, beta, true, ai_owner);
-            undo_action(state, action, u);
+            unmake(state, undo);
             best = best.min(val);
             beta = beta.min(val);
             if beta <= alpha { break; }
@@ -264,57 +164,39 @@ fn minimax(state: &mut GameState, depth: i32, mut alpha: i32, mut beta: i32, is_
 }
 
 // ---------------------------------------------------------------------------
-// Position hash + repetition penalty
+// Repetition penalty
 // ---------------------------------------------------------------------------
 
-pub fn position_hash(state: &GameState) -> String {
-    let bg = match state.gyoku(Owner::Black) {
-        Some(i) => &state.pieces[i],
-        None => return String::new(),
-    };
-    let (ox, oy) = (bg.x.unwrap_or(0), bg.y.unwrap_or(0));
-
-    let mut board: Vec<String> = state.pieces.iter()
-        .filter(|p| p.on_board())
-        .map(|p| format!("{},{},{:?},{:?}", p.x.unwrap() - ox, p.y.unwrap() - oy, p.owner, p.face))
-        .collect();
-    board.sort();
-
-    let mut hand: Vec<String> = state.pieces.iter()
-        .filter(|p| !p.on_board())
-        .map(|p| format!("{:?},{:?},{:?}", p.owner, p.cube, p.face))
-        .collect();
-    hand.sort();
-
-    format!("{:?}|{}|{}", state.turn, board.join(";"), hand.join(";"))
-}
-
-fn ai_repetition_penalty(state: &mut GameState, action: &Action, owner: Owner) -> i32 {
+fn ai_repetition_penalty(
+    state: &mut GameState, seen: &HashMap<u64, Vec<PositionRecord>>, action: &Action, owner: Owner,
+) -> i32 {
     let opponent = owner.opponent();
-    let u = apply_action(state, action);
-    let orig_turn = state.turn;
-    state.turn = opponent;
-    let hash = position_hash(state);
+    let undo = make(state, action);
+    let hash = state.position_key();
     let would_check = is_in_check(state, opponent);
-    state.turn = orig_turn;
-    undo_action(state, action, u);
+    unmake(state, undo);
 
-    let hist = match state.position_history.get(&hash) {
+    let hist = match seen.get(&hash) {
         Some(h) if !h.is_empty() => h,
         _ => return 0,
     };
-    let all_check = hist.iter().all(|h| h.in_check);
+    let perpetual = state.rules.perpetual_check_loses
+        && would_check && hist.iter().all(|h| h.in_check);
+    // Occurrences still missing for the game to end after this action
+    let to_limit = state.rules.repetition.saturating_sub(hist.len() + 1);
 
     // Perpetual check penalties
-    if hist.len() >= 3 && all_check && would_check { return -5000; }
-    if hist.len() >= 2 && all_check && would_check { return -3000; }
-    if hist.len() >= 1 && all_check && would_check { return -800; }
+    if perpetual {
+        return match to_limit { 0 => -5000, 1 => -3000, _ => -800 };
+    }
 
     // Sennichite draw: adjust based on eval
     let eval = evaluate(state, owner);
-    if hist.len() >= 3 { return if eval > 0 { -2000 } else { 2000 }; }
-    if hist.len() >= 2 { return if eval > 0 { -500 } else { 500 }; }
-    if eval > 0 { -50 } else { 30 }
+    match to_limit {
+        0 => if eval > 0 { -2000 } else { 2000 },
+        1 => if eval > 0 { -500 } else { 500 },
+        _ => if eval > 0 { -50 } else { 30 },
+    }
 }
 
 fn score_actions(state: &mut GameState, actions: &mut [(Action, i32)], owner: Owner) {
@@ -326,8 +208,8 @@ fn score_actions(state: &mut GameState, actions: &mut [(Action, i32)], owner: Ow
     });
 
     for (action, score) in actions.iter_mut() {
-        match action {
-            Action::Move { piece_idx, mv, .. } => {
+        match &*action {
+            Action::Move { mv, .. } => {
                 if let Some(ci) = mv.capture {
                     if state.pieces[ci].cube == Cube::Gyoku {
                         *score += 1000;
@@ -336,21 +218,9 @@ fn score_actions(state: &mut GameState, actions: &mut [(Action, i32)], owner: Ow
                     }
                 } else {
                     // Check if move gives check
-                    let pi = *piece_idx;
-                    let ox = state.pieces[pi].x;
-                    let oy = state.pieces[pi].y;
-                    let of = state.pieces[pi].face;
-                    state.pieces[pi].x = Some(mv.x);
-                    state.pieces[pi].y = Some(mv.y);
-                    if state.pieces[pi].cube != Cube::Gyoku {
-                        if let Some(opp) = state.pieces[pi].face.opposite() {
-                            state.pieces[pi].face = opp;
-                        }
-                    }
+                    let undo = make(state, action);
                     if is_in_check(state, opponent) { *score += 50; }
-                    state.pieces[pi].x = ox;
-                    state.pieces[pi].y = oy;
-                    state.pieces[pi].face = of;
+                    unmake(state, undo);
                 }
                 if let Some((ox, oy)) = opp_pos {
                     *score += 0i32.max(5 - cheb_dist(mv.x, mv.y, ox, oy));
@@ -370,19 +240,21 @@ fn score_actions(state: &mut GameState, actions: &mut [(Action, i32)], owner: Ow
 // AI move selection
 // ---------------------------------------------------------------------------
 
-/// Choose and return the best action for the current AI player.
-/// Returns None if no actions available.
-pub fn ai_choose(state: &mut GameState) -> Option<Action> {
+/// Choose and return the best action at `difficulty` (1-5) for the side
+/// to move, steering by `seen` (`Game::position_history`) around
+/// repetitions and breaking ties with `rng`. Returns None if no actions
+/// available.
+pub fn ai_choose(
+    state: &mut GameState, seen: &HashMap<u64, Vec<PositionRecord>>, difficulty: u8, rng: &mut Rng,
+) -> Option<Action> {
     let owner = state.turn;
     let mut actions = get_all_actions(state, owner);
     if actions.is_empty() { return None; }
 
-    let difficulty = state.ai_difficulty;
-
     // Easy: random, but avoid perpetual check loss
     if difficulty == 1 {
         let penalties: Vec<i32> = actions.iter()
-            .map(|a| ai_repetition_penalty(state, a, owner))
+            .map(|a| ai_repetition_penalty(state, seen, a, owner))
             .collect();
         let safe: Vec<usize> = (0..actions.len())
             .filter(|&i| penalties[i] > -5000)
@@ -390,7 +262,7 @@ pub fn ai_choose(state: &mut GameState) -> Option<Action> {
         let pool = if safe.is_empty() {
             (0..actions.len()).collect::<Vec<_>>()
         } else { safe };
-        let idx = pool[rand_usize(pool.len())];
+        let idx = pool[rng.below(pool.len())];
         return Some(actions.swap_remove(idx));
     }
 
@@ -402,7 +274,7 @@ pub fn ai_choose(state: &mut GameState) -> Option<Action> {
             _ => 1,
         };
         let depth = adaptive_depth(base_depth, actions.len());
-        actions.sort_by(|a, b| move_order_score(b, state).cmp(&move_order_score(a, state)));
+        actions.sort_by_key(|a| std::cmp::Reverse(move_order_score(a, state)));
         let mut best_score = i32::MIN;
         let mut best_idxs = Vec::new();
         for i in 0..actions.len() {
@@ -413,21 +285,21 @@ pub fn ai_choose(state: &mut GameState) -> Option<Action> {
                     }
                 }
             }
-            let u = apply_action(state, &actions[i]);
-            let score = minimax(state, depth, best_score, i32::MAX, false, owner)
-                + ai_repetition_penalty(state, &actions[i], owner);
-            undo_action(state, &actions[i], u);
+            let undo = make(state, &actions[i]);
+            let score = minimax(state, depth, best_score, i32::MAX, false, owner);
+            unmake(state, undo);
+            let score = score + ai_repetition_penalty(state, seen, &actions[i], owner);
             if score > best_score { best_score = score; best_idxs = vec![i]; }
             else if score == best_score { best_idxs.push(i); }
         }
-        let idx = best_idxs[rand_usize(best_idxs.len())];
+        let idx = best_idxs[rng.below(best_idxs.len())];
         return Some(actions.swap_remove(idx));
     }
 
     // Medium: heuristic scoring
     let mut scored: Vec<(Action, i32)> = actions.into_iter()
         .map(|a| {
-            let pen = ai_repetition_penalty(state, &a, owner);
+            let pen = ai_repetition_penalty(state, seen, &a, owner);
             (a, pen)
         })
         .collect();
@@ -437,16 +309,41 @@ pub fn ai_choose(state: &mut GameState) -> Option<Action> {
         .filter(|(_, s)| *s == max_s)
         .map(|(a, _)| a)
         .collect();
-    Some(best[rand_usize(best.len())].clone())
+    Some(best[rng.below(best.len())].clone())
 }
 
-/// Simple pseudo-random using std
-fn rand_usize(max: usize) -> usize {
-    use std::time::SystemTime;
-    if max == 0 { return 0; }
-    let seed = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .unwrap_or_default()
-        .subsec_nanos() as usize;
-    seed % max
+// ---------------------------------------------------------------------------
+// Resigning and draws
+// ---------------------------------------------------------------------------
+
+/// How `owner` stands after a shallow search, whichever side is to move.
+fn outlook(state: &mut GameState, owner: Owner) -> i32 {
+    minimax(state, 2, i32::MIN, i32::MAX, state.turn == owner, owner)
+}
+
+/// Whether the AI to move gives the game up, scoring below `resign_below`.
+/// Without a threshold it never does, and no search is run.
+pub fn ai_resigns(state: &mut GameState, resign_below: Option<i32>) -> bool {
+    let owner = state.turn;
+    resign_below.is_some_and(|limit| outlook(state, owner) < limit)
+}
+
+/// Whether the AI answering the pending draw offer takes it, scoring below
+/// `draw_below`. Without a threshold it declines, and no search is run.
+pub fn ai_accepts_draw(state: &mut GameState, draw_below: Option<i32>) -> bool {
+    let owner = state.turn.opponent();
+    draw_below.is_some_and(|limit| outlook(state, owner) < limit)
+}
+
+/// Choose where the side to move places its Gyoku during setup.
+/// Black always opens at the origin; White picks a random legal square.
+pub fn ai_choose_gyoku(state: &GameState, rng: &mut Rng) -> Option<(i32, i32)> {
+    match state.turn {
+        Owner::Black => Some((0, 0)),
+        Owner::White => {
+            let positions = get_white_gyoku_positions(state);
+            if positions.is_empty() { return None; }
+            Some(positions[rng.below(positions.len())])
+        }
+    }
 }
@@ -0,0 +1,51 @@
+// Ki Shogi - Sparse board map
+
+use std::collections::HashMap;
+use crate::state::Piece;
+
+/// Which piece stands on each occupied cell, keyed by coordinates. The board
+/// is infinite and holds at most a handful of pieces, so only occupied cells
+/// are stored. `GameState` keeps its board in step with `pieces`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Board {
+    cells: HashMap<(i32, i32), usize>,
+}
+
+impl Board {
+    pub fn from_pieces(pieces: &[Piece]) -> Self {
+        let cells = pieces.iter().enumerate()
+            .filter_map(|(i, p)| p.pos().map(|pos| (pos, i)))
+            .collect();
+        Self { cells }
+    }
+
+    /// Index into `pieces` of the piece at (x, y).
+    pub fn get(&self, x: i32, y: i32) -> Option<usize> {
+        self.cells.get(&(x, y)).copied()
+    }
+
+    pub fn is_occupied(&self, x: i32, y: i32) -> bool {
+        self.cells.contains_key(&(x, y))
+    }
+
+    pub fn insert(&mut self, pos: (i32, i32), idx: usize) {
+        self.cells.insert(pos, idx);
+    }
+
+    pub fn remove(&mut self, pos: (i32, i32)) {
+        self.cells.remove(&pos);
+    }
+
+    /// Occupied cells and their piece indices, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = ((i32, i32), usize)> + '_ {
+        self.cells.iter().map(|(&pos, &i)| (pos, i))
+    }
+
+    pub fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+}
@@ -0,0 +1,129 @@
+// Ki Shogi - Dead position detection
+
+use std::collections::HashSet;
+use crate::types::{Cube, Owner};
+use crate::state::{Action, GameState};
+use crate::game::material;
+use crate::rules::{has_legal_action, is_in_check, make, unmake};
+use crate::ai::get_all_actions;
+
+// ---------------------------------------------------------------------------
+// Dead positions
+// ---------------------------------------------------------------------------
+//
+// A position is dead when no sequence of legal actions, however either side
+// plays, ends in a win: no Gyoku can ever be captured and neither side can
+// ever be left without a legal action. Such a game is drawn at once rather
+// than played on until sennichite happens to end it.
+//
+// Pieces never leave the game (a captured cube goes to the captor's hand, a
+// stranded one to the opponent's), every piece on the board stays within a
+// few cells of its Gyoku, and the two Gyoku keep a fixed distance, so up to
+// translation there are only finitely many positions. With little material
+// they are few enough to visit them all, drops from hand included, and that
+// search is the proof. Bare Gyoku against bare Gyoku is dead with the
+// standard pieces; a cube in either hand is not, since it can be dropped.
+//
+// Only study positions qualify: a game from the Gyoku setup always has 4
+// or 6 cubes besides the Gyoku, far more than the search can cover, so
+// `Game` checks `can_be_dead` once and never searches such a game. Under
+// `ply_limit_material` a position with unequal material can still be won
+// at the ply limit, so the search gives up on the first one it meets.
+
+/// Most non-Gyoku pieces, on the board and in hand, for the search to be
+/// tried. More material is never dead in practice and takes too long.
+const MAX_PIECES: usize = 2;
+
+/// Positions visited before giving up on a proof.
+const MAX_POSITIONS: usize = 20_000;
+
+/// Whether the material is small enough for `is_dead_position` to try.
+/// Material is conserved, so this holds for a whole game or not at all.
+pub fn can_be_dead(state: &GameState) -> bool {
+    state.pieces.iter().filter(|p| p.cube != Cube::Gyoku).count() <= MAX_PIECES
+}
+
+/// Whether no line of play from here can end in a win for either side.
+/// Errs on the side of `false`: a position it cannot prove dead is live.
+pub fn is_dead_position(state: &GameState) -> bool {
+    if !can_be_dead(state) {
+        return false;
+    }
+    let by_material = state.rules.ply_limit.is_some() && state.rules.ply_limit_material;
+    let root = state.clone();
+
+    let mut seen = HashSet::from([root.position_key()]);
+    let mut todo = vec![root];
+    while let Some(mut pos) = todo.pop() {
+        // A check may repeat into a perpetual check, which is a win
+        if pos.rules.perpetual_check_loses && is_in_check(&pos, pos.turn) {
+            return false;
+        }
+        if by_material && material(&pos, Owner::Black) != material(&pos, Owner::White) {
+            return false;
+        }
+        let turn = pos.turn;
+        for action in get_all_actions(&mut pos, turn) {
+            let takes_gyoku = match &action {
+                Action::Move { mv, .. } => mv.capture.is_some_and(|ci| pos.pieces[ci].cube == Cube::Gyoku),
+                Action::Drop { .. } => false,
+            };
+            if takes_gyoku {
+                return false;
+            }
+            let undo = make(&mut pos, &action);
+            let next = pos.turn;
+            let stuck = !has_legal_action(&mut pos, next);
+            let key = pos.position_key();
+            if !stuck && seen.insert(key) {
+                if seen.len() > MAX_POSITIONS {
+                    return false;
+                }
+                todo.push(pos.clone());
+            }
+            unmake(&mut pos, undo);
+            if stuck {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ruleset::RuleSet;
+
+    fn position(rules: &str, s: &str) -> GameState {
+        let mut state = GameState::new();
+        state.rules = RuleSet::parse(rules).unwrap();
+        state.load_position(s).unwrap();
+        state
+    }
+
+    #[test]
+    fn bare_gyoku_is_dead() {
+        assert!(is_dead_position(&position("basic", "bGyoku0,0;wGyoku2,2 -/- b basic")));
+        let state = position("basic,ply-limit=20,ply-limit-result=material", "bGyoku0,0;wGyoku2,2 -/- w basic");
+        assert!(is_dead_position(&state));
+    }
+
+    #[test]
+    fn cube_in_hand_or_on_board_is_live() {
+        assert!(!is_dead_position(&position("basic", "bGyoku0,0;wGyoku2,2 Hi/- b basic")));
+        assert!(!is_dead_position(&position("basic", "bGyoku0,0;bHi0,1;wGyoku2,2 -/- w basic")));
+    }
+
+    #[test]
+    fn full_material_is_never_searched() {
+        let mut state = GameState::new();
+        state.init_game(true);
+        assert!(!can_be_dead(&state));
+        state.init_game(false);
+        assert!(!can_be_dead(&state));
+        let state = position("ki", "bGyoku0,0;wGyoku2,2 Ki/Ki b ki");
+        assert!(can_be_dead(&state));
+        assert!(!is_dead_position(&state));
+    }
+}
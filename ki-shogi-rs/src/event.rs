@@ -0,0 +1,91 @@
+// Ki Shogi - Game events
+
+use std::fmt;
+use crate::types::{Owner, Face};
+use crate::state::{Declaration, GameResult, GameState};
+
+// ---------------------------------------------------------------------------
+// Events
+// ---------------------------------------------------------------------------
+//
+// Game reports what each action did as a sequence of events, in the order
+// it happened: the move or drop, then any capture, flip or promotion, then
+// stranding, then check, repetition and the end of the game. A ply reads
+//
+//     Moved, Captured, Promoted, Stranded..., Check
+//
+// Anything that needs to follow a game (the terminal UI, a bell, network
+// sync, statistics) subscribes an `Observer` instead of being called from
+// the game code. Pieces are indices into `GameState::pieces`.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GameEvent {
+    /// A Gyoku placed during setup.
+    PiecePlaced { piece: usize, owner: Owner, at: (i32, i32) },
+    /// A piece moved on the board, showing `face` when it left.
+    Moved { piece: usize, owner: Owner, face: Face, from: (i32, i32), to: (i32, i32) },
+    /// A piece that moved without capturing turned to its opposite face.
+    Flipped { piece: usize, from: Face, to: Face },
+    /// A piece taken into the hand of `by`, showing `face` when taken.
+    Captured { piece: usize, by: Owner, face: Face, at: (i32, i32) },
+    /// A capturing piece turned to the face chosen for it.
+    Promoted { piece: usize, from: Face, to: Face },
+    Dropped { piece: usize, owner: Owner, face: Face, at: (i32, i32) },
+    /// A piece of `owner` left unsupported went to the opponent's hand,
+    /// showing `face` on the board before.
+    Stranded { piece: usize, owner: Owner, face: Face, at: (i32, i32) },
+    /// The side to move is in check.
+    Check { owner: Owner },
+    /// One more occurrence of the position ends the game, lost by the
+    /// checking side when `perpetual_check`.
+    RepetitionWarning { perpetual_check: bool },
+    Declared { owner: Owner, declaration: Declaration },
+    /// The last move or drop was taken back, with any declarations after it.
+    Undone,
+    GameEnded(GameResult),
+}
+
+// ---------------------------------------------------------------------------
+// Observers
+// ---------------------------------------------------------------------------
+
+/// Receives every event of the game it is subscribed to, with the state as
+/// the action left it.
+pub trait Observer {
+    fn notify(&mut self, event: &GameEvent, state: &GameState);
+}
+
+impl<F: FnMut(&GameEvent, &GameState)> Observer for F {
+    fn notify(&mut self, event: &GameEvent, state: &GameState) {
+        self(event, state)
+    }
+}
+
+/// The observers of a game. A clone of the game starts without any, so
+/// copies taken for analysis never report to the original's subscribers.
+#[derive(Default)]
+pub struct Observers(Vec<Box<dyn Observer>>);
+
+impl Observers {
+    pub fn push(&mut self, observer: Box<dyn Observer>) {
+        self.0.push(observer);
+    }
+
+    pub fn notify(&mut self, event: &GameEvent, state: &GameState) {
+        for observer in &mut self.0 {
+            observer.notify(event, state);
+        }
+    }
+}
+
+impl Clone for Observers {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl fmt::Debug for Observers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Observers({})", self.0.len())
+    }
+}
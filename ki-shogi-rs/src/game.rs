@@ -0,0 +1,811 @@
+// Ki Shogi - Headless game controller
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use crate::types::{Owner, Cube, Face};
+use crate::state::{
+    Action, Declaration, EndReason, GameResult, GameState, LogKind, MoveLogEntry, Phase, Piece,
+    PositionError, PositionRecord,
+};
+use crate::rules::{
+    get_legal_moves, get_legal_drops, is_in_check, has_legal_action,
+    get_white_gyoku_positions, make, unmake, Undo,
+};
+use crate::ai::get_all_actions;
+use crate::dead::{can_be_dead, is_dead_position};
+use crate::event::{GameEvent, Observer, Observers};
+use crate::history::{History, ReplayError};
+use crate::notation::Stranding;
+use crate::zobrist::Zobrist;
+use crate::board::Board;
+use crate::ruleset::RuleSet;
+
+// ---------------------------------------------------------------------------
+// Outcome / errors
+// ---------------------------------------------------------------------------
+
+/// What happened as a result of a successfully applied action.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// Play continues with the other side to move.
+    Continue,
+    /// Play continues, but one more occurrence of the position ends the
+    /// game by repetition.
+    SennichiteWarning,
+    /// As `SennichiteWarning`, with the side to move in check every time
+    /// and perpetual check losing under the rules.
+    PerpetualCheckWarning,
+    /// The side to move offered a draw; the other side has to accept or
+    /// decline it before play goes on.
+    DrawOffered,
+    /// The game is over.
+    Ended(GameResult),
+}
+
+impl Outcome {
+    pub fn is_over(self) -> bool {
+        matches!(self, Outcome::Ended(_))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameError {
+    /// The game has already ended.
+    GameOver,
+    /// A Gyoku placement outside setup, or a move or drop during setup.
+    WrongPhase,
+    /// The piece does not belong to the side to move, or is not where the
+    /// action needs it (on the board to move, in hand to drop).
+    NotYourPiece,
+    /// The action is not among the legal actions of the position.
+    IllegalAction,
+    /// A capture needs a promotion face from `Face::promotions()` and none
+    /// (or a wrong one) was given, or a non-capture carried one.
+    InvalidPromotion,
+    /// A draw offer awaits an answer, so nothing else can be played.
+    DrawOfferPending,
+    /// A draw was accepted or declined without being offered.
+    NoDrawOffer,
+}
+
+impl fmt::Display for GameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            GameError::GameOver => "the game is over",
+            GameError::WrongPhase => "action not allowed in this phase",
+            GameError::NotYourPiece => "piece cannot be used by the side to move",
+            GameError::IllegalAction => "illegal action",
+            GameError::InvalidPromotion => "invalid promotion choice",
+            GameError::DrawOfferPending => "a draw offer awaits an answer",
+            GameError::NoDrawOffer => "no draw has been offered",
+        };
+        f.write_str(s)
+    }
+}
+
+impl std::error::Error for GameError {}
+
+// ---------------------------------------------------------------------------
+// Undo history
+// ---------------------------------------------------------------------------
+
+/// An applied action with what `make` changed and the game state around it.
+#[derive(Debug, Clone)]
+struct Ply {
+    action: Action,
+    undo: Undo,
+    in_check: bool,
+    log_len: usize,
+    move_num: usize,
+    quiet_plies: usize,
+    /// The `position_history` key this ply added a record to, if any.
+    history_key: Option<u64>,
+}
+
+// ---------------------------------------------------------------------------
+// Game
+// ---------------------------------------------------------------------------
+
+/// A game of Ki Shogi driven purely through actions: setup placements, moves
+/// (with promotion choice), drops and declarations. Never touches the terminal, so bots,
+/// tools and the TUI all play through the same rules, and follow it through
+/// the events it sends its observers.
+#[derive(Debug, Clone)]
+pub struct Game {
+    pub state: GameState,
+    move_log: Vec<MoveLogEntry>,
+    /// The positions of `move_log`, rebuilt on demand.
+    history: History,
+    move_num: usize,
+    /// Repetition records per `position_key`.
+    position_history: HashMap<u64, Vec<PositionRecord>>,
+    undo_stack: Vec<Ply>,
+    redo_stack: Vec<Action>,
+    observers: Observers,
+    /// Whether the material is small enough for `is_dead_position` to be
+    /// worth running. Material never changes, so this is settled up front.
+    dead_possible: bool,
+}
+
+impl Game {
+    /// A new game with Black to place its Gyoku.
+    pub fn new(use_ki: bool) -> Self {
+        let mut state = GameState::new();
+        state.init_game(use_ki);
+        Self::from_state(state)
+    }
+
+    /// A new game under the given rules, with Black to place its Gyoku.
+    pub fn with_rules(rules: RuleSet) -> Self {
+        let mut state = GameState::new();
+        state.rules = rules;
+        state.init_game(rules.use_ki);
+        Self::from_state(state)
+    }
+
+    /// Take over an existing position with an empty move log. Nothing
+    /// before it can be undone.
+    pub fn from_state(state: GameState) -> Self {
+        let mut game = Self {
+            state,
+            move_log: Vec::new(),
+            history: History::default(),
+            move_num: 0,
+            position_history: HashMap::new(),
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+            observers: Observers::default(),
+            dead_possible: false,
+        };
+        game.restart();
+        game
+    }
+
+    /// Forget everything played before the current position.
+    fn restart(&mut self) {
+        self.move_log.clear();
+        self.history.restart(&self.state.pieces);
+        self.move_num = 0;
+        self.position_history.clear();
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        self.dead_possible = can_be_dead(&self.state);
+    }
+
+    /// Send every event from now on to `observer`.
+    pub fn subscribe(&mut self, observer: impl Observer + 'static) {
+        self.observers.push(Box::new(observer));
+    }
+
+    fn emit(&mut self, event: GameEvent) {
+        self.observers.notify(&event, &self.state);
+    }
+
+    /// Replace the game with a position string, keeping the players. The
+    /// position is in play; it may already be lost for the side to move, or
+    /// dead.
+    pub fn load_position(&mut self, s: &str) -> Result<Outcome, PositionError> {
+        self.state.load_position(s)?;
+        self.restart();
+        let turn = self.state.turn;
+        if !has_legal_action(&mut self.state, turn) {
+            let reason = EndReason::NoLegalAction;
+            return Ok(self.finish(GameResult::Win { winner: turn.opponent(), reason }));
+        }
+        if self.dead_possible && is_dead_position(&self.state) {
+            return Ok(self.finish(GameResult::Draw { reason: EndReason::DeadPosition }));
+        }
+        Ok(Outcome::Continue)
+    }
+
+    pub fn phase(&self) -> Phase {
+        self.state.phase
+    }
+
+    pub fn turn(&self) -> Owner {
+        self.state.turn
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.state.phase == Phase::Over
+    }
+
+    pub fn result(&self) -> Option<GameResult> {
+        self.state.result
+    }
+
+    pub fn move_log(&self) -> &[MoveLogEntry] {
+        &self.move_log
+    }
+
+    /// The pieces as they stood after move log entry `index`; None past
+    /// the end of the log.
+    pub fn pieces_after(&self, index: usize) -> Option<Result<Vec<Piece>, ReplayError>> {
+        self.history.pieces_after(&self.move_log, self.state.rules, index)
+    }
+
+    /// Checkpoint the move log positions every `every` plies, or never.
+    pub fn set_checkpoint_every(&mut self, every: Option<usize>) -> Result<(), ReplayError> {
+        self.history.set_checkpoint_every(every, &self.move_log, self.state.rules)
+    }
+
+    /// How often each position, by `position_key`, has occurred so far.
+    pub fn position_history(&self) -> &HashMap<u64, Vec<PositionRecord>> {
+        &self.position_history
+    }
+
+    /// Legal squares for the Gyoku of the side to move during setup.
+    /// Black may start anywhere, so only White's squares are listed.
+    pub fn gyoku_positions(&self) -> Vec<(i32, i32)> {
+        if self.state.phase != Phase::Setup || self.state.turn == Owner::Black {
+            return Vec::new();
+        }
+        get_white_gyoku_positions(&self.state)
+    }
+
+    /// Every legal move (one entry per promotion choice) and drop for the
+    /// side to move. None while a draw offer awaits an answer.
+    pub fn legal_actions(&mut self) -> Vec<Action> {
+        if self.state.phase != Phase::Play || self.state.draw_offer.is_some() { return Vec::new(); }
+        let turn = self.state.turn;
+        get_all_actions(&mut self.state, turn)
+    }
+
+    /// Place the Gyoku of the side to move. White must be exactly
+    /// Chebyshev distance 2 from Black; once it is placed, play begins.
+    pub fn place_gyoku(&mut self, pos: (i32, i32)) -> Result<Outcome, GameError> {
+        match self.state.phase {
+            Phase::Setup => {}
+            Phase::Play => return Err(GameError::WrongPhase),
+            Phase::Over => return Err(GameError::GameOver),
+        }
+        let owner = self.state.turn;
+        if owner == Owner::White && !get_white_gyoku_positions(&self.state).contains(&pos) {
+            return Err(GameError::IllegalAction);
+        }
+        let gi = self.state.gyoku(owner).ok_or(GameError::NotYourPiece)?;
+        self.state.unindex_piece(gi);
+        self.state.pieces[gi].x = Some(pos.0);
+        self.state.pieces[gi].y = Some(pos.1);
+        self.state.index_piece(gi);
+        self.log_move(MoveLogEntry {
+            cube: Some(Cube::Gyoku),
+            face: Some(Face::Gyoku),
+            face_after: Some(Face::Gyoku),
+            to: Some(pos),
+            ..MoveLogEntry::new(owner, LogKind::Place)
+        });
+        self.state.turn = owner.opponent();
+        if owner == Owner::White {
+            self.state.phase = Phase::Play;
+        }
+        self.emit(GameEvent::PiecePlaced { piece: gi, owner, at: pos });
+        Ok(Outcome::Continue)
+    }
+
+    /// Resign or offer a draw for the side to move, or accept or decline
+    /// the draw it offered for the other side. Declarations are logged but
+    /// not undone on their own: undoing the ply before them removes them.
+    pub fn declare(&mut self, d: Declaration) -> Result<Outcome, GameError> {
+        match self.state.phase {
+            Phase::Play => {}
+            Phase::Setup => return Err(GameError::WrongPhase),
+            Phase::Over => return Err(GameError::GameOver),
+        }
+        let turn = self.state.turn;
+        match (d.is_answer(), self.state.draw_offer.is_some()) {
+            (false, true) => return Err(GameError::DrawOfferPending),
+            (true, false) => return Err(GameError::NoDrawOffer),
+            _ => {}
+        }
+        let owner = if d.is_answer() { turn.opponent() } else { turn };
+        self.log_move(MoveLogEntry::new(owner, LogKind::Declare(d)));
+        self.emit(GameEvent::Declared { owner, declaration: d });
+        Ok(match d {
+            Declaration::Resign => {
+                self.finish(GameResult::Win { winner: turn.opponent(), reason: EndReason::Resignation })
+            }
+            Declaration::OfferDraw => {
+                self.state.draw_offer = Some(turn);
+                Outcome::DrawOffered
+            }
+            Declaration::AcceptDraw => {
+                self.state.draw_offer = None;
+                self.finish(GameResult::Draw { reason: EndReason::Agreement })
+            }
+            Declaration::DeclineDraw => {
+                self.state.draw_offer = None;
+                Outcome::Continue
+            }
+        })
+    }
+
+    /// Apply a move or drop for the side to move, then run stranding,
+    /// repetition and game-over detection. Clears anything to redo.
+    pub fn apply(&mut self, action: &Action) -> Result<Outcome, GameError> {
+        let outcome = self.apply_recorded(action)?;
+        self.redo_stack.clear();
+        Ok(outcome)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+
+    /// Take back the last move or drop, restoring pieces, hands, turn,
+    /// check state, the move log and repetition counts, and dropping any
+    /// declarations made after it. A finished game
+    /// becomes playable again. Gyoku setup cannot be undone.
+    pub fn undo(&mut self) -> Option<Action> {
+        let ply = self.undo_stack.pop()?;
+        let state = &mut self.state;
+        unmake(state, ply.undo);
+        state.in_check = ply.in_check;
+        state.quiet_plies = ply.quiet_plies;
+        state.result = None;
+        state.phase = Phase::Play;
+        state.draw_offer = None;
+        self.move_log.truncate(ply.log_len);
+        self.history.truncate(ply.log_len);
+        self.move_num = ply.move_num;
+        if let Some(key) = ply.history_key {
+            if let Some(hist) = self.position_history.get_mut(&key) {
+                hist.pop();
+                if hist.is_empty() { self.position_history.remove(&key); }
+            }
+        }
+        self.redo_stack.push(ply.action.clone());
+        self.emit(GameEvent::Undone);
+        Some(ply.action)
+    }
+
+    /// Re-apply the most recently undone action.
+    pub fn redo(&mut self) -> Option<Result<Outcome, GameError>> {
+        let action = self.redo_stack.pop()?;
+        Some(self.apply_recorded(&action))
+    }
+
+    /// Apply an action and push it on the undo stack, leaving redo alone.
+    fn apply_recorded(&mut self, action: &Action) -> Result<Outcome, GameError> {
+        match self.state.phase {
+            Phase::Play => {}
+            Phase::Setup => return Err(GameError::WrongPhase),
+            Phase::Over => return Err(GameError::GameOver),
+        }
+        if self.state.draw_offer.is_some() {
+            return Err(GameError::DrawOfferPending);
+        }
+        let action = match action {
+            Action::Move { piece_idx, mv, promote_to } => {
+                self.check_move(*piece_idx, (mv.x, mv.y), *promote_to)?
+            }
+            Action::Drop { piece_idx, face, pos } => self.check_drop(*piece_idx, *face, *pos)?,
+        };
+
+        let turn = self.state.turn;
+        let (pidx, from, to) = match &action {
+            Action::Move { piece_idx, mv, .. } => (*piece_idx, self.state.pieces[*piece_idx].pos(), (mv.x, mv.y)),
+            Action::Drop { piece_idx, pos, .. } => (*piece_idx, None, *pos),
+        };
+        let face = match &action {
+            Action::Move { .. } => self.state.pieces[pidx].face,
+            Action::Drop { face, .. } => *face,
+        };
+        let captured = match &action {
+            Action::Move { mv, .. } => mv.capture.map(|ci| (ci, self.state.pieces[ci].face)),
+            Action::Drop { .. } => None,
+        };
+        let took_gyoku = captured.is_some_and(|(ci, _)| self.state.pieces[ci].cube == Cube::Gyoku);
+        let (kind, promote_to) = match &action {
+            Action::Move { promote_to, .. } if captured.is_some() => (LogKind::Capture, *promote_to),
+            Action::Move { .. } => (LogKind::Move, None),
+            Action::Drop { .. } => (LogKind::Drop, None),
+        };
+        let cube = self.state.pieces[pidx].cube;
+        let hand_face = from.is_none().then_some(self.state.pieces[pidx].face);
+
+        let in_check = self.state.in_check;
+        let log_len = self.move_log.len();
+        let move_num = self.move_num;
+        let quiet_plies = self.state.quiet_plies;
+        let progress = match &action {
+            Action::Move { mv, .. } => mv.capture.is_some(),
+            Action::Drop { .. } => true,
+        };
+        let undo = make(&mut self.state, &action);
+        let progress = progress || undo.stranded().next().is_some();
+        self.state.quiet_plies = if progress { 0 } else { quiet_plies + 1 };
+        // By square, as piece order differs between a game and its replay
+        let mut stranded: Vec<_> = undo.stranded_from().collect();
+        stranded.sort_by_key(|&(_, at, _)| at);
+        let new_face = self.state.pieces[pidx].face;
+        self.undo_stack.push(Ply { action, undo, in_check, log_len, move_num, quiet_plies, history_key: None });
+        self.log_move(MoveLogEntry {
+            cube: Some(cube),
+            face: Some(hand_face.unwrap_or(face)),
+            face_after: Some(new_face),
+            from,
+            to: Some(to),
+            captured: captured.map(|(_, f)| f),
+            promote_to,
+            stranded: stranded.iter().map(|&(_, at, face)| Stranding { face, at }).collect(),
+            ..MoveLogEntry::new(turn, kind)
+        });
+
+        match from {
+            Some(from) => {
+                self.emit(GameEvent::Moved { piece: pidx, owner: turn, face, from, to });
+                if let Some((ci, captured_face)) = captured {
+                    self.emit(GameEvent::Captured { piece: ci, by: turn, face: captured_face, at: to });
+                }
+                if new_face != face {
+                    self.emit(if captured.is_some() {
+                        GameEvent::Promoted { piece: pidx, from: face, to: new_face }
+                    } else {
+                        GameEvent::Flipped { piece: pidx, from: face, to: new_face }
+                    });
+                }
+            }
+            None => self.emit(GameEvent::Dropped { piece: pidx, owner: turn, face, at: to }),
+        }
+        for (piece, at, face) in stranded {
+            self.emit(GameEvent::Stranded { piece, owner: turn, face, at });
+        }
+
+        // Capturing Gyoku = immediate win
+        let outcome = if took_gyoku {
+            self.finish(GameResult::Win { winner: turn, reason: EndReason::GyokuCaptured })
+        } else {
+            self.end_turn()
+        };
+        // After the game end, as a captured Gyoku is only consistent once play is over
+        if cfg!(debug_assertions) {
+            if let Err(found) = self.state.validate() {
+                panic!("inconsistent state after {}: {:?}", self.move_log[log_len].kanji(), found);
+            }
+        }
+        Ok(outcome)
+    }
+
+    /// Validate a move for the side to move and return it with the capture
+    /// and promotion filled in.
+    fn check_move(&mut self, pidx: usize, to: (i32, i32), promote_to: Option<Face>) -> Result<Action, GameError> {
+        let turn = self.state.turn;
+        let piece = self.state.pieces.get(pidx).ok_or(GameError::NotYourPiece)?;
+        if piece.owner != turn || !piece.on_board() {
+            return Err(GameError::NotYourPiece);
+        }
+        let of = piece.face;
+        let cube = piece.cube;
+
+        let mv = get_legal_moves(&mut self.state, pidx).into_iter()
+            .find(|m| m.x == to.0 && m.y == to.1)
+            .ok_or(GameError::IllegalAction)?;
+
+        let promos: &[Face] = match mv.capture {
+            None => &[],
+            Some(ci) if self.state.pieces[ci].cube == Cube::Gyoku => &[],
+            Some(_) if cube == Cube::Gyoku => &[],
+            Some(_) => of.promotions(),
+        };
+        let promo = match (promote_to, promos.len()) {
+            (Some(pt), _) if promos.contains(&pt) => Some(pt),
+            (Some(_), _) => return Err(GameError::InvalidPromotion),
+            (None, 0) => None,
+            (None, 1) => Some(promos[0]),
+            (None, _) => return Err(GameError::InvalidPromotion),
+        };
+        Ok(Action::Move { piece_idx: pidx, mv, promote_to: promo })
+    }
+
+    fn check_drop(&mut self, pidx: usize, face: Face, pos: (i32, i32)) -> Result<Action, GameError> {
+        let turn = self.state.turn;
+        let piece = self.state.pieces.get(pidx).ok_or(GameError::NotYourPiece)?;
+        if piece.owner != turn || piece.on_board() || piece.cube == Cube::Gyoku {
+            return Err(GameError::NotYourPiece);
+        }
+        if face.cube() != piece.cube {
+            return Err(GameError::IllegalAction);
+        }
+        if !get_legal_drops(&mut self.state, turn, face).contains(&pos) {
+            return Err(GameError::IllegalAction);
+        }
+        Ok(Action::Drop { piece_idx: pidx, face, pos })
+    }
+
+    /// Number the entry and append it to the log.
+    fn log_move(&mut self, entry: MoveLogEntry) {
+        self.move_num += 1;
+        self.move_log.push(MoveLogEntry { num: self.move_num, ..entry });
+        self.history.record(self.move_log.len(), &self.state.pieces);
+    }
+
+    fn end_turn(&mut self) -> Outcome {
+        let state = &mut self.state;
+        state.in_check = is_in_check(state, state.turn);
+        if let Some(entry) = self.move_log.last_mut() {
+            entry.check = state.in_check;
+        }
+        if state.in_check {
+            let owner = state.turn;
+            self.emit(GameEvent::Check { owner });
+        }
+
+        let state = &mut self.state;
+        if !has_legal_action(state, state.turn) {
+            let winner = state.turn.opponent();
+            return self.finish(GameResult::Win { winner, reason: EndReason::NoLegalAction });
+        }
+        if self.dead_possible && is_dead_position(state) {
+            return self.finish(GameResult::Draw { reason: EndReason::DeadPosition });
+        }
+
+        debug_assert_eq!(state.board, Board::from_pieces(&state.pieces), "board map out of step");
+        debug_assert_eq!(state.zobrist, Zobrist::of(&state.pieces), "incremental hash out of step");
+        let hash = state.position_key();
+        if let Some(ply) = self.undo_stack.last_mut() {
+            ply.history_key = Some(hash);
+        }
+        let hist = self.position_history.entry(hash).or_default();
+        hist.push(PositionRecord { in_check: state.in_check });
+        let count = hist.len();
+        let all_check = hist.iter().all(|h| h.in_check);
+        let rules = state.rules;
+
+        if count >= rules.repetition {
+            // The side being checked every time wins; otherwise a draw
+            let result = if all_check && rules.perpetual_check_loses {
+                GameResult::Win { winner: state.turn, reason: EndReason::PerpetualCheck }
+            } else {
+                GameResult::Draw { reason: EndReason::Sennichite }
+            };
+            return self.finish(result);
+        }
+        if rules.ply_limit.is_some_and(|n| state.quiet_plies >= n) {
+            let reason = EndReason::PlyLimit;
+            let lead = if rules.ply_limit_material {
+                material(state, Owner::Black).cmp(&material(state, Owner::White))
+            } else {
+                Ordering::Equal
+            };
+            let result = match lead {
+                Ordering::Greater => GameResult::Win { winner: Owner::Black, reason },
+                Ordering::Less => GameResult::Win { winner: Owner::White, reason },
+                Ordering::Equal => GameResult::Draw { reason },
+            };
+            return self.finish(result);
+        }
+        // Warn on the last repeat before the limit, never on a first visit
+        if count > 1 && count + 1 == rules.repetition {
+            let perpetual = all_check && rules.perpetual_check_loses;
+            self.emit(GameEvent::RepetitionWarning { perpetual_check: perpetual });
+            return if perpetual { Outcome::PerpetualCheckWarning } else { Outcome::SennichiteWarning };
+        }
+        Outcome::Continue
+    }
+
+    pub(crate) fn finish(&mut self, result: GameResult) -> Outcome {
+        self.state.result = Some(result);
+        self.state.phase = Phase::Over;
+        self.emit(GameEvent::GameEnded(result));
+        Outcome::Ended(result)
+    }
+}
+
+/// Face value of everything `owner` holds, on the board and in hand, for
+/// adjudication at the ply limit.
+pub(crate) fn material(state: &GameState, owner: Owner) -> i32 {
+    state.pieces.iter()
+        .filter(|p| p.owner == owner && p.cube != Cube::Gyoku)
+        .map(|p| p.face.face_value())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{LegalMove, Piece};
+
+    /// A position `load_position` would refuse: White is in check with
+    /// Black to move.
+    fn gyoku_en_prise() -> Game {
+        let mut state = GameState::new();
+        let mut place = |owner, cube, face, (x, y)| {
+            let mut p = Piece::new(state.pieces.len(), owner, cube, face);
+            p.x = Some(x);
+            p.y = Some(y);
+            state.pieces.push(p);
+        };
+        place(Owner::Black, Cube::Gyoku, Face::Gyoku, (0, 0));
+        place(Owner::White, Cube::Gyoku, Face::Gyoku, (2, 2));
+        place(Owner::Black, Cube::Hi, Face::base(Cube::Hi), (2, 5));
+        state.reindex();
+        state.phase = Phase::Play;
+        state.turn = Owner::Black;
+        Game::from_state(state)
+    }
+
+    #[test]
+    fn capturing_gyoku_ends_the_game_and_validates() {
+        let mut game = gyoku_en_prise();
+        let action = game.legal_actions().into_iter()
+            .find(|a| matches!(a, Action::Move { mv, .. } if (mv.x, mv.y) == (2, 2)))
+            .expect("Hi can take the Gyoku");
+        let outcome = game.apply(&action).unwrap();
+        let result = GameResult::Win { winner: Owner::Black, reason: EndReason::GyokuCaptured };
+        assert_eq!(outcome, Outcome::Ended(result));
+        assert_eq!(game.phase(), Phase::Over);
+        assert!(game.state.validate().is_ok());
+    }
+
+    /// Gyoku steps around a square. Positions are keyed up to translation,
+    /// so every other ply repeats the one two plies before.
+    const GYOKU_STEPS: [((i32, i32), (i32, i32)); 4] =
+        [((0, 0), (0, 1)), ((2, 2), (2, 1)), ((0, 1), (0, 0)), ((2, 1), (2, 2))];
+
+    fn gyoku_square(repetition: usize) -> Game {
+        let mut state = GameState::new();
+        state.rules.repetition = repetition;
+        let mut game = Game::from_state(state);
+        game.load_position("bGyoku0,0;wGyoku2,2 Hi/Hi b basic").unwrap();
+        game
+    }
+
+    /// The legal move of the piece on `from` to `to`.
+    fn step(game: &mut Game, (from, to): ((i32, i32), (i32, i32))) -> Action {
+        let piece = game.state.piece_at(from.0, from.1);
+        game.legal_actions().into_iter()
+            .find(|a| matches!(a, Action::Move { piece_idx, mv, .. }
+                if Some(*piece_idx) == piece && (mv.x, mv.y) == to))
+            .expect("the piece can step")
+    }
+
+    /// Shuffle both Gyoku around a square until the game ends or `plies`
+    /// are played, returning each outcome.
+    fn shuffle_gyoku(repetition: usize, plies: usize) -> Vec<Outcome> {
+        let mut game = gyoku_square(repetition);
+        let mut outcomes = Vec::new();
+        for &steps in GYOKU_STEPS.iter().cycle().take(plies) {
+            let action = step(&mut game, steps);
+            let outcome = game.apply(&action).unwrap();
+            outcomes.push(outcome);
+            if outcome.is_over() {
+                break;
+            }
+        }
+        outcomes
+    }
+
+    #[test]
+    fn repetition_warns_only_on_the_last_repeat() {
+        let outcomes = shuffle_gyoku(3, 8);
+        let warned: Vec<usize> = (0..outcomes.len())
+            .filter(|&i| outcomes[i] == Outcome::SennichiteWarning)
+            .collect();
+        assert_eq!(warned, [2]);
+        let draw = GameResult::Draw { reason: EndReason::Sennichite };
+        assert_eq!(outcomes.last(), Some(&Outcome::Ended(draw)));
+
+        let outcomes = shuffle_gyoku(2, 8);
+        assert!(!outcomes.contains(&Outcome::SennichiteWarning));
+        assert_eq!(outcomes.len(), 3);
+        assert_eq!(outcomes.last(), Some(&Outcome::Ended(draw)));
+    }
+
+    #[test]
+    fn undo_and_redo_restore_the_position_and_its_counts() {
+        let mut game = gyoku_square(3);
+        for steps in &GYOKU_STEPS[..2] {
+            let action = step(&mut game, *steps);
+            assert_eq!(game.apply(&action).unwrap(), Outcome::Continue);
+        }
+        let before = game.state.clone();
+        let counts = game.position_history().clone();
+        let action = step(&mut game, GYOKU_STEPS[2]);
+        assert_eq!(game.apply(&action).unwrap(), Outcome::SennichiteWarning);
+        let played = game.move_log().last().unwrap().kanji();
+
+        game.undo().unwrap();
+        assert_eq!(game.state.pieces, before.pieces);
+        assert_eq!(game.state.zobrist, before.zobrist);
+        assert_eq!(game.state.position_key(), before.position_key());
+        assert_eq!(game.state.turn, before.turn);
+        assert_eq!(game.position_history(), &counts);
+        assert_eq!(game.move_log().len(), 2);
+
+        // Redo plays the same ply, which repeats the position again
+        assert_eq!(game.redo().unwrap().unwrap(), Outcome::SennichiteWarning);
+        assert_eq!(game.move_log().last().unwrap().kanji(), played);
+        assert!(!game.can_redo());
+
+        // Back to the start: the counts went with the plies, so the warning
+        // comes at the same ply when they are played again
+        while game.undo().is_some() {}
+        assert!(game.position_history().is_empty());
+        assert!(game.can_redo());
+        let mut outcomes = Vec::new();
+        for steps in &GYOKU_STEPS[..3] {
+            let action = step(&mut game, *steps);
+            outcomes.push(game.apply(&action).unwrap());
+            assert!(!game.can_redo(), "a new ply clears the redo stack");
+        }
+        assert_eq!(outcomes, [Outcome::Continue, Outcome::Continue, Outcome::SennichiteWarning]);
+    }
+
+    #[test]
+    fn resignation_ends_the_game_for_the_opponent() {
+        let mut game = gyoku_square(4);
+        let result = GameResult::Win { winner: Owner::White, reason: EndReason::Resignation };
+        assert_eq!(game.declare(Declaration::Resign).unwrap(), Outcome::Ended(result));
+        assert_eq!(game.result(), Some(result));
+        assert!(game.is_over());
+        assert_eq!(game.move_log().last().unwrap().kind, LogKind::Declare(Declaration::Resign));
+    }
+
+    #[test]
+    fn declined_draw_offer_leaves_the_game_in_play() {
+        let mut game = gyoku_square(4);
+        assert_eq!(game.declare(Declaration::OfferDraw).unwrap(), Outcome::DrawOffered);
+        // Nothing but an answer until then
+        assert!(game.legal_actions().is_empty());
+        assert!(matches!(game.declare(Declaration::Resign), Err(GameError::DrawOfferPending)));
+        assert_eq!(game.declare(Declaration::DeclineDraw).unwrap(), Outcome::Continue);
+        assert_eq!(game.phase(), Phase::Play);
+        assert_eq!(game.turn(), Owner::Black);
+        assert!(!game.legal_actions().is_empty());
+        let logged: Vec<_> = game.move_log().iter().map(|e| (e.owner, e.kind)).collect();
+        assert_eq!(logged, [
+            (Owner::Black, LogKind::Declare(Declaration::OfferDraw)),
+            (Owner::White, LogKind::Declare(Declaration::DeclineDraw)),
+        ]);
+    }
+
+    #[test]
+    fn accepted_draw_offer_ends_in_agreement() {
+        let mut game = gyoku_square(4);
+        assert!(matches!(game.declare(Declaration::AcceptDraw), Err(GameError::NoDrawOffer)));
+        game.declare(Declaration::OfferDraw).unwrap();
+        let draw = GameResult::Draw { reason: EndReason::Agreement };
+        assert_eq!(game.declare(Declaration::AcceptDraw).unwrap(), Outcome::Ended(draw));
+        assert_eq!(game.result(), Some(draw));
+        assert_eq!(game.move_log().len(), 2);
+    }
+
+    #[test]
+    fn events_come_in_the_order_they_happen() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let mut game = Game::new(true);
+        game.load_position("bGyoku0,0;bKi1,0;bKaku2,-1;wGyoku2,2;wHi1,2 -/- b ki").unwrap();
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let seen = Rc::clone(&events);
+        game.subscribe(move |e: &GameEvent, _: &GameState| seen.borrow_mut().push(e.clone()));
+
+        let on = |game: &Game, x, y| game.state.piece_at(x, y).unwrap();
+        let (ki, kaku, hi) = (on(&game, 1, 0), on(&game, 2, -1), on(&game, 1, 2));
+        let face = |name| Face::from_name(name).unwrap();
+        // The Ki jumps onto the Hi, promotes, and leaves the Kaku unsupported
+        let mv = LegalMove { x: 1, y: 2, capture: Some(hi) };
+        let action = Action::Move { piece_idx: ki, mv, promote_to: Some(face("Ryuu")) };
+        assert_eq!(game.apply(&action).unwrap(), Outcome::Continue);
+        game.undo().unwrap();
+        game.declare(Declaration::Resign).unwrap();
+
+        let result = GameResult::Win { winner: Owner::White, reason: EndReason::Resignation };
+        assert_eq!(*events.borrow(), [
+            GameEvent::Moved { piece: ki, owner: Owner::Black, face: face("Ki"), from: (1, 0), to: (1, 2) },
+            GameEvent::Captured { piece: hi, by: Owner::Black, face: face("Hi"), at: (1, 2) },
+            GameEvent::Promoted { piece: ki, from: face("Ki"), to: face("Ryuu") },
+            GameEvent::Stranded { piece: kaku, owner: Owner::Black, face: face("Kaku"), at: (2, -1) },
+            GameEvent::Undone,
+            GameEvent::Declared { owner: Owner::Black, declaration: Declaration::Resign },
+            GameEvent::GameEnded(result),
+        ]);
+    }
+}
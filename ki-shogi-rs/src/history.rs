@@ -0,0 +1,241 @@
+// Ki Shogi - Move log positions (start, checkpoints, replay)
+
+use std::fmt;
+use crate::types::Face;
+use crate::state::{Action, GameState, LegalMove, LogKind, MoveLogEntry, Piece};
+use crate::ruleset::RuleSet;
+use crate::rules::make;
+
+/// Plies between checkpoints unless set otherwise.
+pub const CHECKPOINT_EVERY: usize = 16;
+
+// ---------------------------------------------------------------------------
+// History
+// ---------------------------------------------------------------------------
+//
+// The move log holds actions only. The position after any entry is the
+// pieces the log started from with the entries up to it replayed, starting
+// from the last checkpoint before it when there is one. Checkpoints are
+// the pieces after every `checkpoint_every` entries and follow the log
+// through undo. A replayed position shows the same pieces on the same
+// squares, though pieces of one cube in a hand may swap indices.
+
+/// A log entry that cannot be played on the position before it, so the
+/// log has come apart from the game that wrote it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayError {
+    /// Log index of a placement, move or drop missing its face or target.
+    Incomplete(usize),
+    /// Log index of a placement whose Gyoku is not in the game.
+    NoGyoku(usize),
+    /// Log index and the square its moving piece should stand on.
+    NoPieceOn(usize, (i32, i32)),
+    /// Log index and the face it drops, not in its owner's hand.
+    NotInHand(usize, Face),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Incomplete(i) => write!(f, "log entry {} has no face or target", i + 1),
+            ReplayError::NoGyoku(i) => write!(f, "log entry {} places a missing Gyoku", i + 1),
+            ReplayError::NoPieceOn(i, (x, y)) => write!(f, "log entry {} moves from empty ({},{})", i + 1, x, y),
+            ReplayError::NotInHand(i, face) => write!(f, "log entry {} drops {:?}, not in hand", i + 1, face),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+#[derive(Debug, Clone)]
+pub struct History {
+    /// The pieces before the first entry of the log.
+    start: Vec<Piece>,
+    /// `checkpoints[k]` holds the pieces after entry `(k + 1) * n - 1`.
+    checkpoints: Vec<Vec<Piece>>,
+    /// Plies between checkpoints; None to always replay from the start.
+    checkpoint_every: Option<usize>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self { start: Vec::new(), checkpoints: Vec::new(), checkpoint_every: Some(CHECKPOINT_EVERY) }
+    }
+}
+
+impl History {
+    /// Start over from `pieces`, with an empty log.
+    pub fn restart(&mut self, pieces: &[Piece]) {
+        self.start = pieces.to_vec();
+        self.checkpoints.clear();
+    }
+
+    pub fn checkpoint_every(&self) -> Option<usize> {
+        self.checkpoint_every
+    }
+
+    /// Checkpoint every `every` plies of `log` instead, or never. Entries
+    /// past one that fails to replay get no checkpoints.
+    pub fn set_checkpoint_every(
+        &mut self,
+        every: Option<usize>,
+        log: &[MoveLogEntry],
+        rules: RuleSet,
+    ) -> Result<(), ReplayError> {
+        self.checkpoint_every = every.filter(|&n| n > 0);
+        self.checkpoints.clear();
+        let Some(n) = self.checkpoint_every else { return Ok(()) };
+        let mut state = GameState::from_pieces(self.start.clone(), rules);
+        for (i, entry) in log.iter().enumerate() {
+            replay(&mut state, i, entry)?;
+            if (i + 1).is_multiple_of(n) {
+                self.checkpoints.push(state.pieces.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// Note that the log has grown to `log_len` entries, leaving `pieces`.
+    pub fn record(&mut self, log_len: usize, pieces: &[Piece]) {
+        self.truncate(log_len - 1);
+        if self.checkpoint_every.is_some_and(|n| log_len.is_multiple_of(n)) {
+            self.checkpoints.push(pieces.to_vec());
+        }
+    }
+
+    /// Forget checkpoints past the first `log_len` entries.
+    pub fn truncate(&mut self, log_len: usize) {
+        let kept = self.checkpoint_every.map_or(0, |n| log_len / n);
+        self.checkpoints.truncate(kept);
+    }
+
+    /// The pieces after entry `index` of `log`; None past its end.
+    pub fn pieces_after(
+        &self,
+        log: &[MoveLogEntry],
+        rules: RuleSet,
+        index: usize,
+    ) -> Option<Result<Vec<Piece>, ReplayError>> {
+        if index >= log.len() { return None; }
+        let k = self.checkpoint_every.map_or(0, |n| (index + 1) / n).min(self.checkpoints.len());
+        let (pieces, first) = match k {
+            0 => (&self.start, 0),
+            k => (&self.checkpoints[k - 1], k * self.checkpoint_every.unwrap_or(0)),
+        };
+        let mut state = GameState::from_pieces(pieces.to_vec(), rules);
+        for (i, entry) in log.iter().enumerate().take(index + 1).skip(first) {
+            if let Err(e) = replay(&mut state, i, entry) {
+                return Some(Err(e));
+            }
+        }
+        Some(Ok(state.pieces))
+    }
+}
+
+/// Play log entry `index` again, finding its pieces by square.
+/// Declarations leave the pieces as they are.
+fn replay(state: &mut GameState, index: usize, entry: &MoveLogEntry) -> Result<(), ReplayError> {
+    if let LogKind::Declare(_) = entry.kind { return Ok(()) }
+    let (Some(face), Some(to)) = (entry.face_after, entry.to) else {
+        return Err(ReplayError::Incomplete(index));
+    };
+    let on = |state: &GameState, (x, y): (i32, i32)| state.piece_at(x, y);
+    let action = match entry.kind {
+        LogKind::Declare(_) => return Ok(()),
+        LogKind::Place => {
+            let gi = state.gyoku(entry.owner).ok_or(ReplayError::NoGyoku(index))?;
+            state.unindex_piece(gi);
+            state.pieces[gi].x = Some(to.0);
+            state.pieces[gi].y = Some(to.1);
+            state.index_piece(gi);
+            return Ok(());
+        }
+        LogKind::Move | LogKind::Capture => {
+            let from = entry.from.ok_or(ReplayError::Incomplete(index))?;
+            let piece_idx = on(state, from).ok_or(ReplayError::NoPieceOn(index, from))?;
+            let capture = if entry.kind == LogKind::Capture { on(state, to) } else { None };
+            let mv = LegalMove { x: to.0, y: to.1, capture };
+            Action::Move { piece_idx, mv, promote_to: entry.promote_to }
+        }
+        LogKind::Drop => {
+            let in_hand = state.hand_pieces_for(entry.owner).into_iter()
+                .find(|&i| Some(state.pieces[i].face) == entry.face);
+            let piece_idx = in_hand.ok_or(ReplayError::NotInHand(index, entry.face.unwrap_or(face)))?;
+            Action::Drop { piece_idx, face, pos: to }
+        }
+    };
+    state.turn = entry.owner;
+    make(state, &action);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Game;
+    use crate::rng::Rng;
+
+    /// The pieces as a sorted list of what stands where, so hand pieces of
+    /// one cube that swapped indices still compare equal.
+    fn layout(pieces: &[Piece]) -> Vec<String> {
+        let mut out: Vec<String> = pieces.iter()
+            .map(|p| format!("{:?}", (p.owner, p.cube, p.face, p.x, p.y)))
+            .collect();
+        out.sort();
+        out
+    }
+
+    /// A game of random legal plies from the setup, with the pieces after
+    /// every log entry, long enough to strand a piece and to promote.
+    fn random_game() -> (Game, Vec<Vec<Piece>>) {
+        for seed in 0.. {
+            let mut rng = Rng::new(seed);
+            let mut game = Game::new(true);
+            let mut after = Vec::new();
+            game.place_gyoku((0, 0)).unwrap();
+            after.push(game.state.pieces.clone());
+            let squares = game.gyoku_positions();
+            game.place_gyoku(squares[rng.below(squares.len())]).unwrap();
+            after.push(game.state.pieces.clone());
+            while !game.is_over() && after.len() < 120 {
+                let actions = game.legal_actions();
+                game.apply(&actions[rng.below(actions.len())]).unwrap();
+                after.push(game.state.pieces.clone());
+            }
+            let log = game.move_log();
+            if log.iter().any(|e| !e.stranded.is_empty()) && log.iter().any(|e| e.promote_to.is_some()) {
+                return (game, after);
+            }
+        }
+        unreachable!()
+    }
+
+    #[test]
+    fn replay_reproduces_every_position() {
+        let (mut game, after) = random_game();
+        assert_eq!(after.len(), game.move_log().len());
+        for every in [None, Some(1), Some(5), Some(CHECKPOINT_EVERY)] {
+            game.set_checkpoint_every(every).unwrap();
+            for (i, pieces) in after.iter().enumerate() {
+                let replayed = game.pieces_after(i).unwrap().unwrap();
+                assert_eq!(layout(&replayed), layout(pieces), "entry {} every {:?}", i, every);
+            }
+            assert!(game.pieces_after(after.len()).is_none());
+        }
+    }
+
+    #[test]
+    fn a_missing_piece_is_reported() {
+        let (game, _) = random_game();
+        let mut log = game.move_log().to_vec();
+        let i = log.iter().position(|e| e.kind == LogKind::Move).unwrap();
+        log[i].from = Some((99, 99));
+        // Game::new starts from the pieces of a fresh setup
+        let mut setup = GameState::new();
+        setup.init_game(true);
+        let mut history = History::default();
+        history.restart(&setup.pieces);
+        let found = history.pieces_after(&log, game.state.rules, log.len() - 1);
+        assert!(matches!(found, Some(Err(ReplayError::NoPieceOn(j, (99, 99)))) if j == i));
+    }
+}
@@ -7,7 +7,7 @@ use std::time::Duration;
 pub enum Key {
     Up, Down, Left, Right,
     Enter, Esc, Tab, Space,
-    Q, D, M, L, R,
+    Q, D, M, L, R, U, Y, S, O, X, Equals,
     One, Two, Three, Four, Five,
 }
 
@@ -35,6 +35,12 @@ pub fn poll_key(timeout_ms: u64) -> Option<Key> {
                 KeyCode::Char('m') | KeyCode::Char('M') => Some(Key::M),
                 KeyCode::Char('l') | KeyCode::Char('L') => Some(Key::L),
                 KeyCode::Char('r') | KeyCode::Char('R') => Some(Key::R),
+                KeyCode::Char('u') | KeyCode::Char('U') => Some(Key::U),
+                KeyCode::Char('y') | KeyCode::Char('Y') => Some(Key::Y),
+                KeyCode::Char('s') | KeyCode::Char('S') => Some(Key::S),
+                KeyCode::Char('o') | KeyCode::Char('O') => Some(Key::O),
+                KeyCode::Char('x') | KeyCode::Char('X') => Some(Key::X),
+                KeyCode::Char('=') => Some(Key::Equals),
                 KeyCode::Char('1') => Some(Key::One),
                 KeyCode::Char('2') => Some(Key::Two),
                 KeyCode::Char('3') => Some(Key::Three),
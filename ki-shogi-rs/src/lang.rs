@@ -23,6 +23,11 @@ pub fn t(key: &str) -> &'static str {
     }
 }
 
+/// `t` with the `{n}` in the text replaced by `n`.
+pub fn tn(key: &str, n: usize) -> String {
+    t(key).replace("{n}", &n.to_string())
+}
+
 /// ANSI codes struct for tutorial pages
 pub struct Ansi {
     pub bold: &'static str,
@@ -291,7 +296,7 @@ fn t_zh(key: &str) -> &'static str {
         "black_short" => "黒",
         "white_short" => "白",
         "wins" => "胜利!",
-        "quit_menu" => "Q 退出  M 主菜单  R 重新开始",
+        "quit_menu" => "Q 退出  M 主菜单  R 重新开始  U 悔棋  S 保存",
         "black_hand" => "黒 持驹",
         "white_hand" => "白 持驹",
         "hand_empty" => "(空)",
@@ -301,8 +306,8 @@ fn t_zh(key: &str) -> &'static str {
         "your_turn" => "的回合",
         "controls_setup_1" => "方向键:移动 Enter:确认",
         "controls_setup_2" => "L:语言/Lang M:菜单 R:重开 Q:退出",
-        "controls_board_1" => "方向键:移动 Enter:选中 D:放置持驹 Tab:棋谱",
-        "controls_board_2" => "L:语言/Lang M:菜单 R:重开 Q:退出",
+        "controls_board_1" => "方向键:移动 Enter:选中 D:放置持驹 Tab:棋谱 U/Y:悔棋/重做",
+        "controls_board_2" => "L:语言/Lang S/O:保存/读取 M:菜单 R:重开 Q:退出",
         "select_target" => "选择目标",
         "controls_move" => "方向键:移动 Enter:确认 ESC:取消 L:语言/Lang",
         "check" => "将军!",
@@ -326,16 +331,46 @@ fn t_zh(key: &str) -> &'static str {
         "cant_drop" => "无法放置此棋子",
         "no_drop_pos" => "该面无合法放置位置",
         "invalid_drop" => "无效放置位置",
+        "why_unreachable" => "该面走不到这里: ",
+        "why_blocked" => "被挡住: ",
+        "why_unsupported" => "此处无支撑，棋子会被俘获: ",
+        "why_gyoku_distance" => "玉必须与对方玉保持距离 ",
+        "why_leaves_check" => "己方玉将被将军: ",
+        "why_drop_check" => "放置不能将军: ",
+        "why_drop_adjacent" => "不能放在对方玉旁边: ",
+        "why_drop_range" => "只能放在己方玉周围范围内: ",
         "place_black_gyoku" => "黒方放置玉",
         "log_title" => "棋谱",
         "log_browse_nav" => "浏览",
         "log_browse_back" => "返回",
         "draw" => "和棋!",
-        "sennichite" => "千日手 (局面重复四次)",
-        "sennichite_warning" => "⚠ 局面已重复三次!",
+        "sennichite" => "千日手 (局面重复{n}次)",
+        "sennichite_warning" => "⚠ 局面已重复{n}次!",
         "perpetual_check_lose" => "反复将军判负",
         "perpetual_check_warning" => "⚠ 连续将军中，再重复将判负!",
+        "reason_gyoku_captured" => "玉被吃",
+        "reason_no_legal_action" => "无合法行动",
+        "reason_resignation" => "认输",
+        "reason_dead_position" => "死局 (双方都无法取胜)",
+        "reason_ply_limit" => "达到步数上限",
+        "ply_limit" => "无吃子/打入步数: ",
+        "reason_timeout" => "超时",
+        "reason_agreement" => "协议和棋",
         "paused" => "已暂停",
+        "undo_none" => "没有可悔的棋",
+        "record_saved" => "棋谱已保存: ",
+        "record_save_failed" => "保存失败: ",
+        "record_loaded" => "棋谱已读取: ",
+        "record_load_failed" => "读取失败: ",
+        "no_record" => "没有棋谱文件 (按 S 保存或以 --load 启动)",
+        "redo_none" => "没有可重做的棋",
+        "controls_declare" => "X:认输 =:提和",
+        "confirm_resign" => "确定认输?",
+        "controls_confirm_resign" => "Enter:认输 ESC:取消",
+        "draw_offered" => "提出和棋, 是否接受?",
+        "controls_draw_offer" => "Enter:接受 ESC:拒绝",
+        "draw_declined" => "和棋被拒绝",
+        "stranded" => "失去支撑, 归对方所有: ",
         "space_pause" => "Space:暂停/继续",
         "tut_title" => "规则教程",
         "tut_prev" => "←上一页",
@@ -376,7 +411,7 @@ fn t_en(key: &str) -> &'static str {
         "black_short" => "黒",
         "white_short" => "白",
         "wins" => "Wins!",
-        "quit_menu" => "Q Quit  M Menu  R Restart",
+        "quit_menu" => "Q Quit  M Menu  R Restart  U Undo  S Save",
         "black_hand" => "黒 Hand",
         "white_hand" => "白 Hand",
         "hand_empty" => "(none)",
@@ -386,8 +421,8 @@ fn t_en(key: &str) -> &'static str {
         "your_turn" => "'s turn",
         "controls_setup_1" => "Arrows:Move Enter:Confirm",
         "controls_setup_2" => "L:语言/Lang M:Menu R:Restart Q:Quit",
-        "controls_board_1" => "Arrows:Move Enter:Select D:Drop Tab:Log",
-        "controls_board_2" => "L:语言/Lang M:Menu R:Restart Q:Quit",
+        "controls_board_1" => "Arrows:Move Enter:Select D:Drop Tab:Log U/Y:Undo/Redo",
+        "controls_board_2" => "L:语言/Lang S/O:Save/Load M:Menu R:Restart Q:Quit",
         "select_target" => "Select target",
         "controls_move" => "Arrows:Move Enter:Confirm ESC:Cancel L:语言/Lang",
         "check" => "Check!",
@@ -411,16 +446,46 @@ fn t_en(key: &str) -> &'static str {
         "cant_drop" => "Cannot drop this piece",
         "no_drop_pos" => "No legal drop positions",
         "invalid_drop" => "Invalid drop position",
+        "why_unreachable" => "This face cannot reach here: ",
+        "why_blocked" => "Blocked by ",
+        "why_unsupported" => "Nothing supports it there, it would be stranded: ",
+        "why_gyoku_distance" => "Gyoku must stay at distance ",
+        "why_leaves_check" => "Leaves your Gyoku in check by ",
+        "why_drop_check" => "A drop may not give check: ",
+        "why_drop_adjacent" => "Cannot drop next to the enemy Gyoku: ",
+        "why_drop_range" => "Drops must be within range of your Gyoku: ",
         "place_black_gyoku" => "Black places 玉",
         "log_title" => "Log",
         "log_browse_nav" => "Browse",
         "log_browse_back" => "Back",
         "draw" => "Draw!",
-        "sennichite" => "Sennichite (4-fold repetition)",
-        "sennichite_warning" => "⚠ Position repeated 3 times!",
+        "sennichite" => "Sennichite ({n}-fold repetition)",
+        "sennichite_warning" => "⚠ Position repeated {n} times!",
         "perpetual_check_lose" => "Perpetual check — loses",
         "perpetual_check_warning" => "⚠ Perpetual check — one more repeats and you lose!",
+        "reason_gyoku_captured" => "玉 captured",
+        "reason_no_legal_action" => "No legal action",
+        "reason_resignation" => "Resignation",
+        "reason_dead_position" => "Dead position (neither side can win)",
+        "reason_ply_limit" => "Ply limit reached",
+        "ply_limit" => "Plies without capture or drop: ",
+        "reason_timeout" => "Time out",
+        "reason_agreement" => "Draw agreed",
         "paused" => "Paused",
+        "undo_none" => "Nothing to undo",
+        "record_saved" => "Saved: ",
+        "record_save_failed" => "Save failed: ",
+        "record_loaded" => "Loaded: ",
+        "record_load_failed" => "Load failed: ",
+        "no_record" => "No record file (save with S or start with --load)",
+        "redo_none" => "Nothing to redo",
+        "controls_declare" => "X:Resign =:Offer draw",
+        "confirm_resign" => "Resign?",
+        "controls_confirm_resign" => "Enter:Resign ESC:Cancel",
+        "draw_offered" => "offers a draw. Accept?",
+        "controls_draw_offer" => "Enter:Accept ESC:Decline",
+        "draw_declined" => "Draw offer declined",
+        "stranded" => "Stranded, now in the opponent's hand: ",
         "space_pause" => "Space:Pause/Resume",
         "tut_title" => "Tutorial",
         "tut_prev" => "←Prev",
@@ -1,7 +1,21 @@
 pub mod types;
+pub mod pieceset;
 pub mod state;
 pub mod rules;
+pub mod ruleset;
+pub mod board;
+pub mod zobrist;
+pub mod rng;
+pub mod event;
+pub mod history;
+pub mod game;
+pub mod session;
+pub mod notation;
+pub mod record;
+pub mod perft;
+pub mod dead;
 pub mod ai;
+pub mod ui;
 pub mod render;
 pub mod input;
 pub mod lang;
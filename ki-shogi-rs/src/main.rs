@@ -6,794 +6,1018 @@ use std::sync::mpsc;
 use crossterm::terminal;
 
 use ki_shogi::types::{Owner, Cube, Face};
-use ki_shogi::state::{GameState, Mode, AiSide, MoveLogEntry, PendingLogInfo, PositionRecord};
-use ki_shogi::rules::{
-    get_legal_moves, get_legal_drops, is_in_check, handle_stranding,
-    has_legal_action, get_white_gyoku_positions,
-};
+use ki_shogi::state::{Action, Declaration, GameState, LegalMove, Phase};
+use ki_shogi::rules::{explain_drop, explain_move, get_legal_moves, get_legal_drops, is_in_check, IllegalReason};
+use ki_shogi::game::{Game, Outcome};
+use ki_shogi::session::{AiSide, Session};
+use ki_shogi::record::{load_record, record_file_name, save_record, today, GameRecord, RecordError};
+use ki_shogi::ui::{Mode, UiState};
 use ki_shogi::render::{render, cleanup};
 use ki_shogi::input::{poll_key, Key};
 use ki_shogi::lang::{t, toggle_lang};
-use ki_shogi::ai::{ai_choose, position_hash, Action};
-
-fn fk(face: Face) -> &'static str {
-    face.kanji()
+use ki_shogi::ai::{ai_accepts_draw, ai_choose, ai_choose_gyoku, ai_resigns};
+use ki_shogi::notation::{Notation, Ply};
+use ki_shogi::perft::{perft_divide, PerftCounts};
+use ki_shogi::ruleset::RuleSet;
+use ki_shogi::rng::Rng;
+use ki_shogi::zobrist::Zobrist;
+use ki_shogi::pieceset::{install, load_piece_set};
+
+/// Bring the UI in line with the game after an action has been applied.
+fn after_turn(ui: &mut UiState, outcome: Outcome) {
+    if cfg!(debug_assertions) {
+        if let Err(found) = ui.validate() {
+            panic!("inconsistent state: {:?}", found);
+        }
+    }
+    ui.selected = None;
+    ui.legal_moves.clear();
+    ui.hand_pieces.clear();
+    ui.drop_targets.clear();
+    ui.promote_choices.clear();
+    ui.pending_move = None;
+    ui.message.clear();
+    ui.follow_events();
+
+    let state = &ui.game.state;
+    match outcome {
+        Outcome::Ended(result) => {
+            ui.mode = if result.is_draw() { Mode::Draw } else { Mode::GameOver };
+        }
+        Outcome::DrawOffered => ui.mode = Mode::DrawOffer,
+        Outcome::Continue | Outcome::SennichiteWarning | Outcome::PerpetualCheckWarning => {
+            ui.mode = Mode::Board;
+            if !ui.session.ai_side.is_ai(state.turn) {
+                if let Some(gi) = state.gyoku(state.turn) {
+                    if let Some(pos) = state.pieces[gi].pos() {
+                        ui.cursor = pos;
+                    }
+                }
+            }
+        }
+    }
+    render(ui);
 }
 
-fn log_move(state: &mut GameState, owner: Owner, text: String, face: Option<Face>, from: Option<(i32, i32)>, to: Option<(i32, i32)>) {
-    state.move_num += 1;
-    let snapshot = state.take_snapshot();
-    state.move_log.push(MoveLogEntry {
-        num: state.move_num, owner, text, snapshot, face, from, to,
-    });
+/// Black's Gyoku is down: move the UI on to White's placement.
+fn after_black_gyoku(ui: &mut UiState) {
+    let positions = ui.game.gyoku_positions();
+    ui.mode = Mode::SetupWhiteGyoku;
+    if !positions.is_empty() {
+        let best = positions.iter()
+            .max_by(|a, b| a.1.cmp(&b.1).then(b.0.abs().cmp(&a.0.abs())))
+            .copied().unwrap();
+        ui.cursor = best;
+        ui.legal_moves = positions.iter()
+            .map(|&(x, y)| LegalMove { x, y, capture: None })
+            .collect();
+    }
+    ui.message.clear();
 }
 
-fn end_turn(state: &mut GameState) {
-    handle_stranding(state);
-    let snap = state.take_snapshot();
-    if let Some(last) = state.move_log.last_mut() {
-        last.snapshot = snap;
+/// White's Gyoku is down: play begins.
+fn after_white_gyoku(ui: &mut UiState) {
+    let state = &ui.game.state;
+    ui.legal_moves.clear();
+    ui.mode = Mode::Board;
+    if let Some(bgi) = state.gyoku(Owner::Black) {
+        if let Some(pos) = state.pieces[bgi].pos() {
+            ui.cursor = pos;
+        }
     }
-    state.switch_turn();
-    state.message.clear();
+    ui.message = t("game_start").to_string();
+}
 
-    if !has_legal_action(state, state.turn) {
-        state.winner = Some(state.turn.opponent());
-        state.mode = Mode::GameOver;
-        render(state);
-        return;
+fn ai_setup_black_gyoku(ui: &mut UiState) {
+    if let Some(pos) = ai_choose_gyoku(&ui.game.state, &mut ui.session.rng) {
+        if ui.game.place_gyoku(pos).is_ok() {
+            after_black_gyoku(ui);
+            render(ui);
+        }
     }
+}
 
-    state.in_check = is_in_check(state, state.turn);
+fn ai_setup_white_gyoku(ui: &mut UiState) {
+    if let Some(pos) = ai_choose_gyoku(&ui.game.state, &mut ui.session.rng) {
+        if ui.game.place_gyoku(pos).is_ok() {
+            after_white_gyoku(ui);
+            render(ui);
+        }
+    }
+}
 
-    let hash = position_hash(state);
-    let hist = state.position_history.entry(hash).or_default();
-    hist.push(PositionRecord { in_check: state.in_check });
+fn ai_do_move_with_action(ui: &mut UiState, action: Action) {
+    match ui.game.apply(&action) {
+        Ok(outcome) => after_turn(ui, outcome),
+        Err(_) => render(ui),
+    }
+}
 
-    if hist.len() == 3 {
-        let all_check = hist.iter().all(|h| h.in_check);
-        state.message = if all_check {
-            t("perpetual_check_warning").to_string()
-        } else {
-            t("sennichite_warning").to_string()
-        };
+/// The side to move offers a draw. A human opponent is asked; an AI one
+/// answers from the AI thread like any other decision.
+fn offer_draw(ui: &mut UiState) {
+    if ui.game.declare(Declaration::OfferDraw).is_ok() {
+        after_turn(ui, Outcome::DrawOffered);
     }
-    if hist.len() >= 4 {
-        let all_check = hist.iter().all(|h| h.in_check);
-        if all_check {
-            state.winner = Some(state.turn);
-            state.mode = Mode::GameOver;
-            state.message = t("perpetual_check_lose").to_string();
-            render(state);
-            return;
+}
+
+fn answer_draw(ui: &mut UiState, accept: bool) {
+    let d = if accept { Declaration::AcceptDraw } else { Declaration::DeclineDraw };
+    if let Ok(outcome) = ui.game.declare(d) {
+        after_turn(ui, outcome);
+        if !accept {
+            ui.message = t("draw_declined").to_string();
+            render(ui);
         }
-        state.mode = Mode::Draw;
-        state.message = t("sennichite").to_string();
-        render(state);
-        return;
     }
+}
 
-    state.mode = Mode::Board;
-    state.selected = None;
-    state.legal_moves.clear();
-    if !state.ai_side.is_ai(state.turn) {
-        if let Some(gi) = state.gyoku(state.turn) {
-            if state.pieces[gi].on_board() {
-                state.cursor = (state.pieces[gi].x.unwrap(), state.pieces[gi].y.unwrap());
+fn on_confirm_resign(ui: &mut UiState, key: Key) {
+    match key {
+        Key::Enter => {
+            if let Ok(outcome) = ui.game.declare(Declaration::Resign) {
+                after_turn(ui, outcome);
+                return;
             }
         }
+        Key::Esc | Key::X => {
+            ui.mode = Mode::Board;
+            ui.message.clear();
+        }
+        _ => {}
     }
-    render(state);
-}
-
-fn execute_move(state: &mut GameState, pidx: usize, mv_x: i32, mv_y: i32, cap_idx: Option<usize>, promote_to: Option<Face>) {
-    let ox = state.pieces[pidx].x.unwrap();
-    let oy = state.pieces[pidx].y.unwrap();
-    let of = state.pieces[pidx].face;
-    let m_from = (ox, oy);
-    let m_to = (mv_x, mv_y);
-
-    if let Some(ci) = cap_idx {
-        // Capturing Gyoku = immediate win
-        if state.pieces[ci].cube == Cube::Gyoku {
-            state.pieces[pidx].x = Some(mv_x);
-            state.pieces[pidx].y = Some(mv_y);
-            state.pieces[ci].x = None;
-            state.pieces[ci].y = None;
-            let text = format!("{}({},{})×{}({},{})", fk(of), ox, oy, fk(Face::Gyoku), mv_x, mv_y);
-            log_move(state, state.pieces[pidx].owner, text, Some(of), Some(m_from), Some(m_to));
-            state.winner = Some(state.pieces[pidx].owner);
-            state.mode = Mode::GameOver;
-            state.selected = None;
-            state.legal_moves.clear();
-            render(state);
-            return;
+    render(ui);
+}
+
+fn on_draw_offer(ui: &mut UiState, key: Key) {
+    let ai_answers = ui.session.ai_side.is_ai(ui.game.turn().opponent());
+    match key {
+        Key::Enter if !ai_answers => answer_draw(ui, true),
+        Key::Esc if !ai_answers => answer_draw(ui, false),
+        Key::Tab => {
+            enter_log_browse(ui);
+            render(ui);
         }
+        _ => {}
+    }
+}
 
-        let cap_face = state.pieces[ci].face;
-        let p_owner = state.pieces[pidx].owner;
-        state.pieces[ci].x = None;
-        state.pieces[ci].y = None;
-        state.pieces[ci].owner = p_owner;
-        state.pieces[pidx].x = Some(mv_x);
-        state.pieces[pidx].y = Some(mv_y);
-
-        let cap_base = format!("{}({},{})×{}({},{})", fk(of), ox, oy, fk(cap_face), mv_x, mv_y);
-
-        if state.pieces[pidx].cube != Cube::Gyoku {
-            let promos = state.pieces[pidx].face.promotions();
-            if !promos.is_empty() {
-                if promos.len() == 1 {
-                    state.pieces[pidx].face = promos[0];
-                    let text = format!("{}→{}", cap_base, fk(promos[0]));
-                    log_move(state, p_owner, text, Some(of), Some(m_from), Some(m_to));
-                    state.selected = None;
-                    state.legal_moves.clear();
-                    end_turn(state);
-                    return;
-                }
-                if let Some(pt) = promote_to {
-                    state.pieces[pidx].face = pt;
-                    let text = format!("{}→{}", cap_base, fk(pt));
-                    log_move(state, p_owner, text, Some(of), Some(m_from), Some(m_to));
-                    state.selected = None;
-                    state.legal_moves.clear();
-                    end_turn(state);
-                    return;
-                }
-                if state.ai_side.is_ai(p_owner) {
-                    let best = promos.iter().max_by_key(|f| f.face_value()).copied().unwrap();
-                    state.pieces[pidx].face = best;
-                    let text = format!("{}→{}", cap_base, fk(best));
-                    log_move(state, p_owner, text, Some(of), Some(m_from), Some(m_to));
-                    state.selected = None;
-                    state.legal_moves.clear();
-                    end_turn(state);
-                    return;
-                }
-                // Human: show promote UI
-                state.promote_choices = promos.to_vec();
-                state.promote_index = 0;
-                state.pending_log_info = Some(PendingLogInfo {
-                    owner: p_owner, cap_base, face: of, from: m_from, to: m_to,
-                });
-                state.mode = Mode::Promote;
-                render(state);
-                return;
-            }
+/// Take back the last ply. Against the AI, its reply is taken back too so
+/// that the human is to move again.
+fn undo_turn(ui: &mut UiState) {
+    if ui.game.undo().is_none() {
+        ui.message = t("undo_none").to_string();
+        render(ui);
+        return;
+    }
+    match ui.session.ai_side {
+        AiSide::Player(ai) if ui.game.state.turn == ai && ui.game.can_undo() => {
+            ui.game.undo();
         }
-        // No promotion available
-        log_move(state, p_owner, cap_base, Some(of), Some(m_from), Some(m_to));
-        state.selected = None;
-        state.legal_moves.clear();
-        end_turn(state);
-    } else {
-        // Non-capture: move and flip
-        let p_owner = state.pieces[pidx].owner;
-        state.pieces[pidx].x = Some(mv_x);
-        state.pieces[pidx].y = Some(mv_y);
-        if state.pieces[pidx].cube != Cube::Gyoku {
-            if let Some(opp) = state.pieces[pidx].face.opposite() {
-                state.pieces[pidx].face = opp;
-            }
+        AiSide::Both => ui.paused = true,
+        _ => {}
+    }
+    after_turn(ui, Outcome::Continue);
+}
+
+/// Replay the last undone ply, and the AI's reply after it if there is one.
+fn redo_turn(ui: &mut UiState) {
+    let mut outcome = match ui.game.redo() {
+        Some(Ok(outcome)) => outcome,
+        _ => {
+            ui.message = t("redo_none").to_string();
+            render(ui);
+            return;
         }
-        let new_face = state.pieces[pidx].face;
-        let flipped = if new_face != of { format!("={}", fk(new_face)) } else { String::new() };
-        let text = format!("{}({},{})→({},{}){}",  fk(of), ox, oy, mv_x, mv_y, flipped);
-        log_move(state, p_owner, text, Some(of), Some(m_from), Some(m_to));
-        state.selected = None;
-        state.legal_moves.clear();
-        end_turn(state);
-    }
-}
-
-fn ai_setup_black_gyoku(state: &mut GameState) {
-    if let Some(gi) = state.gyoku(Owner::Black) {
-        state.pieces[gi].x = Some(0);
-        state.pieces[gi].y = Some(0);
-        let text = format!("{}↓(0,0)", fk(Face::Gyoku));
-        log_move(state, Owner::Black, text, Some(Face::Gyoku), None, Some((0, 0)));
-        state.mode = Mode::SetupWhiteGyoku;
-        let positions = get_white_gyoku_positions(state);
-        if !positions.is_empty() {
-            let best = positions.iter()
-                .max_by(|a, b| a.1.cmp(&b.1).then(b.0.abs().cmp(&a.0.abs())))
-                .copied().unwrap();
-            state.cursor = best;
-            state.legal_moves = positions.iter()
-                .map(|&(x, y)| ki_shogi::state::LegalMove { x, y, capture: None })
-                .collect();
-        }
-        state.turn = Owner::White;
-        state.message.clear();
-        render(state);
-    }
-}
-
-fn ai_setup_white_gyoku(state: &mut GameState) {
-    let positions = get_white_gyoku_positions(state);
-    if positions.is_empty() { return; }
-    let pick = positions[rand_idx(positions.len())];
-    if let Some(gi) = state.gyoku(Owner::White) {
-        state.pieces[gi].x = Some(pick.0);
-        state.pieces[gi].y = Some(pick.1);
-        let text = format!("{}↓({},{})", fk(Face::Gyoku), pick.0, pick.1);
-        log_move(state, Owner::White, text, Some(Face::Gyoku), None, Some(pick));
-        state.legal_moves.clear();
-        state.turn = Owner::Black;
-        state.mode = Mode::Board;
-        if let Some(bgi) = state.gyoku(Owner::Black) {
-            if state.pieces[bgi].on_board() {
-                state.cursor = (state.pieces[bgi].x.unwrap(), state.pieces[bgi].y.unwrap());
+    };
+    if let AiSide::Player(ai) = ui.session.ai_side {
+        if ui.game.state.turn == ai && !outcome.is_over() {
+            if let Some(Ok(next)) = ui.game.redo() {
+                outcome = next;
             }
         }
-        state.message = t("game_start").to_string();
-        render(state);
     }
+    after_turn(ui, outcome);
 }
 
-fn rand_idx(max: usize) -> usize {
-    use std::time::SystemTime;
-    if max == 0 { return 0; }
-    let seed = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .unwrap_or_default()
-        .subsec_nanos() as usize;
-    seed % max
+fn start_game(ui: &mut UiState, use_ki: bool, ai_side: AiSide) {
+    ui.start_game(use_ki, ai_side);
+    if let Some(pos) = ui.session.start_position.clone() {
+        // Checked in main() before the TUI started
+        let outcome = ui.game.load_position(&pos).expect("start position was validated");
+        after_turn(ui, outcome);
+        if outcome == Outcome::Continue {
+            ui.message = t("game_start").to_string();
+            render(ui);
+        }
+        return;
+    }
+    if ai_side.is_ai(Owner::Black) {
+        render(ui);
+        thread::sleep(Duration::from_millis(300));
+        ai_setup_black_gyoku(ui);
+        if ai_side.is_ai(Owner::White) {
+            thread::sleep(Duration::from_millis(300));
+            ai_setup_white_gyoku(ui);
+        }
+        return;
+    }
+    render(ui);
 }
 
-fn ai_do_move_with_action(state: &mut GameState, action: Action) {
-    match action {
-        Action::Move { piece_idx, mv, promote_to } => {
-            let from = state.pieces[piece_idx].pos();
-            state.selected = Some(piece_idx);
-            execute_move(state, piece_idx, mv.x, mv.y, mv.capture, promote_to);
-            state.last_move_from = from;
-            state.last_move_to = Some((mv.x, mv.y));
+fn save_game(ui: &mut UiState) {
+    let record = GameRecord::from_game(&ui.game, &ui.session, &today());
+    let path = record_file_name();
+    match save_record(&path, &record) {
+        Ok(()) => {
+            ui.message = format!("{}{}", t("record_saved"), path);
+            ui.record_path = Some(path);
         }
-        Action::Drop { piece_idx, face, pos } => {
-            state.pieces[piece_idx].face = face;
-            state.pieces[piece_idx].x = Some(pos.0);
-            state.pieces[piece_idx].y = Some(pos.1);
-            let text = format!("{}↓({},{})", fk(face), pos.0, pos.1);
-            let owner = state.pieces[piece_idx].owner;
-            log_move(state, owner, text, Some(face), None, Some(pos));
-            state.selected = None;
-            state.drop_targets.clear();
-            state.last_move_from = None;
-            state.last_move_to = Some(pos);
-            end_turn(state);
+        Err(e) => ui.message = format!("{}{}", t("record_save_failed"), e),
+    }
+    render(ui);
+}
+
+/// Replace the game with a record file and show it in the log browser,
+/// leaving it (Esc) to play on from the last position.
+fn open_record(ui: &mut UiState, path: &str) -> Result<(), RecordError> {
+    let (game, session) = load_record(path)?.replay()?;
+    ui.set_game(game);
+    ui.session = session;
+    ui.record_path = Some(path.to_string());
+    let ai_side = ui.session.ai_side;
+    if ui.game.phase() == Phase::Setup && ui.game.turn() == Owner::Black {
+        if ai_side.is_ai(Owner::Black) {
+            ai_setup_black_gyoku(ui);
+        } else {
+            ui.mode = Mode::SetupBlackGyoku;
+        }
+    } else if ui.game.phase() == Phase::Setup {
+        after_black_gyoku(ui);
+    }
+    if ui.game.phase() == Phase::Setup && ui.game.turn() == Owner::White && ai_side.is_ai(Owner::White) {
+        ai_setup_white_gyoku(ui);
+    }
+    match ui.game.phase() {
+        Phase::Setup => {}
+        Phase::Play => {
+            let state = &ui.game.state;
+            ui.mode = if state.draw_offer.is_some() { Mode::DrawOffer } else { Mode::Board };
+            if let Some(pos) = state.gyoku(state.turn).and_then(|gi| state.pieces[gi].pos()) {
+                ui.cursor = pos;
+            }
+        }
+        Phase::Over => {
+            let draw = ui.game.result().is_some_and(|r| r.is_draw());
+            ui.mode = if draw { Mode::Draw } else { Mode::GameOver };
+        }
+    }
+    ui.message = format!("{}{}", t("record_loaded"), path);
+    enter_log_browse(ui);
+    Ok(())
+}
+
+fn reopen_record(ui: &mut UiState) {
+    match ui.record_path.clone() {
+        Some(path) => {
+            if let Err(e) = open_record(ui, &path) {
+                ui.message = format!("{}{}", t("record_load_failed"), e);
+            }
         }
+        None => ui.message = t("no_record").to_string(),
     }
+    render(ui);
 }
 
-fn on_menu(state: &mut GameState, key: Key) {
-    if key == Key::Esc && state.menu_step > 1 {
-        state.menu_step -= 1;
-        if state.menu_step == 2 && !state.want_ai { state.menu_step = 1; }
-        render(state);
+fn on_menu(ui: &mut UiState, key: Key) {
+    let state = &mut ui.game.state;
+    if key == Key::Esc && ui.menu_step > 1 {
+        ui.menu_step -= 1;
+        if ui.menu_step == 2 && !ui.want_ai { ui.menu_step = 1; }
+        if ui.menu_step == 2 && ui.session.start_position.is_some() { ui.menu_step = 1; }
+        render(ui);
         return;
     }
-    if state.menu_step == 1 {
+    if ui.menu_step == 1 {
         match key {
-            Key::One => state.want_ai = false,
-            Key::Two => state.want_ai = true,
+            Key::One => ui.want_ai = false,
+            Key::Two => ui.want_ai = true,
             Key::Three => {
-                state.tutorial_page = 0;
-                state.mode = Mode::Tutorial;
-                render(state);
+                ui.tutorial_page = 0;
+                ui.mode = Mode::Tutorial;
+                render(ui);
                 return;
             }
             _ => return,
         }
-        state.menu_step = 2;
-    } else if state.menu_step == 2 {
+        ui.menu_step = 2;
+        // The position string fixes the piece set
+        if ui.session.start_position.is_some() {
+            if !ui.want_ai {
+                start_game(ui, false, AiSide::None);
+                return;
+            }
+            ui.menu_step = 3;
+        }
+    } else if ui.menu_step == 2 {
         match key {
-            Key::One => state.use_ki = false,
-            Key::Two => state.use_ki = true,
+            Key::One => state.rules.use_ki = false,
+            Key::Two => state.rules.use_ki = true,
             _ => return,
         }
-        if state.want_ai {
-            state.menu_step = 3;
+        if ui.want_ai {
+            ui.menu_step = 3;
         } else {
-            state.start_game(state.use_ki, AiSide::None);
+            let use_ki = state.rules.use_ki;
+            start_game(ui, use_ki, AiSide::None);
+            return;
         }
-    } else if state.menu_step == 3 {
+    } else if ui.menu_step == 3 {
         match key {
-            Key::One => state.ai_difficulty = 1,
-            Key::Two => state.ai_difficulty = 2,
-            Key::Three => state.ai_difficulty = 3,
-            Key::Four => state.ai_difficulty = 4,
-            Key::Five => state.ai_difficulty = 5,
+            Key::One => ui.session.ai_difficulty = 1,
+            Key::Two => ui.session.ai_difficulty = 2,
+            Key::Three => ui.session.ai_difficulty = 3,
+            Key::Four => ui.session.ai_difficulty = 4,
+            Key::Five => ui.session.ai_difficulty = 5,
             _ => return,
         }
-        state.menu_step = 4;
-    } else if state.menu_step == 4 {
+        ui.menu_step = 4;
+    } else if ui.menu_step == 4 {
         let ai_side = match key {
             Key::One => AiSide::Player(Owner::White),
             Key::Two => AiSide::Player(Owner::Black),
             Key::Three => {
-                if rand_idx(2) == 0 { AiSide::Player(Owner::Black) }
+                if ui.session.rng.below(2) == 0 { AiSide::Player(Owner::Black) }
                 else { AiSide::Player(Owner::White) }
             }
             Key::Four => AiSide::Both,
             _ => return,
         };
-        let use_ki = state.use_ki;
-        state.start_game(use_ki, ai_side);
-        if ai_side.is_ai(Owner::Black) {
-            ai_setup_black_gyoku(state);
-            if ai_side.is_ai(Owner::White) {
-                thread::sleep(Duration::from_millis(300));
-                ai_setup_white_gyoku(state);
-            }
-            return;
-        }
+        let use_ki = state.rules.use_ki;
+        start_game(ui, use_ki, ai_side);
+        return;
     }
-    render(state);
+    render(ui);
 }
 
-fn on_tutorial(state: &mut GameState, key: Key) {
+fn on_tutorial(ui: &mut UiState, key: Key) {
     match key {
         Key::Esc => {
-            state.mode = Mode::Menu;
-            state.menu_step = 1;
+            ui.mode = Mode::Menu;
+            ui.menu_step = 1;
         }
-        Key::Right if state.tutorial_page < 6 => {
-            state.tutorial_page += 1;
+        Key::Right if ui.tutorial_page < 6 => {
+            ui.tutorial_page += 1;
         }
-        Key::Left if state.tutorial_page > 0 => {
-            state.tutorial_page -= 1;
+        Key::Left if ui.tutorial_page > 0 => {
+            ui.tutorial_page -= 1;
         }
         _ => {}
     }
-    render(state);
+    render(ui);
 }
 
-fn on_log_browse(state: &mut GameState, key: Key) {
+fn on_log_browse(ui: &mut UiState, key: Key) {
     match key {
         Key::Esc | Key::Tab => {
-            state.mode = state.pre_log_mode.unwrap_or(Mode::Board);
-            state.pre_log_mode = None;
+            ui.mode = ui.pre_log_mode.unwrap_or(Mode::Board);
+            ui.pre_log_mode = None;
         }
-        Key::Up => {
-            if state.log_index > 0 { state.log_index -= 1; }
+        Key::Up if ui.log_index > 0 => {
+            ui.log_index -= 1;
         }
-        Key::Down => {
-            if state.log_index + 1 < state.move_log.len() {
-                state.log_index += 1;
-            }
+        Key::Down if ui.log_index + 1 < ui.game.move_log().len() => {
+            ui.log_index += 1;
         }
         _ => {}
     }
-    render(state);
+    render(ui);
 }
 
-fn on_setup_black_gyoku(state: &mut GameState, key: Key) {
+fn on_setup_black_gyoku(ui: &mut UiState, key: Key) {
     match key {
-        Key::Up => state.cursor.1 += 1,
-        Key::Down => state.cursor.1 -= 1,
-        Key::Left => state.cursor.0 -= 1,
-        Key::Right => state.cursor.0 += 1,
+        Key::Up => ui.cursor.1 += 1,
+        Key::Down => ui.cursor.1 -= 1,
+        Key::Left => ui.cursor.0 -= 1,
+        Key::Right => ui.cursor.0 += 1,
         Key::Enter => {
-            if let Some(gi) = state.gyoku(Owner::Black) {
-                state.pieces[gi].x = Some(state.cursor.0);
-                state.pieces[gi].y = Some(state.cursor.1);
-                let text = format!("{}↓({},{})", fk(Face::Gyoku), state.cursor.0, state.cursor.1);
-                log_move(state, Owner::Black, text, Some(Face::Gyoku), None, Some(state.cursor));
-                state.mode = Mode::SetupWhiteGyoku;
-                let positions = get_white_gyoku_positions(state);
-                if !positions.is_empty() {
-                    let best = positions.iter()
-                        .max_by(|a, b| a.1.cmp(&b.1).then(b.0.abs().cmp(&a.0.abs())))
-                        .copied().unwrap();
-                    state.cursor = best;
-                    state.legal_moves = positions.iter()
-                        .map(|&(x, y)| ki_shogi::state::LegalMove { x, y, capture: None })
-                        .collect();
-                }
-                state.turn = Owner::White;
-                state.message.clear();
-                if state.ai_side.is_ai(Owner::White) {
-                    render(state);
+            let cursor = ui.cursor;
+            if ui.game.place_gyoku(cursor).is_ok() {
+                after_black_gyoku(ui);
+                if ui.session.ai_side.is_ai(Owner::White) {
+                    render(ui);
                     thread::sleep(Duration::from_millis(300));
-                    ai_setup_white_gyoku(state);
+                    ai_setup_white_gyoku(ui);
                     return;
                 }
             }
         }
         _ => {}
     }
-    render(state);
+    render(ui);
 }
 
-fn on_setup_white_gyoku(state: &mut GameState, key: Key) {
-    let positions = get_white_gyoku_positions(state);
-    let pos_set: std::collections::HashSet<(i32, i32)> = positions.into_iter().collect();
-
+fn on_setup_white_gyoku(ui: &mut UiState, key: Key) {
     match key {
-        Key::Up => state.cursor.1 += 1,
-        Key::Down => state.cursor.1 -= 1,
-        Key::Left => state.cursor.0 -= 1,
-        Key::Right => state.cursor.0 += 1,
+        Key::Up => ui.cursor.1 += 1,
+        Key::Down => ui.cursor.1 -= 1,
+        Key::Left => ui.cursor.0 -= 1,
+        Key::Right => ui.cursor.0 += 1,
         Key::Enter => {
-            if pos_set.contains(&state.cursor) {
-                if let Some(gi) = state.gyoku(Owner::White) {
-                    state.pieces[gi].x = Some(state.cursor.0);
-                    state.pieces[gi].y = Some(state.cursor.1);
-                    let text = format!("{}↓({},{})", fk(Face::Gyoku), state.cursor.0, state.cursor.1);
-                    log_move(state, Owner::White, text, Some(Face::Gyoku), None, Some(state.cursor));
-                    state.legal_moves.clear();
-                    state.turn = Owner::Black;
-                    state.mode = Mode::Board;
-                    if let Some(bgi) = state.gyoku(Owner::Black) {
-                        if state.pieces[bgi].on_board() {
-                            state.cursor = (state.pieces[bgi].x.unwrap(), state.pieces[bgi].y.unwrap());
-                        }
-                    }
-                    state.message = t("game_start").to_string();
-                }
+            let cursor = ui.cursor;
+            if ui.game.place_gyoku(cursor).is_ok() {
+                after_white_gyoku(ui);
             } else {
-                state.message = t("invalid_pos").to_string();
+                ui.message = t("invalid_pos").to_string();
             }
         }
         _ => {}
     }
-    render(state);
+    render(ui);
+}
+
+fn enter_log_browse(ui: &mut UiState) {
+    let log = ui.game.move_log();
+    if !log.is_empty() {
+        ui.log_index = log.len() - 1;
+        ui.pre_log_mode = Some(ui.mode);
+        ui.mode = Mode::LogBrowse;
+    }
 }
 
-fn on_board(state: &mut GameState, key: Key) {
+fn on_board(ui: &mut UiState, key: Key) {
+    let state = &mut ui.game.state;
     match key {
-        Key::Up => state.cursor.1 += 1,
-        Key::Down => state.cursor.1 -= 1,
-        Key::Left => state.cursor.0 -= 1,
-        Key::Right => state.cursor.0 += 1,
-        Key::Tab => {
-            if !state.move_log.is_empty() {
-                state.log_index = state.move_log.len() - 1;
-                state.pre_log_mode = Some(Mode::Board);
-                state.mode = Mode::LogBrowse;
-            }
-        }
+        Key::Up => ui.cursor.1 += 1,
+        Key::Down => ui.cursor.1 -= 1,
+        Key::Left => ui.cursor.0 -= 1,
+        Key::Right => ui.cursor.0 += 1,
+        Key::Tab => enter_log_browse(ui),
         Key::D => {
-            state.last_move_from = None;
-            state.last_move_to = None;
+            ui.last_move_from = None;
+            ui.last_move_to = None;
+            ui.last_stranded.clear();
             let hand = state.hand_pieces_for(state.turn);
             if hand.is_empty() {
-                state.message = t("no_hand").to_string();
-                render(state);
+                ui.message = t("no_hand").to_string();
+                render(ui);
                 return;
             }
-            state.hand_pieces = hand;
-            state.hand_index = 0;
-            state.mode = Mode::Hand;
+            ui.hand_pieces = hand;
+            ui.hand_index = 0;
+            ui.mode = Mode::Hand;
         }
         Key::Enter => {
-            state.last_move_from = None;
-            state.last_move_to = None;
-            if let Some(pi) = state.piece_at(state.cursor.0, state.cursor.1) {
+            ui.last_move_from = None;
+            ui.last_move_to = None;
+            ui.last_stranded.clear();
+            if let Some(pi) = state.piece_at(ui.cursor.0, ui.cursor.1) {
                 if state.pieces[pi].owner == state.turn {
                     let moves = get_legal_moves(state, pi);
                     if moves.is_empty() {
-                        state.message = if is_in_check(state, state.turn) {
+                        ui.message = if is_in_check(state, state.turn) {
                             t("no_moves_check").to_string()
                         } else {
                             t("no_moves").to_string()
                         };
-                        render(state);
+                        render(ui);
                         return;
                     }
-                    state.selected = Some(pi);
-                    state.legal_moves = moves;
-                    state.mode = Mode::Selected;
+                    ui.selected = Some(pi);
+                    ui.legal_moves = moves;
+                    ui.mode = Mode::Selected;
                 } else {
-                    state.message = t("not_yours").to_string();
+                    ui.message = t("not_yours").to_string();
                 }
             } else {
-                state.message = t("empty_sq").to_string();
+                ui.message = t("empty_sq").to_string();
             }
         }
         _ => {}
     }
-    render(state);
+    render(ui);
 }
 
-fn on_selected(state: &mut GameState, key: Key) {
+fn on_selected(ui: &mut UiState, key: Key) {
+    let state = &mut ui.game.state;
     match key {
         Key::Esc => {
-            state.selected = None;
-            state.legal_moves.clear();
-            state.mode = Mode::Board;
-            state.message.clear();
-        }
-        Key::Up => state.cursor.1 += 1,
-        Key::Down => state.cursor.1 -= 1,
-        Key::Left => state.cursor.0 -= 1,
-        Key::Right => state.cursor.0 += 1,
+            ui.selected = None;
+            ui.legal_moves.clear();
+            ui.mode = Mode::Board;
+            ui.message.clear();
+        }
+        Key::Up => ui.cursor.1 += 1,
+        Key::Down => ui.cursor.1 -= 1,
+        Key::Left => ui.cursor.0 -= 1,
+        Key::Right => ui.cursor.0 += 1,
         Key::Enter => {
-            let found = state.legal_moves.iter()
-                .find(|m| m.x == state.cursor.0 && m.y == state.cursor.1)
+            let found = ui.legal_moves.iter()
+                .find(|m| m.x == ui.cursor.0 && m.y == ui.cursor.1)
                 .cloned();
             if let Some(mv) = found {
-                let pidx = state.selected.unwrap();
-                execute_move(state, pidx, mv.x, mv.y, mv.capture, None);
-                return;
+                let pidx = ui.selected.unwrap();
+                let p = &state.pieces[pidx];
+                let promos = match mv.capture {
+                    Some(ci) if p.cube != Cube::Gyoku && state.pieces[ci].cube != Cube::Gyoku => {
+                        p.face.promotions()
+                    }
+                    _ => &[],
+                };
+                // Several promotion faces: let the player choose first
+                if promos.len() > 1 {
+                    ui.promote_choices = promos.to_vec();
+                    ui.promote_index = 0;
+                    ui.pending_move = Some(mv);
+                    ui.mode = Mode::Promote;
+                    render(ui);
+                    return;
+                }
+                let action = Action::Move { piece_idx: pidx, mv, promote_to: None };
+                if let Ok(outcome) = ui.game.apply(&action) {
+                    after_turn(ui, outcome);
+                    return;
+                }
             } else {
-                state.message = t("invalid_target").to_string();
+                let pidx = ui.selected.unwrap();
+                ui.message = match explain_move(state, pidx, ui.cursor) {
+                    Some(reason) => illegal_message(state, &reason),
+                    None => t("invalid_target").to_string(),
+                };
             }
         }
         _ => {}
     }
-    render(state);
+    render(ui);
 }
 
-fn on_hand(state: &mut GameState, key: Key) {
+/// Status line text saying why a move or drop is illegal.
+fn illegal_message(state: &GameState, reason: &IllegalReason) -> String {
+    let sq = |(x, y): (i32, i32)| format!("({},{})", x, y);
+    let piece = |i: usize| {
+        let p = &state.pieces[i];
+        format!("{}{}", p.face.kanji(), p.pos().map(sq).unwrap_or_default())
+    };
+    let gyoku = |at: (i32, i32)| format!("{}{}", Face::Gyoku.kanji(), sq(at));
+    match reason {
+        IllegalReason::Unavailable => t("invalid_target").to_string(),
+        IllegalReason::Unreachable { face, from, to } => {
+            format!("{}{}{}→{}", t("why_unreachable"), face.kanji(), sq(*from), sq(*to))
+        }
+        IllegalReason::Blocked { piece: i, .. } => format!("{}{}", t("why_blocked"), piece(*i)),
+        IllegalReason::Unsupported { at } => format!("{}{}", t("why_unsupported"), sq(*at)),
+        IllegalReason::GyokuDistance { required, .. } => format!("{}{}", t("why_gyoku_distance"), required),
+        IllegalReason::LeavesCheck { attackers, .. } => {
+            let names: Vec<String> = attackers.iter().map(|&i| piece(i)).collect();
+            format!("{}{}", t("why_leaves_check"), names.join(" "))
+        }
+        IllegalReason::DropGivesCheck { gyoku: g, .. } => format!("{}{}", t("why_drop_check"), gyoku(*g)),
+        IllegalReason::DropNextToGyoku { gyoku: g, .. } => format!("{}{}", t("why_drop_adjacent"), gyoku(*g)),
+        IllegalReason::DropOutOfRange { range, .. } => format!("{}{}", t("why_drop_range"), range),
+    }
+}
+
+fn on_hand(ui: &mut UiState, key: Key) {
     match key {
         Key::Esc => {
-            state.mode = Mode::Board;
-            state.message.clear();
+            ui.mode = Mode::Board;
+            ui.message.clear();
         }
-        Key::Up => {
-            if state.hand_index > 0 { state.hand_index -= 1; }
+        Key::Up if ui.hand_index > 0 => {
+            ui.hand_index -= 1;
         }
-        Key::Down => {
-            if state.hand_index + 1 < state.hand_pieces.len() {
-                state.hand_index += 1;
-            }
+        Key::Down if ui.hand_index + 1 < ui.hand_pieces.len() => {
+            ui.hand_index += 1;
         }
         Key::Enter => {
-            let pi = state.hand_pieces[state.hand_index];
-            let cube = state.pieces[pi].cube;
+            let pi = ui.hand_pieces[ui.hand_index];
+            let cube = ui.game.state.pieces[pi].cube;
             let faces = Face::cube_faces(cube).to_vec();
             if faces.is_empty() {
-                state.message = t("cant_drop").to_string();
-                render(state);
+                ui.message = t("cant_drop").to_string();
+                render(ui);
                 return;
             }
-            state.drop_faces = faces;
-            state.face_index = 0;
-            state.selected = Some(pi);
-            state.mode = Mode::FaceSelect;
+            ui.drop_faces = faces;
+            ui.face_index = 0;
+            ui.selected = Some(pi);
+            ui.mode = Mode::FaceSelect;
         }
         _ => {}
     }
-    render(state);
+    render(ui);
 }
 
-fn on_face_select(state: &mut GameState, key: Key) {
+fn on_face_select(ui: &mut UiState, key: Key) {
     match key {
         Key::Esc => {
-            state.mode = Mode::Hand;
-            state.message.clear();
+            ui.mode = Mode::Hand;
+            ui.message.clear();
         }
-        Key::Up => {
-            if state.face_index > 0 { state.face_index -= 1; }
+        Key::Up if ui.face_index > 0 => {
+            ui.face_index -= 1;
         }
-        Key::Down => {
-            if state.face_index + 1 < state.drop_faces.len() {
-                state.face_index += 1;
-            }
+        Key::Down if ui.face_index + 1 < ui.drop_faces.len() => {
+            ui.face_index += 1;
         }
         Key::Enter => {
-            let face = state.drop_faces[state.face_index];
+            let face = ui.drop_faces[ui.face_index];
+            let state = &mut ui.game.state;
             let drops = get_legal_drops(state, state.turn, face);
             if drops.is_empty() {
-                state.message = t("no_drop_pos").to_string();
-                render(state);
+                ui.message = t("no_drop_pos").to_string();
+                render(ui);
                 return;
             }
-            if let Some(si) = state.selected {
-                state.pieces[si].face = face;
-            }
-            state.cursor = drops[0];
-            state.drop_targets = drops;
-            state.mode = Mode::DropTarget;
+            ui.cursor = drops[0];
+            ui.drop_targets = drops;
+            ui.mode = Mode::DropTarget;
         }
         _ => {}
     }
-    render(state);
+    render(ui);
 }
 
-fn on_drop_target(state: &mut GameState, key: Key) {
+fn on_drop_target(ui: &mut UiState, key: Key) {
+    let state = &mut ui.game.state;
     match key {
         Key::Esc => {
-            state.drop_targets.clear();
-            state.mode = Mode::FaceSelect;
-            state.message.clear();
-        }
-        Key::Up => state.cursor.1 += 1,
-        Key::Down => state.cursor.1 -= 1,
-        Key::Left => state.cursor.0 -= 1,
-        Key::Right => state.cursor.0 += 1,
+            ui.drop_targets.clear();
+            ui.mode = Mode::FaceSelect;
+            ui.message.clear();
+        }
+        Key::Up => ui.cursor.1 += 1,
+        Key::Down => ui.cursor.1 -= 1,
+        Key::Left => ui.cursor.0 -= 1,
+        Key::Right => ui.cursor.0 += 1,
         Key::Enter => {
-            if !state.drop_targets.contains(&state.cursor) {
-                state.message = t("invalid_drop").to_string();
-                render(state);
+            if !ui.drop_targets.contains(&ui.cursor) {
+                let face = ui.drop_faces[ui.face_index];
+                ui.message = match explain_drop(state, state.turn, face, ui.cursor) {
+                    Some(reason) => illegal_message(state, &reason),
+                    None => t("invalid_drop").to_string(),
+                };
+                render(ui);
                 return;
             }
-            let si = state.selected.unwrap();
-            let face = state.pieces[si].face;
-            let owner = state.pieces[si].owner;
-            state.pieces[si].x = Some(state.cursor.0);
-            state.pieces[si].y = Some(state.cursor.1);
-            let text = format!("{}↓({},{})", fk(face), state.cursor.0, state.cursor.1);
-            log_move(state, owner, text, Some(face), None, Some(state.cursor));
-            state.selected = None;
-            state.drop_targets.clear();
-            end_turn(state);
-            return;
+            let action = Action::Drop {
+                piece_idx: ui.selected.unwrap(),
+                face: ui.drop_faces[ui.face_index],
+                pos: ui.cursor,
+            };
+            // The dropped piece leaves the hand list
+            let hand = std::mem::take(&mut ui.hand_pieces);
+            if let Ok(outcome) = ui.game.apply(&action) {
+                after_turn(ui, outcome);
+                return;
+            }
+            ui.hand_pieces = hand;
         }
         _ => {}
     }
-    render(state);
+    render(ui);
 }
 
-fn on_promote(state: &mut GameState, key: Key) {
+fn on_promote(ui: &mut UiState, key: Key) {
     match key {
-        Key::Up => {
-            if state.promote_index > 0 { state.promote_index -= 1; }
+        Key::Up if ui.promote_index > 0 => {
+            ui.promote_index -= 1;
         }
-        Key::Down => {
-            if state.promote_index + 1 < state.promote_choices.len() {
-                state.promote_index += 1;
-            }
+        Key::Down if ui.promote_index + 1 < ui.promote_choices.len() => {
+            ui.promote_index += 1;
         }
         Key::Enter => {
-            let chosen = state.promote_choices[state.promote_index];
-            if let Some(si) = state.selected {
-                state.pieces[si].face = chosen;
-            }
-            if let Some(info) = state.pending_log_info.take() {
-                let text = format!("{}→{}", info.cap_base, fk(chosen));
-                log_move(state, info.owner, text, Some(info.face), Some(info.from), Some(info.to));
+            let chosen = ui.promote_choices[ui.promote_index];
+            if let (Some(pidx), Some(mv)) = (ui.selected, ui.pending_move.take()) {
+                let action = Action::Move { piece_idx: pidx, mv, promote_to: Some(chosen) };
+                if let Ok(outcome) = ui.game.apply(&action) {
+                    after_turn(ui, outcome);
+                    return;
+                }
             }
-            state.selected = None;
-            state.legal_moves.clear();
-            state.promote_choices.clear();
-            end_turn(state);
-            return;
         }
         _ => {}
     }
-    render(state);
+    render(ui);
 }
 
-fn handle_key(state: &mut GameState, key: Key) {
+fn handle_key(ui: &mut UiState, key: Key) {
     if key == Key::Q {
         cleanup();
         std::process::exit(0);
     }
     if key == Key::L {
         toggle_lang();
-        render(state);
+        render(ui);
         return;
     }
-    if key == Key::R && state.mode != Mode::Menu {
-        state.reset();
-        state.start_game(state.use_ki, state.ai_side);
-        render(state);
-        if state.ai_side.is_ai(Owner::Black) {
-            thread::sleep(Duration::from_millis(300));
-            ai_setup_black_gyoku(state);
-            if state.ai_side.is_ai(Owner::White) {
-                thread::sleep(Duration::from_millis(300));
-                ai_setup_white_gyoku(state);
-            }
+    if key == Key::R && ui.mode != Mode::Menu {
+        ui.reset();
+        let (use_ki, ai_side) = (ui.game.state.rules.use_ki, ui.session.ai_side);
+        start_game(ui, use_ki, ai_side);
+        return;
+    }
+    if key == Key::M && ui.mode != Mode::Menu {
+        ui.reset_to_menu();
+        render(ui);
+        return;
+    }
+
+    if key == Key::S
+        && matches!(ui.mode, Mode::Board | Mode::GameOver | Mode::Draw | Mode::LogBrowse)
+    {
+        save_game(ui);
+        return;
+    }
+    if key == Key::O
+        && matches!(ui.mode, Mode::Menu | Mode::Board | Mode::GameOver | Mode::Draw | Mode::LogBrowse)
+    {
+        // Never replace the game under a running AI search
+        let state = &ui.game.state;
+        if ui.mode == Mode::Board && ui.session.ai_side.is_ai(state.turn) && !ui.paused {
+            return;
         }
+        reopen_record(ui);
         return;
     }
-    if key == Key::M && state.mode != Mode::Menu {
-        state.reset_to_menu();
-        render(state);
+
+    if matches!(key, Key::U | Key::Y)
+        && matches!(ui.mode, Mode::Board | Mode::GameOver | Mode::Draw)
+    {
+        // Never change the position under a running AI search
+        let state = &ui.game.state;
+        if ui.mode == Mode::Board && ui.session.ai_side.is_ai(state.turn) && !ui.paused {
+            return;
+        }
+        if key == Key::U { undo_turn(ui) } else { redo_turn(ui) }
+        return;
+    }
+
+    if matches!(key, Key::X | Key::Equals) && ui.mode == Mode::Board {
+        let state = &ui.game.state;
+        if ui.session.ai_side.is_ai(state.turn) {
+            return;
+        }
+        if key == Key::Equals {
+            offer_draw(ui);
+        } else {
+            ui.mode = Mode::ConfirmResign;
+            ui.message.clear();
+            render(ui);
+        }
         return;
     }
 
     // During AI turn: allow cursor, Tab, Space (pause), block the rest
-    if state.ai_side.is_ai(state.turn) && state.mode == Mode::Board {
+    let state = &ui.game.state;
+    if ui.session.ai_side.is_ai(state.turn) && ui.mode == Mode::Board {
         match key {
-            Key::Space => {
-                state.paused = !state.paused;
-                render(state);
-            }
-            Key::Up => { state.cursor.1 += 1; render(state); }
-            Key::Down => { state.cursor.1 -= 1; render(state); }
-            Key::Left => { state.cursor.0 -= 1; render(state); }
-            Key::Right => { state.cursor.0 += 1; render(state); }
-            Key::Tab if !state.move_log.is_empty() => {
-                state.log_index = state.move_log.len() - 1;
-                state.pre_log_mode = Some(Mode::Board);
-                state.mode = Mode::LogBrowse;
-                render(state);
-            }
-            _ => {}
+            Key::Space => ui.paused = !ui.paused,
+            Key::Up => ui.cursor.1 += 1,
+            Key::Down => ui.cursor.1 -= 1,
+            Key::Left => ui.cursor.0 -= 1,
+            Key::Right => ui.cursor.0 += 1,
+            Key::Tab => enter_log_browse(ui),
+            _ => return,
         }
+        render(ui);
         return;
     }
 
-    match state.mode {
-        Mode::Menu => on_menu(state, key),
-        Mode::SetupBlackGyoku => on_setup_black_gyoku(state, key),
-        Mode::SetupWhiteGyoku => on_setup_white_gyoku(state, key),
-        Mode::Board => on_board(state, key),
-        Mode::Selected => on_selected(state, key),
-        Mode::Hand => on_hand(state, key),
-        Mode::FaceSelect => on_face_select(state, key),
-        Mode::DropTarget => on_drop_target(state, key),
-        Mode::Promote => on_promote(state, key),
-        Mode::Tutorial => on_tutorial(state, key),
-        Mode::LogBrowse => on_log_browse(state, key),
+    match ui.mode {
+        Mode::Menu => on_menu(ui, key),
+        Mode::SetupBlackGyoku => on_setup_black_gyoku(ui, key),
+        Mode::SetupWhiteGyoku => on_setup_white_gyoku(ui, key),
+        Mode::Board => on_board(ui, key),
+        Mode::Selected => on_selected(ui, key),
+        Mode::Hand => on_hand(ui, key),
+        Mode::FaceSelect => on_face_select(ui, key),
+        Mode::DropTarget => on_drop_target(ui, key),
+        Mode::Promote => on_promote(ui, key),
+        Mode::Tutorial => on_tutorial(ui, key),
+        Mode::LogBrowse => on_log_browse(ui, key),
+        Mode::ConfirmResign => on_confirm_resign(ui, key),
+        Mode::DrawOffer => on_draw_offer(ui, key),
         Mode::GameOver | Mode::Draw => {
             match key {
-                Key::Up => { state.cursor.1 += 1; render(state); }
-                Key::Down => { state.cursor.1 -= 1; render(state); }
-                Key::Left => { state.cursor.0 -= 1; render(state); }
-                Key::Right => { state.cursor.0 += 1; render(state); }
-                Key::Tab if !state.move_log.is_empty() => {
-                    state.log_index = state.move_log.len() - 1;
-                    state.pre_log_mode = Some(state.mode);
-                    state.mode = Mode::LogBrowse;
-                    render(state);
-                }
-                _ => {}
+                Key::Up => ui.cursor.1 += 1,
+                Key::Down => ui.cursor.1 -= 1,
+                Key::Left => ui.cursor.0 -= 1,
+                Key::Right => ui.cursor.0 += 1,
+                Key::Tab => enter_log_browse(ui),
+                _ => return,
             }
+            render(ui);
         }
     }
 }
 
+fn usage() -> ! {
+    eprintln!("usage: ki-shogi [--pieces <piece set file>] [--rules <rules>] [--seed <n>] [--position \"<position string>\"] [--load <record file>]");
+    eprintln!("                [--ai-resign <score>] [--ai-draw <score>]");
+    eprintln!("       ki-shogi [--pieces <piece set file>] perft <depth> \"<position string>\"");
+    std::process::exit(2);
+}
+
+/// `perft <depth> <position>`: print the leaf counts below each legal action
+/// of the side to move, then the total.
+fn run_perft(mut args: impl Iterator<Item = String>) -> ! {
+    let depth: u32 = args.next()
+        .and_then(|d| d.parse().ok())
+        .filter(|&d| d > 0)
+        .unwrap_or_else(|| usage());
+    let pos = args.next().unwrap_or_else(|| usage());
+    if args.next().is_some() { usage(); }
+    let mut state = GameState::from_position_string(&pos).unwrap_or_else(|e| {
+        eprintln!("invalid position: {}", e);
+        std::process::exit(1);
+    });
+
+    let mut total = PerftCounts::default();
+    for (action, counts) in perft_divide(&mut state, depth) {
+        println!("{}: {}", Notation::of_action(&state, &action).to_ascii(), counts);
+        total.add(&counts);
+    }
+    println!();
+    println!("{}", total);
+    std::process::exit(0);
+}
+
+/// What the AI thread sends back: its move, drop or resignation, and the
+/// rng it drew from.
+type AiReply = (Option<Ply>, Rng);
+
+/// What an AI request was made for: the pieces where they stand (unlike
+/// `position_key`, not up to translation), the side to move and whether a
+/// draw offer awaits an answer.
+type AiTag = (Zobrist, Owner, bool);
+
+fn ai_tag(state: &GameState) -> AiTag {
+    (state.zobrist, state.turn, state.draw_offer.is_some())
+}
+
+/// An AI threshold option: the score below which the AI takes the step.
+fn threshold_arg(arg: Option<String>) -> Option<i32> {
+    Some(arg.and_then(|s| s.parse().ok()).unwrap_or_else(|| usage()))
+}
+
 fn main() {
-    terminal::enable_raw_mode().expect("Failed to enable raw mode");
+    let mut args = std::env::args().skip(1);
     let mut state = GameState::new();
-    render(&state);
+    let mut session = Session::new();
+    let mut pieces_path = None;
+    let mut record_path = None;
+    // Nothing in the loop may look at a face: the piece set is installed
+    // after it, whatever the order of the options.
+    let mut game_options = false;
+    let mut perft_args = None;
+    while let Some(arg) = args.next() {
+        if arg != "--pieces" && arg != "perft" {
+            game_options = true;
+        }
+        match arg.as_str() {
+            "--pieces" => pieces_path = Some(args.next().unwrap_or_else(|| usage())),
+            "perft" => perft_args = Some(args.by_ref().collect::<Vec<_>>()),
+            "--position" => {
+                session.start_position = Some(args.next().unwrap_or_else(|| usage()));
+            }
+            "--rules" => {
+                let spec = args.next().unwrap_or_else(|| usage());
+                state.rules = RuleSet::parse(&spec).unwrap_or_else(|e| {
+                    eprintln!("invalid rules: {}", e);
+                    std::process::exit(1);
+                });
+            }
+            "--seed" => {
+                let seed = args.next().and_then(|s| s.parse().ok()).unwrap_or_else(|| usage());
+                session.set_seed(seed);
+            }
+            "--ai-resign" => session.ai_resign_below = threshold_arg(args.next()),
+            "--ai-draw" => session.ai_draw_below = threshold_arg(args.next()),
+            "--load" => record_path = Some(args.next().unwrap_or_else(|| usage())),
+            _ => usage(),
+        }
+    }
+    if let Some(path) = &pieces_path {
+        if let Err(e) = load_piece_set(path).and_then(install) {
+            eprintln!("invalid piece set {}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+    if let Some(perft_args) = perft_args {
+        if game_options { usage(); }
+        run_perft(perft_args.into_iter());
+    }
+    if let Some(path) = &record_path {
+        if let Err(e) = load_record(path).and_then(|r| r.replay()) {
+            eprintln!("cannot load {}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+    // Validate now, under the chosen rules, so a bad string never reaches the TUI
+    if let Some(pos) = &session.start_position {
+        if let Err(e) = state.clone().load_position(pos) {
+            eprintln!("invalid position: {}", e);
+            std::process::exit(1);
+        }
+    }
 
-    let mut ai_rx: Option<mpsc::Receiver<Option<Action>>> = None;
+    terminal::enable_raw_mode().expect("Failed to enable raw mode");
+    let load = record_path.is_some();
+    let mut ui = UiState::new(Game::from_state(state), session);
+    ui.record_path = record_path;
+    if load {
+        reopen_record(&mut ui);
+    } else {
+        render(&ui);
+    }
+
+    // The receiver is paired with the position it was asked about, so a
+    // result that arrives after the game has moved on (an undo, or a ply
+    // played while paused) is discarded. The search runs on a clone, so its
+    // rng comes back with the result to keep the game's random choices in
+    // one sequence.
+    let mut ai_rx: Option<(mpsc::Receiver<AiReply>, AiTag)> = None;
 
     loop {
-        // AI turn handling
-        if state.mode == Mode::Board && state.ai_side.is_ai(state.turn) && !state.paused {
+        // AI turn handling: a move, or the answer to a draw offer
+        let state = &ui.game.state;
+        let ai_to_act = match ui.mode {
+            Mode::Board => ui.session.ai_side.is_ai(state.turn),
+            Mode::DrawOffer => ui.session.ai_side.is_ai(state.turn.opponent()),
+            _ => false,
+        };
+        if ai_to_act && !ui.paused {
             // Spawn AI thread if not already running
             if ai_rx.is_none() {
-                state.ai_think_start = Some(Instant::now());
-                let mut state_clone = state.clone();
+                ui.ai_think_start = Some(Instant::now());
+                // The search gets the position and its repetition counts only
+                let mut position = state.clone();
+                let seen = ui.game.position_history().clone();
+                let session = &ui.session;
+                let (level, mut rng) = (session.ai_difficulty, session.rng);
+                let (resign_below, draw_below) = (session.ai_resign_below, session.ai_draw_below);
                 let (tx, rx) = mpsc::channel();
                 thread::spawn(move || {
-                    let result = ai_choose(&mut state_clone);
-                    let _ = tx.send(result);
+                    let result = if position.draw_offer.is_some() {
+                        let accept = ai_accepts_draw(&mut position, draw_below);
+                        let d = if accept { Declaration::AcceptDraw } else { Declaration::DeclineDraw };
+                        Some(Ply::Declare(d))
+                    } else if ai_resigns(&mut position, resign_below) {
+                        Some(Ply::Declare(Declaration::Resign))
+                    } else {
+                        ai_choose(&mut position, &seen, level, &mut rng).map(Ply::Action)
+                    };
+                    let _ = tx.send((result, rng));
                 });
-                ai_rx = Some(rx);
-                render(&state);
+                ai_rx = Some((rx, ai_tag(state)));
+                render(&ui);
             }
 
             // Check if AI finished
-            if let Some(ref rx) = ai_rx {
+            if let Some((ref rx, tag)) = ai_rx {
                 match rx.try_recv() {
-                    Ok(action) => {
+                    Ok((ply, rng)) => {
                         ai_rx = None;
-                        state.ai_think_start = None;
-                        if let Some(action) = action {
-                            ai_do_move_with_action(&mut state, action);
+                        ui.ai_think_start = None;
+                        if tag != ai_tag(&ui.game.state) {
+                            continue;
+                        }
+                        ui.session.rng = rng;
+                        match ply {
+                            Some(Ply::Action(action)) => ai_do_move_with_action(&mut ui, action),
+                            Some(Ply::Declare(d)) if d.is_answer() => {
+                                answer_draw(&mut ui, d == Declaration::AcceptDraw);
+                            }
+                            Some(Ply::Declare(d)) => {
+                                if let Ok(outcome) = ui.game.declare(d) {
+                                    after_turn(&mut ui, outcome);
+                                }
+                            }
+                            _ => {}
                         }
                         continue;
                     }
                     Err(mpsc::TryRecvError::Empty) => {}
                     Err(mpsc::TryRecvError::Disconnected) => {
                         ai_rx = None;
-                        state.ai_think_start = None;
+                        ui.ai_think_start = None;
                     }
                 }
             }
 
             // Poll for user input while AI thinks
             if let Some(key) = poll_key(200) {
-                handle_key(&mut state, key);
+                handle_key(&mut ui, key);
             } else {
-                render(&state); // re-render to update timer
+                render(&ui); // re-render to update timer
             }
             continue;
         }
 
         // Human turn: block until key
         if let Some(key) = poll_key(500) {
-            handle_key(&mut state, key);
+            handle_key(&mut ui, key);
         }
     }
-}
\ No newline at end of file
+}
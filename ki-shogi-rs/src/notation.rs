@@ -0,0 +1,465 @@
+// Ki Shogi - Move notation (parse / format)
+
+use std::fmt;
+use crate::types::{Owner, Cube, Face};
+use crate::state::{Action, Declaration, GameState, Phase};
+use crate::rules::get_white_gyoku_positions;
+use crate::ai::get_all_actions;
+
+// ---------------------------------------------------------------------------
+// Grammar
+// ---------------------------------------------------------------------------
+//
+//     line     = ply {STRANDED face square}
+//     ply      = drop | move | capture | declare
+//     drop     = face DROP square                  (Gyoku: setup placement)
+//     move     = face [square] MOVE square [FLIP face]
+//     capture  = face [square] CAPTURE [face] square [PROMOTE face]
+//     square   = "(" int "," int ")"
+//     int      = ["-"] digit {digit}
+//     face     = kanji | name                      (see Face::name, any case)
+//     declare  = 投了 | 提和 | 受和 | 拒和
+//              | resign | offer-draw | accept-draw | decline-draw
+//
+//     symbol    kanji   ascii
+//     DROP      ↓       *
+//     MOVE      →       -
+//     CAPTURE   ×       x
+//     FLIP      =       =
+//     PROMOTE   →       -
+//     STRANDED  †       !
+//
+// The move log writes every part: 飛(0,1)→(0,3)=猪, 角(2,2)×飛(3,3)→瓦,
+// 麒↓(1,1), 玉↓(0,0). By hand the origin square, the captured face, the
+// flip and the promotion may be left out as long as one legal action still
+// matches, e.g. 飛→(0,3) or Kaku x(3,3)-Ga. Whitespace is ignored, so the
+// ASCII declarations are written with hyphens.
+//
+// Pieces of the mover stranded by a ply are annotated after it with the
+// face and square they had, e.g. 玉(0,0)→(1,0) †飛(0,2). The annotations
+// follow from the ply, so parsing skips them.
+
+/// A parsed ply, not yet checked against a position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Notation {
+    /// A drop from hand, or a Gyoku placement during setup.
+    Drop { face: Face, to: (i32, i32) },
+    /// A non-capturing move; `flip` is the face shown afterwards.
+    Move { face: Face, from: Option<(i32, i32)>, to: (i32, i32), flip: Option<Face> },
+    Capture {
+        face: Face,
+        from: Option<(i32, i32)>,
+        captured: Option<Face>,
+        to: (i32, i32),
+        promote: Option<Face>,
+    },
+    /// Resigning, or offering or answering a draw.
+    Declare(Declaration),
+}
+
+/// A piece of the side that just moved, lost to stranding: its face and
+/// square before it went to the opponent's hand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stranding {
+    pub face: Face,
+    pub at: (i32, i32),
+}
+
+/// A notation resolved against a position.
+#[derive(Debug, Clone)]
+pub enum Ply {
+    PlaceGyoku((i32, i32)),
+    Action(Action),
+    Declare(Declaration),
+}
+
+#[derive(Debug, Clone)]
+pub enum NotationError {
+    /// Character offset and what was expected there.
+    Syntax(usize, &'static str),
+    UnknownFace(String),
+    /// A Gyoku placement during play, anything else during setup, or a
+    /// draw offer answered when none was made (or not answered when one was).
+    WrongPhase,
+    /// No legal action matches.
+    Illegal,
+    /// More than one legal action matches; all of them are listed.
+    Ambiguous(Vec<Ply>),
+}
+
+impl fmt::Display for NotationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NotationError::Syntax(at, expected) => write!(f, "expected {} at {}", expected, at),
+            NotationError::UnknownFace(s) => write!(f, "unknown face '{}'", s),
+            NotationError::WrongPhase => f.write_str("not possible in this phase"),
+            NotationError::Illegal => f.write_str("no legal action matches"),
+            NotationError::Ambiguous(plies) => write!(f, "ambiguous: {} legal actions match", plies.len()),
+        }
+    }
+}
+
+impl std::error::Error for NotationError {}
+
+// ---------------------------------------------------------------------------
+// Formatting
+// ---------------------------------------------------------------------------
+
+struct Symbols {
+    drop: &'static str,
+    mv: &'static str,
+    capture: &'static str,
+    flip: &'static str,
+    promote: &'static str,
+    stranded: &'static str,
+    face: fn(&Face) -> &'static str,
+    /// Indexed like `Declaration::ALL`.
+    declare: [&'static str; 4],
+}
+
+const KANJI: Symbols = Symbols {
+    drop: "↓", mv: "→", capture: "×", flip: "=", promote: "→", stranded: "†", face: Face::kanji,
+    declare: ["投了", "提和", "受和", "拒和"],
+};
+
+const ASCII: Symbols = Symbols {
+    drop: "*", mv: "-", capture: "x", flip: "=", promote: "-", stranded: "!", face: Face::name,
+    declare: ["resign", "offer-draw", "accept-draw", "decline-draw"],
+};
+
+impl Notation {
+    /// The ASCII transliteration, e.g. Kaku(2,2)xHi(3,3)-Ga.
+    pub fn to_ascii(&self) -> String {
+        self.format(&ASCII)
+    }
+
+    fn format(&self, sym: &Symbols) -> String {
+        let sq = |(x, y): (i32, i32)| format!("({},{})", x, y);
+        let opt_sq = |p: Option<(i32, i32)>| p.map(sq).unwrap_or_default();
+        let opt_face = |prefix: &str, f: Option<Face>| {
+            f.map(|f| format!("{}{}", prefix, (sym.face)(&f))).unwrap_or_default()
+        };
+        match *self {
+            Notation::Drop { face, to } => format!("{}{}{}", (sym.face)(&face), sym.drop, sq(to)),
+            Notation::Move { face, from, to, flip } => format!(
+                "{}{}{}{}{}",
+                (sym.face)(&face), opt_sq(from), sym.mv, sq(to), opt_face(sym.flip, flip),
+            ),
+            Notation::Capture { face, from, captured, to, promote } => format!(
+                "{}{}{}{}{}{}",
+                (sym.face)(&face), opt_sq(from), sym.capture, opt_face("", captured),
+                sq(to), opt_face(sym.promote, promote),
+            ),
+            Notation::Declare(d) => sym.declare[d as usize].to_string(),
+        }
+    }
+
+    /// Full notation for an action in `state`, before it is applied.
+    pub fn of_action(state: &GameState, action: &Action) -> Notation {
+        match action {
+            Action::Drop { face, pos, .. } => Notation::Drop { face: *face, to: *pos },
+            Action::Move { piece_idx, mv, promote_to } => {
+                let piece = &state.pieces[*piece_idx];
+                let face = piece.face;
+                let (from, to) = (piece.pos(), (mv.x, mv.y));
+                match mv.capture {
+                    None => {
+                        let flip = if piece.cube == Cube::Gyoku { None } else { face.opposite() };
+                        Notation::Move { face, from, to, flip: flip.filter(|&f| f != face) }
+                    }
+                    Some(ci) => {
+                        let captured = state.pieces[ci].face;
+                        // Taking the Gyoku ends the game before any promotion;
+                        // a single promotion is taken without being chosen
+                        let promos: &[Face] = if piece.cube == Cube::Gyoku { &[] } else { face.promotions() };
+                        let promote = match (captured, promos) {
+                            (Face::Gyoku, _) => None,
+                            (_, [only]) => Some(*only),
+                            _ => *promote_to,
+                        };
+                        Notation::Capture { face, from, captured: Some(captured), to, promote }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Kanji notation, as written in the move log.
+impl fmt::Display for Notation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.format(&KANJI))
+    }
+}
+
+impl Stranding {
+    /// The ASCII transliteration, e.g. !Hi(3,2).
+    pub fn to_ascii(&self) -> String {
+        self.format(&ASCII)
+    }
+
+    fn format(&self, sym: &Symbols) -> String {
+        format!("{}{}({},{})", sym.stranded, (sym.face)(&self.face), self.at.0, self.at.1)
+    }
+}
+
+/// Kanji annotation, as appended in the move log: †飛(3,2).
+impl fmt::Display for Stranding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.format(&KANJI))
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Parsing
+// ---------------------------------------------------------------------------
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, kanji: char, ascii: char) -> bool {
+        let hit = matches!(self.peek(), Some(c) if c == kanji || c == ascii);
+        if hit { self.pos += 1; }
+        hit
+    }
+
+    fn expect(&mut self, c: char, what: &'static str) -> Result<(), NotationError> {
+        if self.eat(c, c) { Ok(()) } else { Err(NotationError::Syntax(self.pos, what)) }
+    }
+
+    fn face(&mut self) -> Result<Face, NotationError> {
+        let c = self.peek().ok_or(NotationError::Syntax(self.pos, "face"))?;
+        if !c.is_ascii() {
+            self.pos += 1;
+            return Face::from_name(&c.to_string()).ok_or_else(|| NotationError::UnknownFace(c.to_string()));
+        }
+        // No name is a prefix of another, so at most one matches
+        let rest: String = self.chars[self.pos..].iter().collect();
+        let face = Face::all()
+            .find(|f| rest.get(..f.name().len()).is_some_and(|r| r.eq_ignore_ascii_case(f.name())));
+        match face {
+            Some(f) => {
+                self.pos += f.name().len();
+                Ok(f)
+            }
+            None if c.is_ascii_alphabetic() => {
+                let word: String = rest.chars().take_while(|c| c.is_ascii_alphabetic()).collect();
+                Err(NotationError::UnknownFace(word))
+            }
+            None => Err(NotationError::Syntax(self.pos, "face")),
+        }
+    }
+
+    fn int(&mut self) -> Result<i32, NotationError> {
+        let start = self.pos;
+        if self.peek() == Some('-') { self.pos += 1; }
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) { self.pos += 1; }
+        let s: String = self.chars[start..self.pos].iter().collect();
+        s.parse().map_err(|_| NotationError::Syntax(start, "number"))
+    }
+
+    fn square(&mut self) -> Result<(i32, i32), NotationError> {
+        self.expect('(', "'('")?;
+        let x = self.int()?;
+        self.expect(',', "','")?;
+        let y = self.int()?;
+        self.expect(')', "')'")?;
+        Ok((x, y))
+    }
+
+    /// A declaration spanning the rest of the input.
+    fn declaration(&mut self) -> Option<Declaration> {
+        let rest: String = self.chars[self.pos..].iter().collect();
+        let i = (0..Declaration::ALL.len()).find(|&i| {
+            rest == KANJI.declare[i] || rest.eq_ignore_ascii_case(ASCII.declare[i])
+        })?;
+        self.pos = self.chars.len();
+        Some(Declaration::ALL[i])
+    }
+
+    fn ply(&mut self) -> Result<Notation, NotationError> {
+        if let Some(d) = self.declaration() {
+            return Ok(Notation::Declare(d));
+        }
+        let face = self.face()?;
+        if self.eat('↓', '*') {
+            return Ok(Notation::Drop { face, to: self.square()? });
+        }
+        let from = if self.peek() == Some('(') { Some(self.square()?) } else { None };
+        if self.eat('→', '-') {
+            let to = self.square()?;
+            let flip = if self.eat('=', '=') { Some(self.face()?) } else { None };
+            return Ok(Notation::Move { face, from, to, flip });
+        }
+        if self.eat('×', 'x') {
+            let captured = if self.peek() == Some('(') { None } else { Some(self.face()?) };
+            let to = self.square()?;
+            let promote = if self.eat('→', '-') { Some(self.face()?) } else { None };
+            return Ok(Notation::Capture { face, from, captured, to, promote });
+        }
+        Err(NotationError::Syntax(self.pos, "'↓', '→' or '×'"))
+    }
+}
+
+/// Parse kanji or ASCII notation.
+pub fn parse(s: &str) -> Result<Notation, NotationError> {
+    let chars: Vec<char> = s.chars().filter(|c| !c.is_whitespace()).collect();
+    let mut p = Parser { chars, pos: 0 };
+    let n = p.ply()?;
+    while p.eat('†', '!') {
+        p.face()?;
+        p.square()?;
+    }
+    if p.pos != p.chars.len() {
+        return Err(NotationError::Syntax(p.pos, "end of input"));
+    }
+    Ok(n)
+}
+
+// ---------------------------------------------------------------------------
+// Resolving
+// ---------------------------------------------------------------------------
+
+/// The single legal ply for the side to move that `n` describes.
+pub fn resolve(state: &mut GameState, n: &Notation) -> Result<Ply, NotationError> {
+    match (state.phase, n) {
+        (Phase::Setup, Notation::Drop { face: Face::Gyoku, to }) => {
+            let white = state.turn == Owner::White;
+            if white && !get_white_gyoku_positions(state).contains(to) {
+                return Err(NotationError::Illegal);
+            }
+            return Ok(Ply::PlaceGyoku(*to));
+        }
+        (Phase::Setup, _) | (Phase::Play, Notation::Drop { face: Face::Gyoku, .. }) => {
+            return Err(NotationError::WrongPhase);
+        }
+        (Phase::Over, _) => return Err(NotationError::Illegal),
+        (Phase::Play, Notation::Declare(d)) => {
+            if d.is_answer() != state.draw_offer.is_some() {
+                return Err(NotationError::WrongPhase);
+            }
+            return Ok(Ply::Declare(*d));
+        }
+        (Phase::Play, _) if state.draw_offer.is_some() => return Err(NotationError::WrongPhase),
+        (Phase::Play, _) => {}
+    }
+
+    let turn = state.turn;
+    let mut found: Vec<Action> = Vec::new();
+    for action in get_all_actions(state, turn) {
+        let full = Notation::of_action(state, &action);
+        // Equal notation means an equivalent action: two hand pieces of one
+        // cube, or promotion choices that a Gyoku capture never uses
+        if matches(n, &full) && !found.iter().any(|a| Notation::of_action(state, a) == full) {
+            found.push(action);
+        }
+    }
+    match found.len() {
+        0 => Err(NotationError::Illegal),
+        1 => Ok(Ply::Action(found.pop().unwrap())),
+        _ => Err(NotationError::Ambiguous(found.into_iter().map(Ply::Action).collect())),
+    }
+}
+
+/// Parse and resolve in one step.
+pub fn parse_ply(state: &mut GameState, s: &str) -> Result<Ply, NotationError> {
+    resolve(state, &parse(s)?)
+}
+
+/// Whether the (possibly partial) notation `n` describes the full one.
+fn matches(n: &Notation, full: &Notation) -> bool {
+    fn opt<T: PartialEq>(given: &Option<T>, actual: &Option<T>) -> bool {
+        given.is_none() || given == actual
+    }
+    match (n, full) {
+        (Notation::Drop { face, to }, Notation::Drop { face: f, to: t }) => face == f && to == t,
+        (
+            Notation::Move { face, from, to, flip },
+            Notation::Move { face: f, from: fr, to: t, flip: fl },
+        ) => face == f && opt(from, fr) && to == t && opt(flip, fl),
+        (
+            Notation::Capture { face, from, captured, to, promote },
+            Notation::Capture { face: f, from: fr, captured: c, to: t, promote: p },
+        ) => face == f && opt(from, fr) && opt(captured, c) && to == t && opt(promote, p),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{LogKind, MoveLogEntry};
+
+    fn face(name: &str) -> Face {
+        Face::from_name(name).unwrap()
+    }
+
+    fn entry(kind: LogKind, face: Face, face_after: Face, from: Option<(i32, i32)>, to: (i32, i32)) -> MoveLogEntry {
+        let mut e = MoveLogEntry::new(Owner::Black, kind);
+        e.cube = Some(face.cube());
+        e.face = Some(face);
+        e.face_after = Some(face_after);
+        e.from = from;
+        e.to = Some(to);
+        e
+    }
+
+    #[test]
+    fn log_text_parses_back_in_both_renderings() {
+        let mut flip = entry(LogKind::Move, face("Hi"), face("Cho"), Some((0, 1)), (0, 3));
+        flip.stranded.push(Stranding { face: face("Kaku"), at: (1, 4) });
+        let mut capture = entry(LogKind::Capture, face("Kaku"), face("Ga"), Some((2, 2)), (3, 3));
+        capture.captured = Some(face("Hi"));
+        capture.promote_to = Some(face("Ga"));
+        let drop = entry(LogKind::Drop, face("Ki"), face("Ki"), None, (1, 1));
+        let place = entry(LogKind::Place, Face::Gyoku, Face::Gyoku, None, (0, 0));
+        let resign = MoveLogEntry::new(Owner::White, LogKind::Declare(Declaration::Resign));
+
+        for e in [flip, capture, drop, place, resign] {
+            let n = e.notation();
+            assert_eq!(parse(&e.kanji()).unwrap(), n, "{}", e.kanji());
+            assert_eq!(parse(&e.to_ascii()).unwrap(), n, "{}", e.to_ascii());
+            assert_eq!(parse(&n.to_string()).unwrap().to_ascii(), n.to_ascii());
+        }
+    }
+
+    #[test]
+    fn partial_notation_fills_in_what_it_leaves_out() {
+        let n = parse("kaku x(3,3)-ga").unwrap();
+        let full = Notation::Capture {
+            face: face("Kaku"), from: None, captured: None, to: (3, 3), promote: Some(face("Ga")),
+        };
+        assert_eq!(n, full);
+        assert!(matches!(parse("Kaku(2,2)"), Err(NotationError::Syntax(..))));
+        assert!(matches!(parse("Neko*(0,0)"), Err(NotationError::UnknownFace(w)) if w == "Neko"));
+    }
+
+    /// Black's Kaku at (1,-1) can take the White Hi at (3,1), promoting to
+    /// Ga or Ken.
+    fn kaku_takes_hi() -> GameState {
+        GameState::from_position_string("bGyoku0,0;bKaku1,-1;wGyoku2,2;wHi3,1 -/- b basic").unwrap()
+    }
+
+    #[test]
+    fn resolve_reports_ambiguous_and_illegal_plies() {
+        let mut state = kaku_takes_hi();
+        match parse_ply(&mut state, "Kaku x(3,1)") {
+            Err(NotationError::Ambiguous(plies)) => assert_eq!(plies.len(), 2),
+            other => panic!("expected two promotions, got {:?}", other),
+        }
+        let ply = parse_ply(&mut state, "角(1,-1)×飛(3,1)→犬").unwrap();
+        assert!(matches!(ply, Ply::Action(Action::Move { promote_to: Some(f), .. }) if f == face("Ken")));
+
+        assert!(matches!(parse_ply(&mut state, "Kaku-(1,0)"), Err(NotationError::Illegal)));
+        assert!(matches!(parse_ply(&mut state, "Kaku x(3,1)-Hon"), Err(NotationError::Illegal)));
+        assert!(matches!(parse_ply(&mut state, "Gyoku*(5,5)"), Err(NotationError::WrongPhase)));
+        assert!(matches!(parse_ply(&mut state, "accept-draw"), Err(NotationError::WrongPhase)));
+    }
+}
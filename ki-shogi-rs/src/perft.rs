@@ -0,0 +1,202 @@
+// Ki Shogi - Move generation counter (perft)
+
+use std::collections::HashMap;
+use std::fmt;
+use crate::types::{Cube, Face};
+use crate::state::{Action, GameState};
+use crate::rules::{make, unmake};
+use crate::ai::get_all_actions;
+
+// ---------------------------------------------------------------------------
+// Counts
+// ---------------------------------------------------------------------------
+
+/// Leaf counts of a perft search, broken down by the last action played.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PerftCounts {
+    pub nodes: u64,
+    /// Non-capturing moves.
+    pub moves: u64,
+    /// Captures, by the face the capturer promotes to; `None` for captures
+    /// by or of a Gyoku, which never promote.
+    pub captures: HashMap<Option<Face>, u64>,
+    /// Drops, by the face dropped.
+    pub drops: HashMap<Face, u64>,
+}
+
+impl PerftCounts {
+    fn leaf(action: &Action) -> Self {
+        let mut c = Self { nodes: 1, ..Self::default() };
+        match action {
+            Action::Move { mv, promote_to, .. } => {
+                if mv.capture.is_some() {
+                    c.captures.insert(*promote_to, 1);
+                } else {
+                    c.moves = 1;
+                }
+            }
+            Action::Drop { face, .. } => { c.drops.insert(*face, 1); }
+        }
+        c
+    }
+
+    pub fn add(&mut self, other: &PerftCounts) {
+        self.nodes += other.nodes;
+        self.moves += other.moves;
+        for (&k, &n) in &other.captures {
+            *self.captures.entry(k).or_default() += n;
+        }
+        for (&k, &n) in &other.drops {
+            *self.drops.entry(k).or_default() += n;
+        }
+    }
+
+    pub fn capture_total(&self) -> u64 {
+        self.captures.values().sum()
+    }
+
+    pub fn drop_total(&self) -> u64 {
+        self.drops.values().sum()
+    }
+}
+
+/// One line, with faces in `Face::all()` order so output is stable:
+/// `nodes 120  moves 80  captures 12 [- 2, Ga 10]  drops 28 [Hi 14, Cho 14]`
+impl fmt::Display for PerftCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "nodes {}  moves {}  captures {}", self.nodes, self.moves, self.capture_total())?;
+        let mut caps: Vec<String> = Vec::new();
+        if let Some(n) = self.captures.get(&None) {
+            caps.push(format!("- {}", n));
+        }
+        for face in Face::all() {
+            if let Some(n) = self.captures.get(&Some(face)) {
+                caps.push(format!("{} {}", face.name(), n));
+            }
+        }
+        if !caps.is_empty() {
+            write!(f, " [{}]", caps.join(", "))?;
+        }
+        write!(f, "  drops {}", self.drop_total())?;
+        let drops: Vec<String> = Face::all()
+            .filter_map(|face| self.drops.get(&face).map(|n| format!("{} {}", face.name(), n)))
+            .collect();
+        if !drops.is_empty() {
+            write!(f, " [{}]", drops.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Search
+// ---------------------------------------------------------------------------
+//
+// Every legal action of the side to move is played with `make` down to
+// `depth` plies, one action per promotion choice as `get_all_actions` lists
+// them. A Gyoku capture ends the game, so it counts as a leaf only at the
+// last ply and is not searched past. Repetition is not tracked: perft pins
+// move generation, not game-end rules.
+
+/// Count the leaves `depth` plies below the position.
+pub fn perft(state: &mut GameState, depth: u32) -> PerftCounts {
+    let mut total = PerftCounts::default();
+    if depth == 0 {
+        total.nodes = 1;
+        return total;
+    }
+    for (_, counts) in perft_divide(state, depth) {
+        total.add(&counts);
+    }
+    total
+}
+
+/// `perft` split by the first action, in move-generation order.
+pub fn perft_divide(state: &mut GameState, depth: u32) -> Vec<(Action, PerftCounts)> {
+    if depth == 0 { return Vec::new(); }
+    let turn = state.turn;
+    get_all_actions(state, turn).into_iter()
+        .map(|action| {
+            let counts = if depth == 1 {
+                PerftCounts::leaf(&action)
+            } else if takes_gyoku(state, &action) {
+                PerftCounts::default()
+            } else {
+                let undo = make(state, &action);
+                let counts = perft(state, depth - 1);
+                unmake(state, undo);
+                counts
+            };
+            (action, counts)
+        })
+        .collect()
+}
+
+fn takes_gyoku(state: &GameState, action: &Action) -> bool {
+    match action {
+        Action::Move { mv, .. } => mv.capture.is_some_and(|ci| state.pieces[ci].cube == Cube::Gyoku),
+        Action::Drop { .. } => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// (nodes, moves, captures, drops) at depths 1, 2 and 3.
+    fn assert_counts(position: &str, want: [(u64, u64, u64, u64); 3]) {
+        let mut state = GameState::from_position_string(position).unwrap();
+        for (depth, &want) in (1..=3).zip(want.iter()) {
+            let c = perft(&mut state, depth);
+            assert_eq!((c.nodes, c.moves, c.capture_total(), c.drop_total()), want, "depth {}", depth);
+        }
+    }
+
+    #[test]
+    fn basic_set_with_hands() {
+        assert_counts("bGyoku0,0;bHi0,1;wGyoku2,2;wKaku2,3 Kaku/Hi w basic", [
+            (41, 3, 2, 36),
+            (1627, 175, 14, 1438),
+            (16843, 7031, 2577, 7235),
+        ]);
+    }
+
+    #[test]
+    fn basic_set_from_the_board() {
+        assert_counts("bGyoku0,0;bHi0,1;bKaku1,0;wGyoku2,2;wKaku2,3;wHi3,2 -/- b basic", [
+            (8, 6, 2, 0),
+            (41, 28, 13, 0),
+            (712, 201, 55, 456),
+        ]);
+    }
+
+    #[test]
+    fn ki_set() {
+        assert_counts("bGyoku0,0;bKi0,1;wGyoku0,2;wHi2,3 Kaku/- b ki", [
+            (40, 10, 0, 30),
+            (136, 132, 4, 0),
+            (2887, 1217, 80, 1590),
+        ]);
+    }
+
+    #[test]
+    fn counts_split_by_face() {
+        let mut state = GameState::from_position_string("bGyoku0,0;bKi0,1;wGyoku0,2;wHi2,3 Kaku/- b ki").unwrap();
+        let c = perft(&mut state, 3);
+        assert_eq!(c.captures.get(&None), Some(&2));
+        assert_eq!(c.captures.get(&Face::from_name("Kaku")), Some(&4));
+        assert_eq!(c.drops.get(&Face::from_name("Myou").unwrap()), Some(&231));
+        assert_eq!(c.to_string().lines().count(), 1);
+    }
+
+    #[test]
+    fn divide_adds_up_to_the_total() {
+        let position = "bGyoku0,0;bHi0,1;bKaku1,0;wGyoku2,2;wKaku2,3;wHi3,2 -/- b basic";
+        let mut state = GameState::from_position_string(position).unwrap();
+        let mut sum = PerftCounts::default();
+        for (_, c) in perft_divide(&mut state, 3) {
+            sum.add(&c);
+        }
+        assert_eq!(sum.to_string(), perft(&mut state, 3).to_string());
+    }
+}
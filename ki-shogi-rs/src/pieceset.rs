@@ -0,0 +1,449 @@
+// Ki Shogi - Piece set definitions
+
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::OnceLock;
+use crate::types::{Cube, DirCategory, Face, MoveMode};
+
+// ---------------------------------------------------------------------------
+// Piece set files
+// ---------------------------------------------------------------------------
+//
+// A piece set names the faces of the four cubes, one `face` line each under
+// a `cube` line; see pieces/standard.pieces for the format and the built-in
+// set. The cubes themselves are fixed: the Gyoku is royal and the Ki cube
+// has its own drop and support ranges, so a new die is tried out by giving
+// one of the four cubes new faces. Faces are numbered cube by cube in the
+// order Gyoku, Hi, Kaku, Ki, whatever order the file lists the cubes in.
+//
+// One set is active per process. It is the built-in set unless another is
+// installed before the first face is looked at; records and position
+// strings name faces, so they only read back under the set they were
+// written with. Records name the set by its fingerprint, a hash of the set
+// written back out in this format.
+
+/// The built-in set.
+pub const STANDARD: &str = include_str!("../pieces/standard.pieces");
+
+/// One face of a cube.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FaceDef {
+    /// ASCII transliteration of the kanji, used in typed notation.
+    pub name: String,
+    pub kanji: String,
+    pub english: String,
+    pub cube: Cube,
+    /// The face a flip turns it to; `None` only for the Gyoku.
+    pub opposite: Option<Face>,
+    /// Promotion choices when it captures.
+    pub promotions: Vec<Face>,
+    /// Heuristic value for AI evaluation.
+    pub value: i32,
+    /// Movement components in order.
+    pub moves: Vec<(MoveMode, Vec<DirCategory>)>,
+}
+
+/// A validated set of faces, indexed by `Face`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PieceSet {
+    faces: Vec<FaceDef>,
+    /// Faces per cube, in `Cube` order.
+    cube_faces: [Vec<Face>; 4],
+}
+
+#[derive(Debug)]
+pub enum PieceSetError {
+    Io(io::Error),
+    /// A line that cannot be read (1-based line number).
+    Syntax(usize, String),
+    /// A face line before the first cube line.
+    NoCube(usize),
+    UnknownFace(String),
+    UnknownDirection(String),
+    /// A name or kanji used by two faces.
+    Duplicate(String),
+    /// A name that is not ASCII letters.
+    BadName(String),
+    /// One name is a prefix of another, so typed notation is ambiguous.
+    NamePrefix(String, String),
+    /// A kanji that is not a single non-ASCII character.
+    BadKanji(String),
+    /// A cube with no faces, or a Gyoku cube with more than one.
+    FaceCount(Cube, usize),
+    TooManyFaces(usize),
+    NoMoves(String),
+    /// A jump direction on a slide.
+    JumpSlide(String),
+    /// The Gyoku face with an opposite or promotions.
+    GyokuChanges(String),
+    MissingOpposite(String),
+    /// The face's opposite does not have it as its opposite.
+    OppositeMismatch(String),
+    /// A face and a face it turns to on different cubes.
+    OffCube(String, String),
+    /// The active set is already in use.
+    AlreadyActive,
+}
+
+impl fmt::Display for PieceSetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PieceSetError::Io(e) => write!(f, "{}", e),
+            PieceSetError::Syntax(line, s) => write!(f, "line {}: cannot read '{}'", line, s),
+            PieceSetError::NoCube(line) => write!(f, "line {}: face before any cube", line),
+            PieceSetError::UnknownFace(s) => write!(f, "unknown face '{}'", s),
+            PieceSetError::UnknownDirection(s) => write!(f, "unknown direction '{}'", s),
+            PieceSetError::Duplicate(s) => write!(f, "'{}' names two faces", s),
+            PieceSetError::BadName(s) => write!(f, "face name '{}' is not ASCII letters", s),
+            PieceSetError::NamePrefix(a, b) => write!(f, "face name '{}' is a prefix of '{}'", a, b),
+            PieceSetError::BadKanji(s) => write!(f, "'{}' is not a single kanji", s),
+            PieceSetError::FaceCount(cube, n) => write!(f, "cube {:?} has {} faces", cube, n),
+            PieceSetError::TooManyFaces(n) => write!(f, "{} faces, at most 256 are allowed", n),
+            PieceSetError::NoMoves(s) => write!(f, "face {} has no movement", s),
+            PieceSetError::JumpSlide(s) => write!(f, "face {} slides along a jump", s),
+            PieceSetError::GyokuChanges(s) => write!(f, "face {} is a Gyoku and cannot flip or promote", s),
+            PieceSetError::MissingOpposite(s) => write!(f, "face {} has no opposite", s),
+            PieceSetError::OppositeMismatch(s) => write!(f, "the opposite of {} is not opposite to it", s),
+            PieceSetError::OffCube(a, b) => write!(f, "face {} turns to {} on another cube", a, b),
+            PieceSetError::AlreadyActive => f.write_str("a piece set is already in use"),
+        }
+    }
+}
+
+impl std::error::Error for PieceSetError {}
+
+impl From<io::Error> for PieceSetError {
+    fn from(e: io::Error) -> Self {
+        PieceSetError::Io(e)
+    }
+}
+
+fn parse_cube(s: &str) -> Option<Cube> {
+    match s {
+        "Gyoku" => Some(Cube::Gyoku),
+        "Hi" => Some(Cube::Hi),
+        "Kaku" => Some(Cube::Kaku),
+        "Ki" => Some(Cube::Ki),
+        _ => None,
+    }
+}
+
+/// A face line with its references still as names.
+struct RawFace {
+    def: FaceDef,
+    opposite: Option<String>,
+    promotions: Vec<String>,
+}
+
+fn parse_face_line(cube: Cube, line_no: usize, line: &str) -> Result<RawFace, PieceSetError> {
+    let syntax = || PieceSetError::Syntax(line_no, line.to_string());
+    let mut words = line.split_whitespace().skip(1);
+    let mut word = || words.next().map(str::to_string).ok_or_else(syntax);
+    let (name, kanji, english) = (word()?, word()?, word()?);
+    let mut raw = RawFace {
+        def: FaceDef {
+            name, kanji, english, cube,
+            opposite: None,
+            promotions: Vec::new(),
+            value: 0,
+            moves: Vec::new(),
+        },
+        opposite: None,
+        promotions: Vec::new(),
+    };
+    let mut value = None;
+    for item in line.split_whitespace().skip(4) {
+        let (key, v) = item.split_once('=').ok_or_else(syntax)?;
+        let list = || v.split(',').map(str::to_string).collect::<Vec<_>>();
+        match key {
+            "value" if value.is_none() => value = Some(v.parse().map_err(|_| syntax())?),
+            "opposite" if raw.opposite.is_none() => raw.opposite = Some(v.to_string()),
+            "promote" if raw.promotions.is_empty() => raw.promotions = list(),
+            "step" | "slide" => {
+                let mode = if key == "step" { MoveMode::Step } else { MoveMode::Slide };
+                let dirs = list().iter()
+                    .map(|d| DirCategory::from_name(d).ok_or_else(|| PieceSetError::UnknownDirection(d.clone())))
+                    .collect::<Result<Vec<_>, _>>()?;
+                raw.def.moves.push((mode, dirs));
+            }
+            _ => return Err(syntax()),
+        }
+    }
+    raw.def.value = value.ok_or_else(syntax)?;
+    Ok(raw)
+}
+
+impl PieceSet {
+    /// The built-in set.
+    pub fn standard() -> Self {
+        Self::parse(STANDARD).expect("the built-in piece set is valid")
+    }
+
+    /// Parse a piece set file and validate it.
+    pub fn parse(text: &str) -> Result<Self, PieceSetError> {
+        let mut raws = Vec::new();
+        let mut cube = None;
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') { continue; }
+            match line.split_whitespace().next() {
+                Some("cube") => {
+                    let mut words = line.split_whitespace().skip(1);
+                    cube = match (words.next().and_then(parse_cube), words.next()) {
+                        (Some(c), None) => Some(c),
+                        _ => return Err(PieceSetError::Syntax(i + 1, line.to_string())),
+                    };
+                }
+                Some("face") => {
+                    let cube = cube.ok_or(PieceSetError::NoCube(i + 1))?;
+                    raws.push(parse_face_line(cube, i + 1, line)?);
+                }
+                _ => return Err(PieceSetError::Syntax(i + 1, line.to_string())),
+            }
+        }
+        if raws.len() > 256 {
+            return Err(PieceSetError::TooManyFaces(raws.len()));
+        }
+        raws.sort_by_key(|r| r.def.cube as usize);
+
+        let find = |name: &str| {
+            raws.iter().position(|r| r.def.name == name)
+                .map(Face::from_index)
+                .ok_or_else(|| PieceSetError::UnknownFace(name.to_string()))
+        };
+        let mut faces = Vec::new();
+        for raw in &raws {
+            let mut def = raw.def.clone();
+            def.opposite = raw.opposite.as_deref().map(find).transpose()?;
+            def.promotions = raw.promotions.iter().map(|n| find(n)).collect::<Result<_, _>>()?;
+            faces.push(def);
+        }
+        let mut cube_faces: [Vec<Face>; 4] = Default::default();
+        for (i, def) in faces.iter().enumerate() {
+            cube_faces[def.cube as usize].push(Face::from_index(i));
+        }
+        let set = PieceSet { faces, cube_faces };
+        set.validate()?;
+        Ok(set)
+    }
+
+    fn validate(&self) -> Result<(), PieceSetError> {
+        for cube in [Cube::Gyoku, Cube::Hi, Cube::Kaku, Cube::Ki] {
+            let n = self.cube_faces(cube).len();
+            if n == 0 || (cube == Cube::Gyoku && n > 1) {
+                return Err(PieceSetError::FaceCount(cube, n));
+            }
+        }
+        for (i, def) in self.faces.iter().enumerate() {
+            if def.name.is_empty() || !def.name.chars().all(|c| c.is_ascii_alphabetic()) {
+                return Err(PieceSetError::BadName(def.name.clone()));
+            }
+            let mut kanji = def.kanji.chars();
+            if !matches!((kanji.next(), kanji.next()), (Some(c), None) if !c.is_ascii()) {
+                return Err(PieceSetError::BadKanji(def.kanji.clone()));
+            }
+            for other in &self.faces[i + 1..] {
+                if other.kanji == def.kanji {
+                    return Err(PieceSetError::Duplicate(def.kanji.clone()));
+                }
+                let (a, b) = (def.name.to_ascii_lowercase(), other.name.to_ascii_lowercase());
+                if a == b {
+                    return Err(PieceSetError::Duplicate(def.name.clone()));
+                }
+                if b.starts_with(&a) {
+                    return Err(PieceSetError::NamePrefix(def.name.clone(), other.name.clone()));
+                }
+                if a.starts_with(&b) {
+                    return Err(PieceSetError::NamePrefix(other.name.clone(), def.name.clone()));
+                }
+            }
+
+            if def.moves.iter().all(|(_, dirs)| dirs.is_empty()) {
+                return Err(PieceSetError::NoMoves(def.name.clone()));
+            }
+            // Slide ranges are bounded by walking one cell at a time
+            if def.moves.iter().any(|(mode, dirs)| *mode == MoveMode::Slide && dirs.iter().any(|d| !d.is_single())) {
+                return Err(PieceSetError::JumpSlide(def.name.clone()));
+            }
+
+            let face = Face::from_index(i);
+            if def.cube == Cube::Gyoku {
+                if def.opposite.is_some() || !def.promotions.is_empty() {
+                    return Err(PieceSetError::GyokuChanges(def.name.clone()));
+                }
+                continue;
+            }
+            let opp = def.opposite.ok_or_else(|| PieceSetError::MissingOpposite(def.name.clone()))?;
+            for &to in def.promotions.iter().chain([&opp]) {
+                if self.face(to).cube != def.cube {
+                    return Err(PieceSetError::OffCube(def.name.clone(), self.face(to).name.clone()));
+                }
+            }
+            if opp == face || self.face(opp).opposite != Some(face) {
+                return Err(PieceSetError::OppositeMismatch(def.name.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn face(&self, face: Face) -> &FaceDef {
+        &self.faces[face.index()]
+    }
+
+    /// Number of faces.
+    pub fn len(&self) -> usize {
+        self.faces.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.faces.is_empty()
+    }
+
+    /// The faces of a cube; the first is the one it is dropped with.
+    pub fn cube_faces(&self, cube: Cube) -> &[Face] {
+        &self.cube_faces[cube as usize]
+    }
+
+    /// FNV-1a hash of the set as written by `Display`, so it ignores
+    /// comments, spacing and the order of the cubes in the file.
+    pub fn fingerprint(&self) -> u64 {
+        self.to_string().bytes().fold(0xCBF2_9CE4_8422_2325, |h, b| {
+            (h ^ b as u64).wrapping_mul(0x0100_0000_01B3)
+        })
+    }
+}
+
+/// Writes the set back out in the file format, one line per cube and face.
+impl fmt::Display for PieceSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = |faces: &[Face]| {
+            faces.iter().map(|&to| self.face(to).name.as_str()).collect::<Vec<_>>().join(",")
+        };
+        for cube in [Cube::Gyoku, Cube::Hi, Cube::Kaku, Cube::Ki] {
+            writeln!(f, "cube {:?}", cube)?;
+            for &face in self.cube_faces(cube) {
+                let def = self.face(face);
+                write!(f, "face {} {} {} value={}", def.name, def.kanji, def.english, def.value)?;
+                if let Some(opp) = def.opposite {
+                    write!(f, " opposite={}", self.face(opp).name)?;
+                }
+                if !def.promotions.is_empty() {
+                    write!(f, " promote={}", names(&def.promotions))?;
+                }
+                for (mode, dirs) in &def.moves {
+                    let key = if *mode == MoveMode::Step { "step" } else { "slide" };
+                    let dirs = dirs.iter().map(|d| format!("{:?}", d)).collect::<Vec<_>>();
+                    write!(f, " {}={}", key, dirs.join(","))?;
+                }
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Active set
+// ---------------------------------------------------------------------------
+
+static ACTIVE: OnceLock<PieceSet> = OnceLock::new();
+
+/// The set every `Face` refers to.
+pub fn active() -> &'static PieceSet {
+    ACTIVE.get_or_init(PieceSet::standard)
+}
+
+/// Make `set` the active set. Fails once any face has been looked at, since
+/// faces already handed out would change meaning.
+pub fn install(set: PieceSet) -> Result<(), PieceSetError> {
+    ACTIVE.set(set).map_err(|_| PieceSetError::AlreadyActive)
+}
+
+pub fn load_piece_set(path: impl AsRef<Path>) -> Result<PieceSet, PieceSetError> {
+    let text = std::fs::read_to_string(path)?;
+    PieceSet::parse(&text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Name, kanji, value, opposite and promotions of one face.
+    type FaceRow = (&'static str, &'static str, i32, &'static str, &'static [&'static str]);
+
+    /// The standard set with `from` replaced by `to`, which must occur once.
+    fn edited(from: &str, to: &str) -> String {
+        assert_eq!(STANDARD.matches(from).count(), 1, "{}", from);
+        STANDARD.replacen(from, to, 1)
+    }
+
+    #[test]
+    fn standard_file_matches_the_built_in_faces() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/pieces/standard.pieces");
+        let set = load_piece_set(path).unwrap();
+        assert_eq!(set, PieceSet::standard());
+
+        let expected: [(Cube, &[FaceRow]); 4] = [
+            (Cube::Gyoku, &[("Gyoku", "玉", 0, "", &[])]),
+            (Cube::Hi, &[
+                ("Hi", "飛", 80, "Cho", &["Chuu"]),
+                ("Cho", "猪", 30, "Hi", &["Ou", "Shu"]),
+                ("Han", "反", 50, "Chuu", &["Shu"]),
+                ("Chuu", "仲", 15, "Han", &["Cho", "Han", "Ou"]),
+                ("Ou", "横", 55, "Shu", &["Hi"]),
+                ("Shu", "竪", 55, "Ou", &["Hi"]),
+            ]),
+            (Cube::Kaku, &[
+                ("Kaku", "角", 80, "Myou", &["Ga", "Ken"]),
+                ("Myou", "猫", 30, "Kaku", &["Kaku"]),
+                ("Hon", "奔", 60, "Ga", &["Kaku"]),
+                ("Ga", "瓦", 20, "Hon", &["Hon", "Myou"]),
+                ("Zou", "雑", 60, "Ken", &["Kaku"]),
+                ("Ken", "犬", 20, "Zou", &["Zou", "Myou"]),
+            ]),
+            (Cube::Ki, &[
+                ("Ki", "麒", 45, "Hou", &["Ryuu", "Ba"]),
+                ("Hou", "鳳", 45, "Ki", &["Ryuu", "Ba"]),
+                ("Ro", "驢", 25, "Ja", &["Ki", "Hou"]),
+                ("Ja", "蛇", 25, "Ro", &["Ki", "Hou"]),
+                ("Ba", "馬", 20, "Ryuu", &["Ja", "Ro"]),
+                ("Ryuu", "龍", 20, "Ba", &["Ja", "Ro"]),
+            ]),
+        ];
+        let name = |face: Face| set.face(face).name.as_str();
+        for (cube, faces) in expected {
+            assert_eq!(set.cube_faces(cube).len(), faces.len(), "{:?}", cube);
+            for (&face, &(n, kanji, value, opposite, promotions)) in set.cube_faces(cube).iter().zip(faces) {
+                let def = set.face(face);
+                assert_eq!((def.name.as_str(), def.kanji.as_str(), def.value, def.cube), (n, kanji, value, cube));
+                assert_eq!(def.opposite.map_or("", name), opposite, "{}", n);
+                assert_eq!(def.promotions.iter().map(|&f| name(f)).collect::<Vec<_>>(), promotions, "{}", n);
+            }
+        }
+        assert_eq!(set.len(), 19);
+    }
+
+    #[test]
+    fn display_parses_back_to_the_same_set() {
+        let set = PieceSet::standard();
+        assert_eq!(PieceSet::parse(&set.to_string()).unwrap(), set);
+
+        let renamed = PieceSet::parse(&edited("Horse", "Pony")).unwrap();
+        assert_ne!(renamed.fingerprint(), set.fingerprint());
+        let commented = PieceSet::parse(&format!("# another comment\n{}", STANDARD)).unwrap();
+        assert_eq!(commented.fingerprint(), set.fingerprint());
+    }
+
+    #[test]
+    fn bad_sets_are_refused() {
+        let parse = |from, to| PieceSet::parse(&edited(from, to));
+
+        assert!(matches!(parse("step=D,JO", "step=D,JX"), Err(PieceSetError::UnknownDirection(d)) if d == "JX"));
+        assert!(matches!(parse("slide=D\n", "slide=JD\n"), Err(PieceSetError::JumpSlide(n)) if n == "Kaku"));
+        assert!(matches!(parse("opposite=Cho  ", ""), Err(PieceSetError::MissingOpposite(n)) if n == "Hi"));
+        let duplicate = "face Hi    飛 Flying   value=80 opposite=Cho  promote=Chuu        slide=O\n";
+        let extra = format!("{}face Hi 乙 Second value=1 opposite=Cho step=O\n", duplicate);
+        assert!(matches!(parse(duplicate, &extra), Err(PieceSetError::Duplicate(n)) if n == "Hi"));
+    }
+}
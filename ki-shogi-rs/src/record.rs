@@ -0,0 +1,486 @@
+// Ki Shogi - Game records (save / load)
+
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::types::Owner;
+use crate::state::{write_violations, EndReason, GameResult, GameState, PositionError, Violation};
+use crate::game::Game;
+use crate::session::{AiSide, Session};
+use crate::ruleset::RuleSet;
+use crate::pieceset::{self, PieceSet};
+use crate::notation::{parse_ply, Ply};
+
+// ---------------------------------------------------------------------------
+// Format
+// ---------------------------------------------------------------------------
+//
+// A record is a block of header tags followed by one line per logged ply:
+//
+//     [Date "2026-10-18"]
+//     [Black "Human"]
+//     [White "AI 3"]
+//     [Set "basic"]
+//     [Seed "8120391247752381"]
+//     [Result "White NoLegalAction"]
+//
+//     1. 玉↓(0,0)
+//     2. 玉↓(2,2)
+//     3. 飛(0,1)→(0,3)=猪
+//     4. 角(2,2)×猪(0,3)→瓦
+//
+// Tags always appear in this order. Games played with a piece set other
+// than the built-in one add its fingerprint (`PieceSet::fingerprint`, in
+// hex) to `Set`, as in `[Set "basic 9f3c0a61d2e4b857"]`, and load only while
+// that set is active. Games under house rules have a
+// `[Rules "..."]` tag after `Set` with the parameters that differ from the
+// standard rules (see `RuleSet::overrides`). A game that started from a
+// position string has a `[Position "..."]` tag after that and no Gyoku
+// setup lines. `Seed` is the game's `Session::seed`; replaying restores
+// it, and playing again from it reproduces the AI's choices.
+// Players are `Human` or `AI <level>`. Result is `*` while the game is in
+// progress, `<Black|White> <reason>` for a win and `Draw <reason>` for a
+// draw, with the reason spelled as in `EndReason`.
+//
+// Ply lines hold exactly the text of the move log (see `notation`), so a
+// record is replayed by resolving each line to its legal action and checking
+// that playing it logs the same text again. Resignations and draw offers
+// are lines of their own (投了, 提和, 受和, 拒和), so a game ended by
+// resignation or agreement replays to its result.
+
+/// Who played one side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Player {
+    Human,
+    /// An AI at the given difficulty (1-5).
+    Ai(u8),
+}
+
+impl fmt::Display for Player {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Player::Human => f.write_str("Human"),
+            Player::Ai(level) => write!(f, "AI {}", level),
+        }
+    }
+}
+
+/// A whole game: header information and the text of every logged ply.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GameRecord {
+    /// YYYY-MM-DD
+    pub date: String,
+    pub black: Player,
+    pub white: Player,
+    /// Rule parameters, including the piece set (`Set` and `Rules` tags).
+    pub rules: RuleSet,
+    /// Fingerprint of the piece set the game was played with.
+    pub pieces: u64,
+    /// Position string the game started from, if not from Gyoku setup.
+    pub start_position: Option<String>,
+    /// Seed of the game's random choices; absent in older records.
+    pub seed: Option<u64>,
+    pub result: Option<GameResult>,
+    /// Move log texts in order, Gyoku placements included.
+    pub moves: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum RecordError {
+    Io(io::Error),
+    /// A line that is neither a tag nor a ply (1-based line number).
+    Syntax(usize, String),
+    MissingTag(&'static str),
+    /// A known tag with a value that cannot be parsed.
+    BadTag(String, String),
+    UnknownTag(String),
+    BadPosition(PositionError),
+    /// Ply number (1-based) and its text, which is malformed, illegal, or
+    /// does not reproduce the same log text when played.
+    BadMove(usize, String),
+    /// The Result tag disagrees with how the replayed game ended.
+    ResultMismatch,
+    /// Ply number (1-based) after which the game fails validation.
+    Inconsistent(usize, Vec<Violation>),
+    /// The record was played with another piece set than the active one
+    /// (recorded and active fingerprints).
+    OtherPieceSet(u64, u64),
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordError::Io(e) => write!(f, "{}", e),
+            RecordError::Syntax(line, s) => write!(f, "line {}: cannot read '{}'", line, s),
+            RecordError::MissingTag(tag) => write!(f, "missing tag {}", tag),
+            RecordError::BadTag(tag, v) => write!(f, "bad value '{}' for tag {}", v, tag),
+            RecordError::UnknownTag(tag) => write!(f, "unknown tag {}", tag),
+            RecordError::BadPosition(e) => write!(f, "bad start position: {}", e),
+            RecordError::BadMove(num, s) => write!(f, "ply {}: cannot play '{}'", num, s),
+            RecordError::ResultMismatch => f.write_str("result does not match the moves"),
+            RecordError::Inconsistent(num, found) => {
+                write!(f, "ply {}: inconsistent game: ", num)?;
+                write_violations(f, found)
+            }
+            RecordError::OtherPieceSet(recorded, active) => write!(
+                f, "played with piece set {:016x}, but {:016x} is in use (see --pieces)", recorded, active,
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RecordError {}
+
+impl From<io::Error> for RecordError {
+    fn from(e: io::Error) -> Self {
+        RecordError::Io(e)
+    }
+}
+
+fn reason_name(reason: EndReason) -> &'static str {
+    match reason {
+        EndReason::GyokuCaptured => "GyokuCaptured",
+        EndReason::NoLegalAction => "NoLegalAction",
+        EndReason::PerpetualCheck => "PerpetualCheck",
+        EndReason::Sennichite => "Sennichite",
+        EndReason::DeadPosition => "DeadPosition",
+        EndReason::PlyLimit => "PlyLimit",
+        EndReason::Resignation => "Resignation",
+        EndReason::Timeout => "Timeout",
+        EndReason::Agreement => "Agreement",
+    }
+}
+
+fn reason_from_name(s: &str) -> Option<EndReason> {
+    [
+        EndReason::GyokuCaptured, EndReason::NoLegalAction, EndReason::PerpetualCheck,
+        EndReason::Sennichite, EndReason::DeadPosition, EndReason::PlyLimit,
+        EndReason::Resignation, EndReason::Timeout, EndReason::Agreement,
+    ].into_iter().find(|&r| reason_name(r) == s)
+}
+
+fn format_result(result: Option<GameResult>) -> String {
+    match result {
+        None => "*".to_string(),
+        Some(GameResult::Win { winner: Owner::Black, reason }) => format!("Black {}", reason_name(reason)),
+        Some(GameResult::Win { winner: Owner::White, reason }) => format!("White {}", reason_name(reason)),
+        Some(GameResult::Draw { reason }) => format!("Draw {}", reason_name(reason)),
+    }
+}
+
+fn parse_result(s: &str) -> Option<Option<GameResult>> {
+    if s == "*" { return Some(None); }
+    let (who, reason) = s.split_once(' ')?;
+    let reason = reason_from_name(reason)?;
+    let result = match who {
+        "Black" => GameResult::Win { winner: Owner::Black, reason },
+        "White" => GameResult::Win { winner: Owner::White, reason },
+        "Draw" => GameResult::Draw { reason },
+        _ => return None,
+    };
+    Some(Some(result))
+}
+
+fn parse_player(s: &str) -> Option<Player> {
+    if s == "Human" { return Some(Player::Human); }
+    let level: u8 = s.strip_prefix("AI ")?.parse().ok()?;
+    (1..=5).contains(&level).then_some(Player::Ai(level))
+}
+
+impl fmt::Display for GameRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "[Date \"{}\"]", self.date)?;
+        writeln!(f, "[Black \"{}\"]", self.black)?;
+        writeln!(f, "[White \"{}\"]", self.white)?;
+        write!(f, "[Set \"{}", self.rules.preset_name())?;
+        if self.pieces != PieceSet::standard().fingerprint() {
+            write!(f, " {:016x}", self.pieces)?;
+        }
+        writeln!(f, "\"]")?;
+        let overrides = self.rules.overrides();
+        if !overrides.is_empty() {
+            writeln!(f, "[Rules \"{}\"]", overrides)?;
+        }
+        if let Some(pos) = &self.start_position {
+            writeln!(f, "[Position \"{}\"]", pos)?;
+        }
+        if let Some(seed) = self.seed {
+            writeln!(f, "[Seed \"{}\"]", seed)?;
+        }
+        writeln!(f, "[Result \"{}\"]", format_result(self.result))?;
+        writeln!(f)?;
+        for (i, text) in self.moves.iter().enumerate() {
+            writeln!(f, "{}. {}", i + 1, text)?;
+        }
+        Ok(())
+    }
+}
+
+impl GameRecord {
+    /// Record the game so far as played by `session`, dated `date` (see
+    /// `today`).
+    pub fn from_game(game: &Game, session: &Session, date: &str) -> Self {
+        let player = |owner: Owner| {
+            if session.ai_side.is_ai(owner) { Player::Ai(session.ai_difficulty) } else { Player::Human }
+        };
+        Self {
+            date: date.to_string(),
+            black: player(Owner::Black),
+            white: player(Owner::White),
+            rules: game.state.rules,
+            pieces: pieceset::active().fingerprint(),
+            start_position: session.start_position.clone(),
+            seed: Some(session.seed),
+            result: game.result(),
+            moves: game.move_log().iter().map(|e| e.kanji()).collect(),
+        }
+    }
+
+    /// Parse a record written by `to_string`.
+    pub fn parse(s: &str) -> Result<Self, RecordError> {
+        let mut date = None;
+        let mut black = None;
+        let mut white = None;
+        let mut use_ki = None;
+        let mut pieces = None;
+        let mut overrides = None;
+        let mut start_position = None;
+        let mut seed = None;
+        let mut result = None;
+        let mut moves = Vec::new();
+
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            if line.is_empty() { continue; }
+            let syntax = || RecordError::Syntax(i + 1, line.to_string());
+
+            if let Some(tag) = line.strip_prefix('[') {
+                let (name, value) = tag.strip_suffix("\"]")
+                    .and_then(|t| t.split_once(" \""))
+                    .ok_or_else(syntax)?;
+                let bad = || RecordError::BadTag(name.to_string(), value.to_string());
+                match name {
+                    "Date" => date = Some(value.to_string()),
+                    "Black" => black = Some(parse_player(value).ok_or_else(bad)?),
+                    "White" => white = Some(parse_player(value).ok_or_else(bad)?),
+                    "Set" => {
+                        let (preset, fingerprint) = match value.split_once(' ') {
+                            Some((preset, hex)) => (preset, Some(u64::from_str_radix(hex, 16).map_err(|_| bad())?)),
+                            None => (value, None),
+                        };
+                        use_ki = Some(match preset {
+                            "basic" => false,
+                            "ki" => true,
+                            _ => return Err(bad()),
+                        });
+                        pieces = fingerprint;
+                    }
+                    "Rules" => overrides = Some(value.to_string()),
+                    "Position" => start_position = Some(value.to_string()),
+                    "Seed" => seed = Some(value.parse().map_err(|_| bad())?),
+                    "Result" => result = Some(parse_result(value).ok_or_else(bad)?),
+                    _ => return Err(RecordError::UnknownTag(name.to_string())),
+                }
+                continue;
+            }
+
+            let (num, text) = line.split_once(". ").ok_or_else(syntax)?;
+            if num.parse::<usize>().ok() != Some(moves.len() + 1) {
+                return Err(syntax());
+            }
+            moves.push(text.to_string());
+        }
+
+        let mut rules = RuleSet::standard(use_ki.ok_or(RecordError::MissingTag("Set"))?);
+        if let Some(o) = overrides {
+            rules = rules.with_overrides(&o)
+                .and_then(|r| r.validate().map(|_| r))
+                .map_err(|_| RecordError::BadTag("Rules".to_string(), o))?;
+        }
+
+        Ok(Self {
+            date: date.ok_or(RecordError::MissingTag("Date"))?,
+            black: black.ok_or(RecordError::MissingTag("Black"))?,
+            white: white.ok_or(RecordError::MissingTag("White"))?,
+            rules,
+            pieces: pieces.unwrap_or_else(|| PieceSet::standard().fingerprint()),
+            start_position,
+            seed,
+            result: result.ok_or(RecordError::MissingTag("Result"))?,
+            moves,
+        })
+    }
+
+    /// Play the record through a fresh `Game`, returned with a session
+    /// whose AI sides and level are taken from the players. Every ply must
+    /// be legal and log exactly the recorded text, and the result must
+    /// match. The active piece set must be the one the game was played with.
+    pub fn replay(&self) -> Result<(Game, Session), RecordError> {
+        let active = pieceset::active().fingerprint();
+        if self.pieces != active {
+            return Err(RecordError::OtherPieceSet(self.pieces, active));
+        }
+        let mut session = Session::new();
+        session.ai_side = match (self.black, self.white) {
+            (Player::Human, Player::Human) => AiSide::None,
+            (Player::Ai(_), Player::Human) => AiSide::Player(Owner::Black),
+            (Player::Human, Player::Ai(_)) => AiSide::Player(Owner::White),
+            (Player::Ai(_), Player::Ai(_)) => AiSide::Both,
+        };
+        if let (Player::Ai(level), _) | (Player::Human, Player::Ai(level)) = (self.black, self.white) {
+            session.ai_difficulty = level;
+        }
+        session.start_position = self.start_position.clone();
+        if let Some(seed) = self.seed {
+            session.set_seed(seed);
+        }
+        let mut state = GameState::new();
+        state.rules = self.rules;
+        state.init_game(self.rules.use_ki);
+
+        let mut game = Game::from_state(state);
+        if let Some(pos) = &self.start_position {
+            game.load_position(pos).map_err(RecordError::BadPosition)?;
+            if game.state.rules.use_ki != self.rules.use_ki {
+                let set = self.rules.preset_name();
+                return Err(RecordError::BadTag("Set".to_string(), set.to_string()));
+            }
+            if game.state.rules != self.rules {
+                return Err(RecordError::BadTag("Rules".to_string(), self.rules.overrides()));
+            }
+        }
+
+        for (i, text) in self.moves.iter().enumerate() {
+            let bad = || RecordError::BadMove(i + 1, text.clone());
+            let played = match parse_ply(&mut game.state, text) {
+                Ok(Ply::PlaceGyoku(pos)) => game.place_gyoku(pos).is_ok(),
+                Ok(Ply::Action(action)) => game.apply(&action).is_ok(),
+                Ok(Ply::Declare(d)) => game.declare(d).is_ok(),
+                Err(_) => false,
+            };
+            let logged = game.move_log().last().is_some_and(|e| e.kanji() == *text);
+            if !played || !logged {
+                return Err(bad());
+            }
+            game.state.validate().map_err(|found| RecordError::Inconsistent(i + 1, found))?;
+        }
+
+        if game.result() != self.result {
+            return Err(RecordError::ResultMismatch);
+        }
+        Ok((game, session))
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Files
+// ---------------------------------------------------------------------------
+
+pub fn save_record(path: impl AsRef<Path>, record: &GameRecord) -> io::Result<()> {
+    std::fs::write(path, record.to_string())
+}
+
+pub fn load_record(path: impl AsRef<Path>) -> Result<GameRecord, RecordError> {
+    let text = std::fs::read_to_string(path)?;
+    GameRecord::parse(&text)
+}
+
+/// Today's date (UTC) as YYYY-MM-DD.
+pub fn today() -> String {
+    let (y, m, d, ..) = utc_now();
+    format!("{:04}-{:02}-{:02}", y, m, d)
+}
+
+/// A file name for saving a record now: ki-shogi-YYYYMMDD-HHMMSS.kfr
+pub fn record_file_name() -> String {
+    let (y, mo, d, h, mi, s) = utc_now();
+    format!("ki-shogi-{:04}{:02}{:02}-{:02}{:02}{:02}.kfr", y, mo, d, h, mi, s)
+}
+
+/// Current UTC time as (year, month, day, hour, minute, second).
+fn utc_now() -> (i64, u32, u32, u32, u32, u32) {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0) as i64;
+    let (days, rem) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let y = yoe + era * 400 + i64::from(m <= 2);
+    (y, m, d, (rem / 3600) as u32, (rem % 3600 / 60) as u32, (rem % 60) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::get_all_actions;
+    use crate::state::Declaration;
+
+    /// A game between two humans from `start`.
+    fn game_from(start: &str) -> (Game, Session) {
+        let mut session = Session::seeded(1);
+        session.start_position = Some(start.to_string());
+        let mut game = Game::from_state(GameState::new());
+        game.load_position(start).unwrap();
+        (game, session)
+    }
+
+    fn resigned_game() -> GameRecord {
+        let (mut game, session) = game_from("bGyoku0,0;wGyoku2,2 Hi/Hi b basic");
+        game.declare(Declaration::Resign).unwrap();
+        GameRecord::from_game(&game, &session, "2026-01-01")
+    }
+
+    #[test]
+    fn ply_without_its_stranding_annotation_is_refused() {
+        let (mut game, session) = game_from("bGyoku0,0;bHi-1,0;wGyoku2,2 -/- b basic");
+        let stranding = get_all_actions(&mut game.state.clone(), Owner::Black).into_iter().find(|action| {
+            let mut trial = game.clone();
+            trial.apply(action).is_ok() && !trial.move_log()[0].stranded.is_empty()
+        });
+        game.apply(&stranding.expect("a ply that strands the Hi")).unwrap();
+
+        let mut record = GameRecord::from_game(&game, &session, "2026-01-01");
+        assert!(record.replay().is_ok());
+        let bare = record.moves[0].split(' ').next().unwrap().to_string();
+        record.moves[0] = bare.clone();
+        assert!(matches!(record.replay(), Err(RecordError::BadMove(1, text)) if text == bare));
+    }
+
+    #[test]
+    fn resignation_replays_from_its_line() {
+        let record = resigned_game();
+        let (game, _) = record.replay().unwrap();
+        assert_eq!(game.result(), record.result);
+    }
+
+    #[test]
+    fn result_without_its_declaration_is_refused() {
+        let mut record = resigned_game();
+        record.moves.pop();
+        assert!(matches!(record.replay(), Err(RecordError::ResultMismatch)));
+    }
+
+    #[test]
+    fn set_tag_names_the_piece_set() {
+        let record = resigned_game();
+        let text = record.to_string();
+        assert!(text.contains("[Set \"basic\"]\n"));
+        assert_eq!(GameRecord::parse(&text).unwrap(), record);
+
+        let mut other = record.clone();
+        other.pieces = 0x00c0_ffee;
+        let text = other.to_string();
+        assert!(text.contains("[Set \"basic 0000000000c0ffee\"]\n"));
+        let parsed = GameRecord::parse(&text).unwrap();
+        assert_eq!(parsed, other);
+        let active = pieceset::active().fingerprint();
+        assert!(matches!(parsed.replay(), Err(RecordError::OtherPieceSet(0x00c0_ffee, a)) if a == active));
+        assert!(GameRecord::parse(&text.replace("c0ffee", "c0ffeg")).is_err());
+    }
+}
@@ -1,7 +1,10 @@
 // Ki Shogi - ANSI terminal renderer
 
-use crate::lang::{get_tutorial_pages, t, Ansi};
-use crate::state::{GameState, Mode, PieceSnapshot};
+use crate::lang::{get_lang, get_tutorial_pages, t, tn, Ansi, Lang};
+use crate::ruleset::RuleSet;
+use crate::state::{EndReason, GameResult, GameState, LogKind, MoveLogEntry, Piece};
+use crate::game::Game;
+use crate::ui::{Mode, UiState};
 use crate::types::{get_move_components, Cube, Face, MoveMode, Owner};
 use std::collections::HashMap;
 use std::io::{self, Write};
@@ -77,7 +80,7 @@ fn merge_sidebar(lines: &mut Vec<String>, sidebar: &[String], start: usize, grid
 }
 
 /// Merge panel on the LEFT of lines within a range
-fn merge_left_panel(lines: &mut Vec<String>, panel: &[String], start: usize, end: usize) -> usize {
+fn merge_left_panel(lines: &mut [String], panel: &[String], start: usize, end: usize) -> usize {
     if panel.is_empty() {
         return 0;
     }
@@ -102,24 +105,21 @@ struct GridInfo {
 }
 
 fn render_grid(
-    state: &GameState,
+    ui: &UiState,
     lines: &mut Vec<String>,
     x1: i32,
     x2: i32,
     y1: i32,
     y2: i32,
 ) -> (usize, usize) {
-    let board_map = state.build_board_map();
-    let legal_set: std::collections::HashSet<String> = state
+    let state = &ui.game.state;
+    let legal_set: std::collections::HashSet<(i32, i32)> = ui
         .legal_moves
         .iter()
-        .map(|m| format!("{},{}", m.x, m.y))
-        .collect();
-    let drop_set: std::collections::HashSet<String> = state
-        .drop_targets
-        .iter()
-        .map(|(x, y)| format!("{},{}", x, y))
+        .map(|m| (m.x, m.y))
         .collect();
+    let drop_set: std::collections::HashSet<(i32, i32)> =
+        ui.drop_targets.iter().copied().collect();
 
     let start_line = lines.len();
     let mut header = "    ".to_string();
@@ -131,18 +131,18 @@ fn render_grid(
     for y in (y1..=y2).rev() {
         let mut row = format!("  {}{:>3} {}", FG_GRAY, y, RESET);
         for x in x1..=x2 {
-            let key = format!("{},{}", x, y);
-            let face = board_map
-                .get(&key)
-                .map(|&i| (state.pieces[i].face, state.pieces[i].owner));
-            let is_cursor = state.cursor.0 == x && state.cursor.1 == y;
-            let is_legal = legal_set.contains(&key);
-            let is_drop = drop_set.contains(&key);
-            let is_selected = state.selected.map_or(false, |si| {
+            let face = state
+                .board
+                .get(x, y)
+                .map(|i| (state.pieces[i].face, state.pieces[i].owner));
+            let is_cursor = ui.cursor.0 == x && ui.cursor.1 == y;
+            let is_legal = legal_set.contains(&(x, y));
+            let is_drop = drop_set.contains(&(x, y));
+            let is_selected = ui.selected.is_some_and(|si| {
                 state.pieces[si].x == Some(x) && state.pieces[si].y == Some(y)
             });
             let is_last_move =
-                state.last_move_from == Some((x, y)) || state.last_move_to == Some((x, y));
+                ui.last_move_from == Some((x, y)) || ui.last_move_to == Some((x, y));
             row += &render_cell(
                 face,
                 is_cursor,
@@ -150,6 +150,7 @@ fn render_grid(
                 is_drop,
                 is_selected,
                 is_last_move,
+                ui.last_stranded.contains(&(x, y)),
             );
         }
         lines.push(row);
@@ -166,6 +167,7 @@ fn render_cell(
     is_drop: bool,
     is_selected: bool,
     is_last_move: bool,
+    is_stranded: bool,
 ) -> String {
     let mut bg = "";
     let mut fg = "";
@@ -188,6 +190,8 @@ fn render_cell(
         bg = BG_GREEN;
     } else if is_last_move {
         bg = BG_BLUE;
+    } else if is_stranded {
+        bg = BG_RED;
     }
 
     format!("{}{}{}{}{}", bg, fg, BOLD, text, RESET)
@@ -217,17 +221,18 @@ fn render_hand(state: &GameState, lines: &mut Vec<String>, owner: Owner) {
     lines.push(s);
 }
 
-fn render_board(state: &GameState, lines: &mut Vec<String>) -> GridInfo {
+fn render_board(ui: &UiState, lines: &mut Vec<String>) -> GridInfo {
+    let state = &ui.game.state;
     let on_board: Vec<_> = state.pieces.iter().filter(|p| p.on_board()).collect();
-    if on_board.is_empty() && state.mode == Mode::SetupBlackGyoku {
+    if on_board.is_empty() && ui.mode == Mode::SetupBlackGyoku {
         let b_start = lines.len();
         let (gw, _) = render_grid(
-            state,
+            ui,
             lines,
-            state.cursor.0 - 3,
-            state.cursor.0 + 3,
-            state.cursor.1 - 3,
-            state.cursor.1 + 3,
+            ui.cursor.0 - 3,
+            ui.cursor.0 + 3,
+            ui.cursor.1 - 3,
+            ui.cursor.1 + 3,
         );
         return GridInfo {
             grid_width: gw,
@@ -235,13 +240,12 @@ fn render_board(state: &GameState, lines: &mut Vec<String>) -> GridInfo {
         };
     }
 
-    let mut min_x = state.cursor.0;
-    let mut max_x = state.cursor.0;
-    let mut min_y = state.cursor.1;
-    let mut max_y = state.cursor.1;
-    for p in &on_board {
-        let px = p.x.unwrap();
-        let py = p.y.unwrap();
+    let mut min_x = ui.cursor.0;
+    let mut max_x = ui.cursor.0;
+    let mut min_y = ui.cursor.1;
+    let mut max_y = ui.cursor.1;
+    let squares = on_board.iter().map(|p| (p.x.unwrap(), p.y.unwrap()));
+    for (px, py) in squares.chain(ui.last_stranded.iter().copied()) {
         min_x = min_x.min(px);
         max_x = max_x.max(px);
         min_y = min_y.min(py);
@@ -251,7 +255,7 @@ fn render_board(state: &GameState, lines: &mut Vec<String>) -> GridInfo {
     let wh_idx = lines.len();
     render_hand(state, lines, Owner::White);
     lines.push(String::new());
-    let (gw, _) = render_grid(state, lines, min_x - 2, max_x + 2, min_y - 2, max_y + 2);
+    let (gw, _) = render_grid(ui, lines, min_x - 2, max_x + 2, min_y - 2, max_y + 2);
     lines.push(String::new());
     render_hand(state, lines, Owner::Black);
     let bh_idx = lines.len() - 1;
@@ -262,14 +266,15 @@ fn render_board(state: &GameState, lines: &mut Vec<String>) -> GridInfo {
     }
 }
 
-fn render_status(state: &GameState, lines: &mut Vec<String>) {
+fn render_status(ui: &UiState, lines: &mut Vec<String>) {
+    let state = &ui.game.state;
     let turn_name = if state.turn == Owner::Black {
         format!("{}{}{}{}", FG_WHITE, BOLD, t("black"), RESET)
     } else {
         format!("{}{}{}{}", FG_CYAN, BOLD, t("white"), RESET)
     };
 
-    match state.mode {
+    match ui.mode {
         Mode::SetupBlackGyoku => {
             lines.push(format!("  {} {}", turn_name, t("place_gyoku")));
         }
@@ -277,8 +282,8 @@ fn render_status(state: &GameState, lines: &mut Vec<String>) {
             lines.push(format!("  {} {}", turn_name, t("place_gyoku_w")));
         }
         Mode::Board => {
-            if state.ai_side.is_ai(state.turn) {
-                if state.paused {
+            if ui.session.ai_side.is_ai(state.turn) {
+                if ui.paused {
                     lines.push(format!(
                         "  {} {}{}{} {} {}",
                         turn_name,
@@ -289,7 +294,7 @@ fn render_status(state: &GameState, lines: &mut Vec<String>) {
                         RESET
                     ));
                 } else {
-                    let elapsed = state
+                    let elapsed = ui
                         .ai_think_start
                         .map(|t| t.elapsed().as_secs_f32())
                         .unwrap_or(0.0);
@@ -309,9 +314,18 @@ fn render_status(state: &GameState, lines: &mut Vec<String>) {
         Mode::Selected => {
             lines.push(format!("  {} {}", turn_name, t("select_target")));
         }
+        Mode::ConfirmResign => {
+            lines.push(format!("  {} {}{}{}", turn_name, FG_YELLOW, t("confirm_resign"), RESET));
+        }
+        Mode::DrawOffer => {
+            lines.push(format!("  {} {}{}{}", turn_name, FG_YELLOW, t("draw_offered"), RESET));
+        }
         _ => {}
     }
 
+    if let Some(limit) = state.rules.ply_limit {
+        lines.push(format!("  {}{}{}/{}{}", FG_GRAY, t("ply_limit"), state.quiet_plies, limit, RESET));
+    }
     if state.in_check {
         lines.push(format!(
             "  {}{}{} {} {}",
@@ -322,13 +336,44 @@ fn render_status(state: &GameState, lines: &mut Vec<String>) {
             RESET
         ));
     }
-    if !state.message.is_empty() {
-        lines.push(format!("  {}{}{}", FG_YELLOW, state.message, RESET));
+    if !ui.message.is_empty() {
+        lines.push(format!("  {}{}{}", FG_YELLOW, ui.message, RESET));
+    }
+}
+
+/// "黒 Black Wins!" / "Draw!" for a finished game.
+fn result_headline(result: &GameResult) -> String {
+    match result.winner() {
+        Some(Owner::Black) => format!("{} {}", t("black"), t("wins")),
+        Some(Owner::White) => format!("{} {}", t("white"), t("wins")),
+        None => t("draw").to_string(),
     }
 }
 
-fn render_menu(state: &GameState, lines: &mut Vec<String>) {
-    let menus: &[(&str, &[(&str, &str)], &str)] = &[
+fn reason_text(reason: EndReason, rules: &RuleSet) -> String {
+    tn(match reason {
+        EndReason::GyokuCaptured => "reason_gyoku_captured",
+        EndReason::NoLegalAction => "reason_no_legal_action",
+        EndReason::PerpetualCheck => "perpetual_check_lose",
+        EndReason::Sennichite => "sennichite",
+        EndReason::DeadPosition => "reason_dead_position",
+        EndReason::PlyLimit => "reason_ply_limit",
+        EndReason::Resignation => "reason_resignation",
+        EndReason::Timeout => "reason_timeout",
+        EndReason::Agreement => "reason_agreement",
+    }, rules.repetition)
+}
+
+/// Closing log line for a finished game.
+fn result_log_line(result: &GameResult, rules: &RuleSet) -> String {
+    format!("{}{}{} — {}{}", FG_YELLOW, BOLD, result_headline(result), reason_text(result.reason(), rules), RESET)
+}
+
+/// A menu page: title key, (hotkey, label key) items, hint key.
+type MenuPage<'a> = (&'a str, &'a [(&'a str, &'a str)], &'a str);
+
+fn render_menu(ui: &UiState, lines: &mut Vec<String>) {
+    let menus: &[MenuPage] = &[
         (
             "choose_opponent",
             &[("1", "local_2p"), ("2", "ai_battle"), ("3", "tutorial")],
@@ -361,20 +406,21 @@ fn render_menu(state: &GameState, lines: &mut Vec<String>) {
             "press_1234",
         ),
     ];
-    let idx = (state.menu_step as usize)
+    let idx = (ui.menu_step as usize)
         .saturating_sub(1)
         .min(menus.len() - 1);
     let (title, items, hint) = menus[idx];
     lines.push(format!("  {}{}{}", BOLD, t(title), RESET));
     lines.push(String::new());
-    for &(k, v) in &*items {
+    for &(k, v) in items {
         lines.push(format!("  {}{}{}  {}", FG_YELLOW, k, RESET, t(v)));
     }
     lines.push(String::new());
     lines.push(format!("  {}{}{}", FG_GRAY, t(hint), RESET));
 }
 
-fn render_hand_select(state: &GameState, lines: &mut Vec<String>) {
+fn render_hand_select(ui: &UiState, lines: &mut Vec<String>) {
+    let state = &ui.game.state;
     let turn_name = if state.turn == Owner::Black {
         t("black_short")
     } else {
@@ -387,10 +433,10 @@ fn render_hand_select(state: &GameState, lines: &mut Vec<String>) {
         t("choose_drop"),
         RESET
     ));
-    for (i, &pi) in state.hand_pieces.iter().enumerate() {
+    for (i, &pi) in ui.hand_pieces.iter().enumerate() {
         let p = &state.pieces[pi];
         let kanji = p.face.kanji();
-        let marker = if i == state.hand_index {
+        let marker = if i == ui.hand_index {
             format!("{}{}", BG_YELLOW, FG_BLACK)
         } else {
             String::new()
@@ -399,12 +445,12 @@ fn render_hand_select(state: &GameState, lines: &mut Vec<String>) {
     }
 }
 
-fn render_face_select(state: &GameState, lines: &mut Vec<String>) {
+fn render_face_select(ui: &UiState, lines: &mut Vec<String>) {
     lines.push(format!("  {}{}{}", BOLD, t("choose_face"), RESET));
-    for (i, &face) in state.drop_faces.iter().enumerate() {
+    for (i, &face) in ui.drop_faces.iter().enumerate() {
         let kanji = face.kanji();
         let eng = face.english();
-        let marker = if i == state.face_index {
+        let marker = if i == ui.face_index {
             format!("{}{}", BG_YELLOW, FG_BLACK)
         } else {
             String::new()
@@ -420,12 +466,12 @@ fn render_drop_target(lines: &mut Vec<String>) {
     lines.push(format!("  {}{}{}", BOLD, t("choose_drop_pos"), RESET));
 }
 
-fn render_promote(state: &GameState, lines: &mut Vec<String>) {
+fn render_promote(ui: &UiState, lines: &mut Vec<String>) {
     lines.push(format!("  {}{}{}", BOLD, t("choose_promote"), RESET));
-    for (i, &face) in state.promote_choices.iter().enumerate() {
+    for (i, &face) in ui.promote_choices.iter().enumerate() {
         let kanji = face.kanji();
         let eng = face.english();
-        let marker = if i == state.promote_index {
+        let marker = if i == ui.promote_index {
             format!("{}{}", BG_YELLOW, FG_BLACK)
         } else {
             String::new()
@@ -437,12 +483,21 @@ fn render_promote(state: &GameState, lines: &mut Vec<String>) {
     }
 }
 
-fn render_log_browse(state: &GameState, lines: &mut Vec<String>) {
-    if state.move_log.is_empty() {
+fn render_log_browse(ui: &UiState, lines: &mut Vec<String>) {
+    let state = &ui.game.state;
+    let log = ui.game.move_log();
+    if log.is_empty() {
         return;
     }
-    let entry = &state.move_log[state.log_index];
-    let snap = &entry.snapshot;
+    let entry = &log[ui.log_index];
+    let snap = &match ui.game.pieces_after(ui.log_index) {
+        Some(Ok(pieces)) => pieces,
+        Some(Err(e)) => {
+            lines.push(format!("  {}{}{}", FG_RED, e, RESET));
+            return;
+        }
+        None => return,
+    };
 
     let on_board: Vec<_> = snap.iter().filter(|p| p.x.is_some()).collect();
     let (mut min_x, mut max_x, mut min_y, mut max_y) = (0i32, 0, 0, 0);
@@ -455,9 +510,9 @@ fn render_log_browse(state: &GameState, lines: &mut Vec<String>) {
         max_y = max_y.max(py);
     }
 
-    let board_map: HashMap<String, &PieceSnapshot> = on_board
+    let board_map: HashMap<(i32, i32), &Piece> = on_board
         .iter()
-        .map(|p| (format!("{},{}", p.x.unwrap(), p.y.unwrap()), *p))
+        .map(|p| ((p.x.unwrap(), p.y.unwrap()), *p))
         .collect();
 
     let (x1, x2, y1, y2) = (min_x - 2, max_x + 2, min_y - 2, max_y + 2);
@@ -475,17 +530,14 @@ fn render_log_browse(state: &GameState, lines: &mut Vec<String>) {
     }
     lines.push(format!("  {}{}{}", FG_GRAY, header, RESET));
 
-    let move_from = entry.from.map(|(x, y)| format!("{},{}", x, y));
-    let move_to = entry.to.map(|(x, y)| format!("{},{}", x, y));
-
     for y in (y1..=y2).rev() {
         let mut row = format!("  {}{:>3} {}", FG_GRAY, y, RESET);
         for x in x1..=x2 {
-            let key = format!("{},{}", x, y);
-            let face = board_map.get(&key).map(|p| (p.face, p.owner));
-            let is_from = move_from.as_deref() == Some(&key);
-            let is_to = move_to.as_deref() == Some(&key);
-            row += &render_cell(face, is_from, false, false, is_to, false);
+            let face = board_map.get(&(x, y)).map(|p| (p.face, p.owner));
+            let is_from = entry.from == Some((x, y));
+            let is_to = entry.to == Some((x, y));
+            let is_stranded = entry.stranded.iter().any(|s| s.at == (x, y));
+            row += &render_cell(face, is_from, false, false, is_to, false, is_stranded);
         }
         lines.push(row);
     }
@@ -502,12 +554,11 @@ fn render_log_browse(state: &GameState, lines: &mut Vec<String>) {
     log_panel.push(format!("{}── {} ──{}", FG_GRAY, t("log_title"), RESET));
     let board_height = board_end - board_start + 1;
     let visible = 12.min(board_height.saturating_sub(2));
-    let total = state.move_log.len();
-    let mut w_start = state.log_index.saturating_sub(visible / 2);
+    let total = log.len();
+    let mut w_start = ui.log_index.saturating_sub(visible / 2);
     w_start = w_start.min(total.saturating_sub(visible));
     let w_end = total.min(w_start + visible);
-    for i in w_start..w_end {
-        let e = &state.move_log[i];
+    for (i, e) in log.iter().enumerate().take(w_end).skip(w_start) {
         let color = if e.owner == Owner::Black {
             FG_WHITE
         } else {
@@ -518,35 +569,41 @@ fn render_log_browse(state: &GameState, lines: &mut Vec<String>) {
         } else {
             t("white_short")
         };
-        if i == state.log_index {
+        if i == ui.log_index {
             log_panel.push(format!(
                 "{}{}{}.{} {}{}",
-                BG_YELLOW, FG_BLACK, e.num, ow, e.text, RESET
+                BG_YELLOW, FG_BLACK, e.num, ow, log_text(e), RESET
             ));
         } else {
             log_panel.push(format!(
                 "{}{}.{} {}{}{}{} {}",
-                FG_GRAY, e.num, RESET, color, BOLD, ow, RESET, e.text
+                FG_GRAY, e.num, RESET, color, BOLD, ow, RESET, log_text(e)
             ));
         }
     }
+    if w_end == total {
+        if let Some(result) = state.result {
+            log_panel.push(result_log_line(&result, &state.rules));
+        }
+    }
 
     // Merge log on left of board area
     let left_w = merge_left_panel(lines, &log_panel, board_start, board_end);
 
     // Merge hint on right
-    if let Some(face) = entry.face {
+    let shown = if entry.kind == LogKind::Drop { entry.face_after } else { entry.face };
+    if let Some(face) = shown {
         let hint = get_move_hint_lines(face, entry.owner);
         let mut gw = grid_width + left_w;
         let end = lines.len().min(board_start + hint.len());
-        for i in board_start..end {
-            gw = gw.max(vis_len(&lines[i]));
+        for l in &lines[board_start.min(end)..end] {
+            gw = gw.max(vis_len(l));
         }
         merge_sidebar(lines, &hint, board_start, gw);
     }
 }
 
-fn render_snap_hand(snap: &[PieceSnapshot], lines: &mut Vec<String>, owner: Owner) {
+fn render_snap_hand(snap: &[Piece], lines: &mut Vec<String>, owner: Owner) {
     let hand: Vec<_> = snap
         .iter()
         .filter(|p| p.x.is_none() && p.cube != Cube::Gyoku && p.owner == owner)
@@ -593,12 +650,12 @@ fn build_diagram(face: Face, owner: Owner) -> Vec<String> {
     }
     let kanji = face.kanji();
     let mut lines = Vec::new();
-    for r in 0..sz {
+    for (r, row) in grid.iter().enumerate() {
         let mut line = String::new();
-        for c in 0..sz {
+        for (c, cell) in row.iter().enumerate() {
             if r as i32 == mid && c as i32 == mid {
                 line += kanji;
-            } else if let Some(mode) = grid[r][c] {
+            } else if let Some(mode) = *cell {
                 if mode == MoveMode::Step {
                     line += "* ";
                 } else {
@@ -677,30 +734,31 @@ fn get_move_hint_lines(face: Face, owner: Owner) -> Vec<String> {
     result
 }
 
-fn get_hint_for_state(state: &GameState) -> Vec<String> {
+fn get_hint_for_state(ui: &UiState) -> Vec<String> {
+    let state = &ui.game.state;
     let mut face = None;
     let mut owner = None;
-    match state.mode {
+    match ui.mode {
         Mode::Board => {
-            if let Some(pi) = state.piece_at(state.cursor.0, state.cursor.1) {
+            if let Some(pi) = state.piece_at(ui.cursor.0, ui.cursor.1) {
                 face = Some(state.pieces[pi].face);
                 owner = Some(state.pieces[pi].owner);
             }
         }
         Mode::Selected => {
-            if let Some(si) = state.selected {
+            if let Some(si) = ui.selected {
                 face = Some(state.pieces[si].face);
                 owner = Some(state.pieces[si].owner);
             }
         }
         Mode::FaceSelect => {
-            if let Some(&f) = state.drop_faces.get(state.face_index) {
+            if let Some(&f) = ui.drop_faces.get(ui.face_index) {
                 face = Some(f);
                 owner = Some(state.turn);
             }
         }
         Mode::Promote => {
-            if let Some(&f) = state.promote_choices.get(state.promote_index) {
+            if let Some(&f) = ui.promote_choices.get(ui.promote_index) {
                 face = Some(f);
                 owner = Some(state.turn);
             }
@@ -737,7 +795,7 @@ fn side_by_side(diagrams: &[Vec<String>], gap: &str) -> Vec<String> {
     result
 }
 
-fn render_tutorial(state: &GameState, lines: &mut Vec<String>) {
+fn render_tutorial(ui: &UiState, lines: &mut Vec<String>) {
     let ansi = Ansi {
         bold: BOLD,
         reset: RESET,
@@ -749,9 +807,9 @@ fn render_tutorial(state: &GameState, lines: &mut Vec<String>) {
         fg_red: FG_RED,
     };
     let pages = get_tutorial_pages(&ansi);
-    let page = &pages[state.tutorial_page];
+    let page = &pages[ui.tutorial_page];
     let total = pages.len();
-    let num = state.tutorial_page + 1;
+    let num = ui.tutorial_page + 1;
     lines.push(format!(
         "  {}{} ({}/{}) - {}{}",
         BOLD,
@@ -762,8 +820,8 @@ fn render_tutorial(state: &GameState, lines: &mut Vec<String>) {
         RESET
     ));
     lines.push(String::new());
-    for i in 1..page.len() {
-        lines.push(format!("  {}", page[i]));
+    for l in &page[1..] {
+        lines.push(format!("  {}", l));
     }
     lines.push(String::new());
     let mut nav = Vec::new();
@@ -777,12 +835,23 @@ fn render_tutorial(state: &GameState, lines: &mut Vec<String>) {
     lines.push(format!("  {}{}{}", FG_GRAY, nav.join("  "), RESET));
 }
 
-fn build_log_panel(state: &GameState) -> Vec<String> {
+/// A log entry in the current language: kanji notation in Chinese, ASCII
+/// in English.
+fn log_text(entry: &MoveLogEntry) -> String {
+    match get_lang() {
+        Lang::Zh => entry.kanji(),
+        Lang::En => entry.to_ascii(),
+    }
+}
+
+fn build_log_panel(game: &Game) -> Vec<String> {
+    let state = &game.state;
+    let log = game.move_log();
     let mut panel = Vec::new();
-    if !state.move_log.is_empty() {
+    if !log.is_empty() {
         panel.push(format!("{}── {} ──{}", FG_GRAY, t("log_title"), RESET));
-        let start = state.move_log.len().saturating_sub(8);
-        for entry in &state.move_log[start..] {
+        let start = log.len().saturating_sub(8);
+        for entry in &log[start..] {
             let color = if entry.owner == Owner::Black {
                 FG_WHITE
             } else {
@@ -795,25 +864,29 @@ fn build_log_panel(state: &GameState) -> Vec<String> {
             };
             panel.push(format!(
                 "{}{}.{} {}{}{}{} {}",
-                FG_GRAY, entry.num, RESET, color, BOLD, ow, RESET, entry.text
+                FG_GRAY, entry.num, RESET, color, BOLD, ow, RESET, log_text(entry)
             ));
         }
+        if let Some(result) = state.result {
+            panel.push(result_log_line(&result, &state.rules));
+        }
     }
     panel
 }
 
-fn build_sidebar(state: &GameState) -> Vec<String> {
-    get_hint_for_state(state)
+fn build_sidebar(ui: &UiState) -> Vec<String> {
+    get_hint_for_state(ui)
 }
 
-fn get_controls_hint(state: &GameState) -> Option<Vec<String>> {
-    match state.mode {
+fn get_controls_hint(ui: &UiState) -> Option<Vec<String>> {
+    let state = &ui.game.state;
+    match ui.mode {
         Mode::SetupBlackGyoku | Mode::SetupWhiteGyoku => Some(vec![
             t("controls_setup_1").into(),
             t("controls_setup_2").into(),
         ]),
         Mode::Board => {
-            if state.ai_side.is_ai(state.turn) {
+            if ui.session.ai_side.is_ai(state.turn) {
                 Some(vec![
                     format!("{}  Tab:{}", t("space_pause"), t("log_title")),
                     t("controls_board_2").into(),
@@ -821,10 +894,13 @@ fn get_controls_hint(state: &GameState) -> Option<Vec<String>> {
             } else {
                 Some(vec![
                     t("controls_board_1").into(),
+                    t("controls_declare").into(),
                     t("controls_board_2").into(),
                 ])
             }
         }
+        Mode::ConfirmResign => Some(vec![t("controls_confirm_resign").into()]),
+        Mode::DrawOffer => Some(vec![t("controls_draw_offer").into()]),
         Mode::Selected => Some(vec![t("controls_move").into()]),
         Mode::Hand | Mode::FaceSelect => Some(vec![t("controls_list").into()]),
         Mode::DropTarget => Some(vec![t("controls_drop").into()]),
@@ -838,40 +914,35 @@ fn get_controls_hint(state: &GameState) -> Option<Vec<String>> {
     }
 }
 
-pub fn render(state: &GameState) {
+pub fn render(ui: &UiState) {
+    let state = &ui.game.state;
     let mut lines = Vec::new();
     lines.push(format!("{}  ═══ {} ═══{}", BOLD, t("game_title"), RESET));
     lines.push(String::new());
 
     let mut grid_info: Option<GridInfo> = None;
 
-    if state.mode == Mode::Menu {
-        render_menu(state, &mut lines);
-    } else if state.mode == Mode::Tutorial {
-        render_tutorial(state, &mut lines);
-    } else if state.mode == Mode::LogBrowse {
-        render_log_browse(state, &mut lines);
+    if ui.mode == Mode::Menu {
+        render_menu(ui, &mut lines);
+    } else if ui.mode == Mode::Tutorial {
+        render_tutorial(ui, &mut lines);
+    } else if ui.mode == Mode::LogBrowse {
+        render_log_browse(ui, &mut lines);
+        if !ui.message.is_empty() {
+            lines.push(format!("  {}{}{}", FG_YELLOW, ui.message, RESET));
+        }
     } else {
-        let gi = render_board(state, &mut lines);
+        let gi = render_board(ui, &mut lines);
         lines.push(String::new());
 
-        match state.mode {
-            Mode::GameOver => {
-                let winner_name = if state.winner == Some(Owner::Black) {
-                    t("black")
-                } else {
-                    t("white")
-                };
-                lines.push(format!(
-                    "  {}{}★ {} {} ★{}",
-                    BOLD,
-                    FG_YELLOW,
-                    winner_name,
-                    t("wins"),
-                    RESET
-                ));
-                if !state.message.is_empty() {
-                    lines.push(format!("  {}{}{}", FG_YELLOW, state.message, RESET));
+        match ui.mode {
+            Mode::GameOver | Mode::Draw => {
+                if let Some(result) = state.result {
+                    lines.push(format!("  {}{}★ {} ★{}", BOLD, FG_YELLOW, result_headline(&result), RESET));
+                    lines.push(format!("  {}{}{}", FG_YELLOW, reason_text(result.reason(), &state.rules), RESET));
+                }
+                if !ui.message.is_empty() {
+                    lines.push(format!("  {}{}{}", FG_YELLOW, ui.message, RESET));
                 }
                 lines.push(String::new());
                 lines.push(format!(
@@ -882,23 +953,11 @@ pub fn render(state: &GameState) {
                     RESET
                 ));
             }
-            Mode::Draw => {
-                lines.push(format!("  {}{}★ {} ★{}", BOLD, FG_YELLOW, t("draw"), RESET));
-                lines.push(format!("  {}{}{}", FG_YELLOW, state.message, RESET));
-                lines.push(String::new());
-                lines.push(format!(
-                    "  {}{}  Tab:{}{}",
-                    FG_GRAY,
-                    t("quit_menu"),
-                    t("log_title"),
-                    RESET
-                ));
-            }
-            Mode::Hand => render_hand_select(state, &mut lines),
-            Mode::FaceSelect => render_face_select(state, &mut lines),
+            Mode::Hand => render_hand_select(ui, &mut lines),
+            Mode::FaceSelect => render_face_select(ui, &mut lines),
             Mode::DropTarget => render_drop_target(&mut lines),
-            Mode::Promote => render_promote(state, &mut lines),
-            _ => render_status(state, &mut lines),
+            Mode::Promote => render_promote(ui, &mut lines),
+            _ => render_status(ui, &mut lines),
         }
 
         grid_info = Some(gi);
@@ -908,21 +967,21 @@ pub fn render(state: &GameState) {
     if let Some(ref gi) = grid_info {
         if gi.grid_width > 0 {
             let side_start = gi.board_start;
-            let sidebar = build_sidebar(state);
+            let sidebar = build_sidebar(ui);
             let mut gw = gi.grid_width;
             if !sidebar.is_empty() {
                 let end = lines.len().min(side_start + sidebar.len());
-                for i in side_start..end {
-                    gw = gw.max(vis_len(&lines[i]));
+                for l in &lines[side_start.min(end)..end] {
+                    gw = gw.max(vis_len(l));
                 }
                 merge_sidebar(&mut lines, &sidebar, side_start, gw);
             }
-            let log_panel = build_log_panel(state);
+            let log_panel = build_log_panel(&ui.game);
             if !log_panel.is_empty() {
                 let mut gw2 = gw;
                 let log_end = lines.len().min(side_start + log_panel.len());
-                for i in side_start..log_end {
-                    gw2 = gw2.max(vis_len(&lines[i]));
+                for l in &lines[side_start.min(log_end)..log_end] {
+                    gw2 = gw2.max(vis_len(l));
                 }
                 merge_sidebar(&mut lines, &log_panel, side_start, gw2);
             }
@@ -930,7 +989,7 @@ pub fn render(state: &GameState) {
     }
 
     // Controls hint at bottom
-    if let Some(ctrl) = get_controls_hint(state) {
+    if let Some(ctrl) = get_controls_hint(ui) {
         lines.push(String::new());
         for c in &ctrl {
             lines.push(format!("  {}{}{}", FG_GRAY, c, RESET));
@@ -0,0 +1,54 @@
+// Ki Shogi - Seedable random numbers
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// ---------------------------------------------------------------------------
+// Rng
+// ---------------------------------------------------------------------------
+//
+// splitmix64: small, fast and good enough for tie-breaks and setup choices.
+// Everything random in a game draws from `Session::rng`, seeded from
+// `Session::seed`, so a game played again with the same seed and the
+// same human input takes the same course.
+
+const GOLDEN: u64 = 0x9E37_79B9_7F4A_7C15;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// A seed from the clock, for games started without one.
+    pub fn time_seed() -> u64 {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        Rng::new(nanos as u64).next_u64()
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(GOLDEN);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `0..max`; 0 when `max` is 0.
+    pub fn below(&mut self, max: usize) -> usize {
+        if max == 0 { return 0; }
+        let max = max as u64;
+        // Reject the top partial block so every value is equally likely
+        let zone = u64::MAX - u64::MAX % max;
+        loop {
+            let v = self.next_u64();
+            if v < zone { return (v % max) as usize; }
+        }
+    }
+}
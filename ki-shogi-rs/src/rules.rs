@@ -1,8 +1,9 @@
 // Ki Shogi - Core rule engine
 
 use crate::types::{Owner, Cube, Face, get_move_vectors};
-use crate::state::{GameState, LegalMove, Piece};
-use std::collections::HashSet;
+use crate::state::{Action, GameState, LegalMove};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 
 /// Chebyshev distance
 pub fn cheb_dist(x1: i32, y1: i32, x2: i32, y2: i32) -> i32 {
@@ -11,6 +12,7 @@ pub fn cheb_dist(x1: i32, y1: i32, x2: i32, y2: i32) -> i32 {
 
 /// BFS: find all Ki cubes connected to a Gyoku via adjacent Ki chain
 fn get_supported_ki(state: &GameState) -> HashSet<usize> {
+    let rules = &state.rules;
     let ki_pieces: Vec<(usize, i32, i32)> = state.pieces.iter().enumerate()
         .filter(|(_, p)| p.cube == Cube::Ki && p.on_board())
         .map(|(i, p)| (i, p.x.unwrap(), p.y.unwrap()))
@@ -21,18 +23,18 @@ fn get_supported_ki(state: &GameState) -> HashSet<usize> {
     let mut queue = Vec::new();
     for &(idx, kx, ky) in &ki_pieces {
         for p in &state.pieces {
-            if p.cube == Cube::Gyoku && p.on_board() {
-                if cheb_dist(kx, ky, p.x.unwrap(), p.y.unwrap()) <= 2 {
-                    supported.insert(idx);
-                    queue.push((idx, kx, ky));
-                    break;
-                }
+            if p.cube == Cube::Gyoku && p.on_board()
+                && cheb_dist(kx, ky, p.x.unwrap(), p.y.unwrap()) <= rules.ki_support_range
+            {
+                supported.insert(idx);
+                queue.push((idx, kx, ky));
+                break;
             }
         }
     }
     while let Some((_, cx, cy)) = queue.pop() {
         for &(idx, kx, ky) in &ki_pieces {
-            if !supported.contains(&idx) && cheb_dist(kx, ky, cx, cy) == 1 {
+            if !supported.contains(&idx) && cheb_dist(kx, ky, cx, cy) <= rules.support_range {
                 supported.insert(idx);
                 queue.push((idx, kx, ky));
             }
@@ -43,119 +45,367 @@ fn get_supported_ki(state: &GameState) -> HashSet<usize> {
 
 /// Is position supported for a given cube type?
 pub fn is_piece_supported(state: &GameState, x: i32, y: i32, cube: Cube) -> bool {
-    let supported_ki = get_supported_ki(state);
+    supported_by(state, &get_supported_ki(state), x, y, cube)
+}
+
+/// `is_piece_supported` with the supported Ki already worked out.
+fn supported_by(state: &GameState, supported_ki: &HashSet<usize>, x: i32, y: i32, cube: Cube) -> bool {
+    let rules = &state.rules;
     if cube == Cube::Ki {
         for p in &state.pieces {
             if p.cube == Cube::Gyoku && p.on_board()
-                && cheb_dist(x, y, p.x.unwrap(), p.y.unwrap()) <= 2
+                && cheb_dist(x, y, p.x.unwrap(), p.y.unwrap()) <= rules.ki_support_range
             { return true; }
         }
-        for &idx in &supported_ki {
+        for &idx in supported_ki {
             let ki = &state.pieces[idx];
-            if cheb_dist(x, y, ki.x.unwrap(), ki.y.unwrap()) == 1 { return true; }
+            let d = cheb_dist(x, y, ki.x.unwrap(), ki.y.unwrap());
+            if d >= 1 && d <= rules.support_range { return true; }
         }
         return false;
     }
-    // Normal piece: adjacent to any Gyoku or supported Ki (dist 1)
+    // Normal piece: near any Gyoku or supported Ki (support range, 1)
     for p in &state.pieces {
         if p.cube == Cube::Gyoku && p.on_board()
-            && cheb_dist(x, y, p.x.unwrap(), p.y.unwrap()) == 1
+            && cheb_dist(x, y, p.x.unwrap(), p.y.unwrap()) <= rules.support_range
         { return true; }
     }
-    for &idx in &supported_ki {
+    for &idx in supported_ki {
         let ki = &state.pieces[idx];
-        if cheb_dist(x, y, ki.x.unwrap(), ki.y.unwrap()) == 1 { return true; }
+        if cheb_dist(x, y, ki.x.unwrap(), ki.y.unwrap()) <= rules.support_range { return true; }
     }
     false
 }
 
-/// Is the given player's Gyoku under attack?
-pub fn is_in_check(state: &GameState, owner: Owner) -> bool {
-    let gi = match state.gyoku(owner) {
-        Some(i) => i,
-        None => return false,
-    };
-    let g = &state.pieces[gi];
-    if !g.on_board() { return false; }
-    let (gx, gy) = (g.x.unwrap(), g.y.unwrap());
-    let opponent = owner.opponent();
-    let board_map = state.build_board_map();
+/// Board pieces of `owner` that are unsupported and would be stranded
+fn stranded_pieces(state: &GameState, owner: Owner) -> Vec<usize> {
+    let supported_ki = get_supported_ki(state);
+    state.pieces.iter().enumerate()
+        .filter(|(_, p)| {
+            p.on_board() && p.cube != Cube::Gyoku && p.owner == owner
+                && !supported_by(state, &supported_ki, p.x.unwrap(), p.y.unwrap(), p.cube)
+        })
+        .map(|(i, _)| i)
+        .collect()
+}
 
-    for p in &state.pieces {
-        if p.owner != opponent || !p.on_board() { continue; }
-        let (px, py) = (p.x.unwrap(), p.y.unwrap());
-        for (dx, dy, is_slide) in get_move_vectors(p.face, p.owner) {
-            let max_d = if is_slide { 20 } else { 1 };
-            for d in 1..=max_d {
-                let nx = px + dx * d;
-                let ny = py + dy * d;
-                if nx == gx && ny == gy { return true; }
-                if board_map.contains_key(&format!("{},{}", nx, ny)) { break; }
-            }
+// ---------------------------------------------------------------------------
+// Slide range
+// ---------------------------------------------------------------------------
+//
+// The board has no edge, so a slide is bounded by geometry instead:
+//
+// * A slide stops at the first piece on its ray. `ray_blocker` finds that
+//   piece from the board map rather than by walking, so a blocker 25 or
+//   2500 cells away is found the same way.
+// * A move must end on a supported cell: near a Gyoku or supported Ki,
+//   within `RuleSet::reach` (2 in the standard rules). Slide vectors are
+//   unit steps, so each step changes the Chebyshev distance to any cell `a`
+//   by at most one, and the cell d steps out is at least d - cheb(p, a)
+//   from `a`. No cell past `support_reach` = max cheb(p, a) + reach over
+//   the Gyoku and Ki on the board can be supported, so quiet moves stop
+//   there.
+//   Captures happen at the blocker and are not cut off by it, since taking
+//   a Gyoku or Ki excuses the capturer from support.
+// * An attack on a given cell needs only the ray from the attacker to it
+//   to be clear up to that cell (`attacks`), however far away it is.
+// * A ray that meets no piece attacks infinitely many empty cells. Only
+//   cells within reach of a Gyoku or Ki can hold a piece after the next
+//   move or drop (drops are near a Gyoku too), so the attack map keeps just
+//   those and the occupied cells. Any of them lies within `support_reach`
+//   of every attacker, so walking empty rays that far finds all attackers.
+
+/// Steps `d >= 1` along the unit vector (dx, dy) from (px, py) to (x, y),
+/// if (x, y) lies on that ray.
+fn steps_along(px: i32, py: i32, dx: i32, dy: i32, x: i32, y: i32) -> Option<i32> {
+    let (ex, ey) = (x - px, y - py);
+    let d = if dx != 0 { ex * dx } else { ey * dy };
+    (d >= 1 && ex == dx * d && ey == dy * d).then_some(d)
+}
+
+/// Steps along the unit vector (dx, dy) from (px, py) to the nearest piece.
+fn ray_blocker(state: &GameState, px: i32, py: i32, dx: i32, dy: i32) -> Option<i32> {
+    state.board.iter()
+        .filter_map(|((x, y), _)| steps_along(px, py, dx, dy, x, y))
+        .min()
+}
+
+/// The furthest step along any slide from (px, py) that can be supported.
+fn support_reach(state: &GameState, px: i32, py: i32) -> i32 {
+    let reach = state.rules.reach();
+    state.pieces.iter()
+        .filter(|p| matches!(p.cube, Cube::Gyoku | Cube::Ki))
+        .filter_map(|p| p.pos())
+        .map(|(x, y)| cheb_dist(px, py, x, y) + reach)
+        .max()
+        .unwrap_or(0)
+}
+
+// ---------------------------------------------------------------------------
+// Attacks
+// ---------------------------------------------------------------------------
+
+/// Visit the cells the piece at `pidx` attacks: along each move vector up
+/// to and including the first occupied cell, with empty rays cut off at the
+/// support reach.
+fn walk_attacks(state: &GameState, pidx: usize, mut f: impl FnMut(i32, i32)) {
+    let p = &state.pieces[pidx];
+    let Some((px, py)) = p.pos() else { return };
+    let reach = support_reach(state, px, py);
+    for (dx, dy, is_slide) in get_move_vectors(p.face, p.owner) {
+        let max_d = if is_slide { ray_blocker(state, px, py, dx, dy).unwrap_or(reach) } else { 1 };
+        for d in 1..=max_d {
+            f(px + dx * d, py + dy * d);
         }
     }
-    false
 }
 
-/// Check if moving piece to (tx,ty) would be legal. Mutates and restores state.
-fn would_be_legal(state: &mut GameState, pidx: usize, tx: i32, ty: i32, cap_idx: Option<usize>) -> bool {
-    let orig_x = state.pieces[pidx].x;
-    let orig_y = state.pieces[pidx].y;
-    let orig_face = state.pieces[pidx].face;
-    let p_cube = state.pieces[pidx].cube;
-    let p_owner = state.pieces[pidx].owner;
-    let p_id = state.pieces[pidx].id;
-
-    state.pieces[pidx].x = Some(tx);
-    state.pieces[pidx].y = Some(ty);
-
-    let mut cap_ox = None;
-    let mut cap_oy = None;
-    if let Some(ci) = cap_idx {
-        cap_ox = state.pieces[ci].x;
-        cap_oy = state.pieces[ci].y;
-        state.pieces[ci].x = None;
-        state.pieces[ci].y = None;
-    } else if p_cube != Cube::Gyoku {
-        if let Some(opp) = state.pieces[pidx].face.opposite() {
-            state.pieces[pidx].face = opp;
+/// Does the piece at `pidx` attack (x, y)?
+pub fn attacks(state: &GameState, pidx: usize, x: i32, y: i32) -> bool {
+    let p = &state.pieces[pidx];
+    let Some((px, py)) = p.pos() else { return false };
+    get_move_vectors(p.face, p.owner).into_iter().any(|(dx, dy, is_slide)| {
+        if !is_slide {
+            return px + dx == x && py + dy == y;
         }
-    }
+        match steps_along(px, py, dx, dy, x, y) {
+            Some(d) => ray_blocker(state, px, py, dx, dy).is_none_or(|b| b >= d),
+            None => false,
+        }
+    })
+}
 
-    let mut legal = true;
+/// Board pieces of `owner` attacking (x, y).
+pub fn attackers_of(state: &GameState, x: i32, y: i32, owner: Owner) -> Vec<usize> {
+    (0..state.pieces.len())
+        .filter(|&i| state.pieces[i].owner == owner && attacks(state, i, x, y))
+        .collect()
+}
 
-    if p_cube == Cube::Gyoku {
+/// Is (x, y) attacked by any board piece of `owner`?
+pub fn is_attacked(state: &GameState, x: i32, y: i32, owner: Owner) -> bool {
+    (0..state.pieces.len())
+        .any(|i| state.pieces[i].owner == owner && attacks(state, i, x, y))
+}
+
+/// The cells one side attacks, with the pieces attacking each. Covers every
+/// occupied cell and every cell within `RuleSet::reach` of a Gyoku or Ki;
+/// see "Slide range" for why no other cell matters.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AttackMap {
+    cells: HashMap<(i32, i32), Vec<usize>>,
+}
+
+impl AttackMap {
+    /// The attack map of `owner`'s board pieces.
+    pub fn of(state: &GameState, owner: Owner) -> Self {
+        let reach = state.rules.reach();
+        let anchors: Vec<(i32, i32)> = state.pieces.iter()
+            .filter(|p| matches!(p.cube, Cube::Gyoku | Cube::Ki))
+            .filter_map(|p| p.pos())
+            .collect();
+        let mut cells: HashMap<(i32, i32), Vec<usize>> = HashMap::new();
         for i in 0..state.pieces.len() {
-            let p = &state.pieces[i];
-            if p.cube == Cube::Gyoku && p.id != p_id && p.on_board() {
-                if cheb_dist(tx, ty, p.x.unwrap(), p.y.unwrap()) != 2 {
-                    legal = false;
+            if state.pieces[i].owner != owner { continue; }
+            walk_attacks(state, i, |x, y| {
+                if state.board.is_occupied(x, y)
+                    || anchors.iter().any(|&(ax, ay)| cheb_dist(x, y, ax, ay) <= reach)
+                {
+                    cells.entry((x, y)).or_default().push(i);
                 }
-                break;
-            }
+            });
         }
+        Self { cells }
     }
 
-    if legal && p_cube != Cube::Gyoku {
-        let skip = cap_idx.map_or(false, |ci| {
-            matches!(state.pieces[ci].cube, Cube::Gyoku | Cube::Ki)
-        });
-        if !skip && !is_piece_supported(state, tx, ty, p_cube) {
-            legal = false;
+    /// Pieces attacking (x, y), in piece order.
+    pub fn attackers(&self, x: i32, y: i32) -> &[usize] {
+        self.cells.get(&(x, y)).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn is_attacked(&self, x: i32, y: i32) -> bool {
+        self.cells.contains_key(&(x, y))
+    }
+
+    /// Attacked cells and their attackers, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = ((i32, i32), &[usize])> + '_ {
+        self.cells.iter().map(|(&pos, a)| (pos, a.as_slice()))
+    }
+}
+
+/// Is the given player's Gyoku under attack?
+pub fn is_in_check(state: &GameState, owner: Owner) -> bool {
+    let Some(gi) = state.gyoku(owner) else { return false };
+    match state.pieces[gi].pos() {
+        Some((gx, gy)) => is_attacked(state, gx, gy, owner.opponent()),
+        None => false,
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Make / unmake
+// ---------------------------------------------------------------------------
+
+/// Everything `make` changed, so that `unmake` can put it back exactly.
+#[derive(Debug, Clone)]
+pub struct Undo {
+    piece_idx: usize,
+    mover: Owner,
+    from: Option<(i32, i32)>,
+    face: Face,
+    /// The captured piece with its square and face before the capture.
+    captured: Option<(usize, (i32, i32), Face)>,
+    /// Pieces the mover lost to stranding, with their squares and faces.
+    stranded: Vec<(usize, (i32, i32), Face)>,
+    turn: Owner,
+}
+
+impl Undo {
+    /// Indices of the pieces stranded by the ply.
+    pub fn stranded(&self) -> impl Iterator<Item = usize> + '_ {
+        self.stranded.iter().map(|&(i, _, _)| i)
+    }
+
+    /// The stranded pieces with the squares and faces they had on the
+    /// board.
+    pub fn stranded_from(&self) -> impl Iterator<Item = (usize, (i32, i32), Face)> + '_ {
+        self.stranded.iter().copied()
+    }
+}
+
+/// Play a full ply: the move or drop itself (capture to hand, promotion or
+/// flip), then stranding of the mover's unsupported pieces, then the turn
+/// passes. Does not check legality. Capturing a Gyoku ends the game, so it
+/// is simply taken off the board and nothing is stranded.
+pub fn make(state: &mut GameState, action: &Action) -> Undo {
+    let (pidx, to) = match action {
+        Action::Move { piece_idx, mv, .. } => (*piece_idx, (mv.x, mv.y)),
+        Action::Drop { piece_idx, pos, .. } => (*piece_idx, *pos),
+    };
+    let mover = state.pieces[pidx].owner;
+    let mut undo = Undo {
+        piece_idx: pidx,
+        mover,
+        from: state.pieces[pidx].pos(),
+        face: state.pieces[pidx].face,
+        captured: None,
+        stranded: Vec::new(),
+        turn: state.turn,
+    };
+
+    state.unindex_piece(pidx);
+    let mut took_gyoku = false;
+    match action {
+        Action::Move { mv, promote_to, .. } => match mv.capture {
+            Some(ci) => {
+                let c = &state.pieces[ci];
+                undo.captured = Some((ci, (mv.x, mv.y), c.face));
+                took_gyoku = c.cube == Cube::Gyoku;
+                state.unindex_piece(ci);
+                state.pieces[ci].x = None;
+                state.pieces[ci].y = None;
+                if !took_gyoku {
+                    state.pieces[ci].owner = mover;
+                }
+                state.index_piece(ci);
+                if let Some(pt) = promote_to {
+                    state.pieces[pidx].face = *pt;
+                }
+            }
+            None => {
+                let p = &mut state.pieces[pidx];
+                if p.cube != Cube::Gyoku {
+                    if let Some(opp) = p.face.opposite() {
+                        p.face = opp;
+                    }
+                }
+            }
+        },
+        Action::Drop { face, .. } => state.pieces[pidx].face = *face,
+    }
+    state.pieces[pidx].x = Some(to.0);
+    state.pieces[pidx].y = Some(to.1);
+    state.index_piece(pidx);
+
+    if !took_gyoku {
+        for idx in stranded_pieces(state, mover) {
+            let p = &state.pieces[idx];
+            undo.stranded.push((idx, (p.x.unwrap(), p.y.unwrap()), p.face));
+            state.unindex_piece(idx);
+            let p = &mut state.pieces[idx];
+            p.x = None;
+            p.y = None;
+            p.owner = mover.opponent();
+            p.face = Face::base(p.cube);
+            state.index_piece(idx);
         }
     }
 
-    if legal && is_in_check(state, p_owner) {
-        legal = false;
+    state.turn = mover.opponent();
+    undo
+}
+
+/// Take back a ply played with `make`.
+pub fn unmake(state: &mut GameState, undo: Undo) {
+    // Stranded pieces first: the mover itself may be among them
+    for &(idx, (x, y), face) in undo.stranded.iter().rev() {
+        state.unindex_piece(idx);
+        let p = &mut state.pieces[idx];
+        p.x = Some(x);
+        p.y = Some(y);
+        p.owner = undo.mover;
+        p.face = face;
+        state.index_piece(idx);
     }
 
-    state.pieces[pidx].x = orig_x;
-    state.pieces[pidx].y = orig_y;
-    state.pieces[pidx].face = orig_face;
-    if let Some(ci) = cap_idx {
-        state.pieces[ci].x = cap_ox;
-        state.pieces[ci].y = cap_oy;
+    let pidx = undo.piece_idx;
+    state.unindex_piece(pidx);
+    let p = &mut state.pieces[pidx];
+    p.x = undo.from.map(|(x, _)| x);
+    p.y = undo.from.map(|(_, y)| y);
+    p.face = undo.face;
+    if let Some((ci, (x, y), face)) = undo.captured {
+        state.unindex_piece(ci);
+        let c = &mut state.pieces[ci];
+        c.x = Some(x);
+        c.y = Some(y);
+        c.owner = undo.mover.opponent();
+        c.face = face;
+        state.index_piece(ci);
     }
+    state.index_piece(pidx);
+    state.turn = undo.turn;
+}
+
+// ---------------------------------------------------------------------------
+// Legal moves and drops
+// ---------------------------------------------------------------------------
+
+/// Check if moving piece to (tx,ty) would be legal, by playing the full ply
+/// with `make` and taking it back.
+fn would_be_legal(state: &mut GameState, pidx: usize, tx: i32, ty: i32, cap_idx: Option<usize>) -> bool {
+    let p_owner = state.pieces[pidx].owner;
+
+    // A Gyoku must stay exactly the Gyoku distance from the other Gyoku
+    if state.pieces[pidx].cube == Cube::Gyoku {
+        if let Some(oi) = state.gyoku(p_owner.opponent()) {
+            if let Some((ox, oy)) = state.pieces[oi].pos() {
+                if cap_idx != Some(oi) && cheb_dist(tx, ty, ox, oy) != state.rules.gyoku_distance {
+                    return false;
+                }
+            }
+        }
+    }
+
+    // Capturing a Gyoku or Ki excuses the capturer from support
+    let skip_support = cap_idx.is_some_and(|ci| {
+        matches!(state.pieces[ci].cube, Cube::Gyoku | Cube::Ki)
+    });
+    let mv = LegalMove { x: tx, y: ty, capture: cap_idx };
+    let undo = make(state, &Action::Move { piece_idx: pidx, mv, promote_to: None });
+    let legal = (skip_support || !undo.stranded().any(|i| i == pidx))
+        && !is_in_check(state, p_owner);
+    unmake(state, undo);
     legal
 }
 
@@ -167,46 +417,41 @@ pub fn get_legal_moves(state: &mut GameState, pidx: usize) -> Vec<LegalMove> {
     let px = state.pieces[pidx].x.unwrap();
     let py = state.pieces[pidx].y.unwrap();
     let opponent = owner.opponent();
-    let board_map = state.build_board_map();
     let vectors = get_move_vectors(face, owner);
+    let reach = support_reach(state, px, py);
     let mut moves = Vec::new();
 
     for (dx, dy, is_slide) in vectors {
-        let max_d = if is_slide { 20 } else { 1 };
-        for d in 1..=max_d {
-            let nx = px + dx * d;
-            let ny = py + dy * d;
-            let key = format!("{},{}", nx, ny);
-            if let Some(&ti) = board_map.get(&key) {
-                if state.pieces[ti].owner == opponent {
-                    if would_be_legal(state, pidx, nx, ny, Some(ti)) {
-                        moves.push(LegalMove { x: nx, y: ny, capture: Some(ti) });
-                    }
-                }
-                break;
-            }
+        // Quiet moves up to the blocker or the support reach, then the blocker
+        let (quiet, blocker) = if is_slide {
+            let b = ray_blocker(state, px, py, dx, dy);
+            (b.map_or(reach, |b| (b - 1).min(reach)), b)
+        } else if state.board.is_occupied(px + dx, py + dy) {
+            (0, Some(1))
+        } else {
+            (1, None)
+        };
+        for d in 1..=quiet {
+            let (nx, ny) = (px + dx * d, py + dy * d);
             if would_be_legal(state, pidx, nx, ny, None) {
                 moves.push(LegalMove { x: nx, y: ny, capture: None });
             }
         }
+        if let Some(d) = blocker {
+            let (nx, ny) = (px + dx * d, py + dy * d);
+            let ti = state.board.get(nx, ny).expect("blocker is on the board");
+            let target = &state.pieces[ti];
+            let capturable = target.cube != Cube::Gyoku || state.rules.gyoku_capture_wins;
+            if target.owner == opponent && capturable
+                && would_be_legal(state, pidx, nx, ny, Some(ti))
+            {
+                moves.push(LegalMove { x: nx, y: ny, capture: Some(ti) });
+            }
+        }
     }
     moves
 }
 
-/// Would dropping a piece with given face at (x,y) give check to opponent?
-fn would_drop_cause_check(state: &mut GameState, owner: Owner, face: Face, x: i32, y: i32) -> bool {
-    let opponent = owner.opponent();
-    if is_in_check(state, opponent) { return false; }
-    let temp = Piece::new(9999, owner, face.cube(), face);
-    let idx = state.pieces.len();
-    state.pieces.push(temp);
-    state.pieces[idx].x = Some(x);
-    state.pieces[idx].y = Some(y);
-    let check = is_in_check(state, opponent);
-    state.pieces.pop();
-    check
-}
-
 /// Get legal drop positions for a piece with a given face
 pub fn get_legal_drops(state: &mut GameState, owner: Owner, face: Face) -> Vec<(i32, i32)> {
     let gi = match state.gyoku(owner) { Some(i) => i, None => return Vec::new() };
@@ -214,9 +459,13 @@ pub fn get_legal_drops(state: &mut GameState, owner: Owner, face: Face) -> Vec<(
     let (gx, gy) = (state.pieces[gi].x.unwrap(), state.pieces[gi].y.unwrap());
     let opponent = owner.opponent();
     let ogi = state.gyoku(opponent);
+    let hand_idx = state.pieces.iter()
+        .position(|p| p.owner == owner && !p.on_board() && p.cube == face.cube());
+    let Some(hand_idx) = hand_idx else { return Vec::new() };
+    // A drop may not give check, unless the opponent is already in check
+    let opp_in_check = is_in_check(state, opponent);
 
-    let is_ki = face.cube() == Cube::Ki;
-    let range: i32 = if is_ki { 2 } else { 1 };
+    let range = state.rules.drop_range_for(face.cube() == Cube::Ki);
     let mut drops = Vec::new();
 
     for dx in -range..=range {
@@ -235,49 +484,21 @@ pub fn get_legal_drops(state: &mut GameState, owner: Owner, face: Face) -> Vec<(
                 }
             }
 
-            if would_drop_cause_check(state, owner, face, nx, ny) { continue; }
-
-            // Drop must resolve own check
-            let temp = Piece::new(9998, owner, face.cube(), face);
-            let tidx = state.pieces.len();
-            state.pieces.push(temp);
-            state.pieces[tidx].x = Some(nx);
-            state.pieces[tidx].y = Some(ny);
-            let still = is_in_check(state, owner);
-            state.pieces.pop();
-            if still { continue; }
-
-            drops.push((nx, ny));
+            // No check given, and own check (if any) resolved. A drop only
+            // adds a piece, so it gives check exactly when it attacks the Gyoku
+            let undo = make(state, &Action::Drop { piece_idx: hand_idx, face, pos: (nx, ny) });
+            let gives_check = ogi.and_then(|oi| state.pieces[oi].pos())
+                .is_some_and(|(ox, oy)| attacks(state, hand_idx, ox, oy));
+            let legal = (opp_in_check || !gives_check) && !is_in_check(state, owner);
+            unmake(state, undo);
+            if legal {
+                drops.push((nx, ny));
+            }
         }
     }
     drops
 }
 
-/// Find stranded pieces and capture them for opponent
-pub fn handle_stranding(state: &mut GameState) {
-    let turn = state.turn;
-    let opponent = turn.opponent();
-    let stranded: Vec<usize> = state.pieces.iter().enumerate()
-        .filter(|(_, p)| {
-            p.on_board() && p.cube != Cube::Gyoku && p.owner == turn
-                && !is_piece_supported(state, p.x.unwrap(), p.y.unwrap(), p.cube)
-        })
-        .map(|(i, _)| i)
-        .collect();
-
-    for idx in stranded {
-        state.pieces[idx].x = None;
-        state.pieces[idx].y = None;
-        state.pieces[idx].owner = opponent;
-        state.pieces[idx].face = match state.pieces[idx].cube {
-            Cube::Hi => Face::Hi,
-            Cube::Kaku => Face::Kaku,
-            Cube::Ki => Face::Ki,
-            _ => state.pieces[idx].face,
-        };
-    }
-}
-
 /// Check if player has any legal action (move or drop)
 pub fn has_legal_action(state: &mut GameState, owner: Owner) -> bool {
     let board_idxs: Vec<usize> = state.pieces.iter().enumerate()
@@ -296,19 +517,362 @@ pub fn has_legal_action(state: &mut GameState, owner: Owner) -> bool {
     false
 }
 
-/// Valid positions for White Gyoku (Chebyshev distance exactly 2 from Black Gyoku)
+// ---------------------------------------------------------------------------
+// Explaining illegal actions
+// ---------------------------------------------------------------------------
+//
+// `explain_move` and `explain_drop` test a proposed action against the same
+// conditions as `get_legal_moves` and `get_legal_drops` and report the first
+// one it fails, so they return `None` exactly for the actions listed there.
+
+/// Why a proposed move or drop is illegal. Squares are absolute (x, y);
+/// pieces are indices into `GameState::pieces`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IllegalReason {
+    /// A move of a piece that is not on the board, or a drop with no cube
+    /// of the face in hand or no own Gyoku on the board.
+    Unavailable,
+    /// No step or slide of the face ends on the square.
+    Unreachable { face: Face, from: (i32, i32), to: (i32, i32) },
+    /// A piece on the slide's path, or on the square itself: an own piece,
+    /// any piece for a drop, or a Gyoku when Gyoku cannot be captured.
+    Blocked { at: (i32, i32), piece: usize },
+    /// Nothing would support the piece on the square, so it would be
+    /// stranded at once.
+    Unsupported { at: (i32, i32) },
+    /// A Gyoku move off the Gyoku distance from the other Gyoku.
+    GyokuDistance { to: (i32, i32), other: (i32, i32), required: i32 },
+    /// The own Gyoku would be in check afterwards, from these pieces.
+    LeavesCheck { gyoku: (i32, i32), attackers: Vec<usize> },
+    /// A drop attacking the enemy Gyoku while it is not in check.
+    DropGivesCheck { at: (i32, i32), gyoku: (i32, i32) },
+    /// A drop next to the enemy Gyoku.
+    DropNextToGyoku { at: (i32, i32), gyoku: (i32, i32) },
+    /// A drop further than the drop range from the own Gyoku.
+    DropOutOfRange { at: (i32, i32), gyoku: (i32, i32), range: i32 },
+}
+
+/// A square as notation writes it.
+fn sq(&(x, y): &(i32, i32)) -> String {
+    format!("({},{})", x, y)
+}
+
+impl fmt::Display for IllegalReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IllegalReason::Unavailable => f.write_str("the piece cannot be played"),
+            IllegalReason::Unreachable { face, from, to } => {
+                write!(f, "{} at {} cannot reach {}", face.name(), sq(from), sq(to))
+            }
+            IllegalReason::Blocked { at, .. } => write!(f, "blocked by the piece at {}", sq(at)),
+            IllegalReason::Unsupported { at } => {
+                write!(f, "nothing supports {}; the piece would be stranded", sq(at))
+            }
+            IllegalReason::GyokuDistance { to, other, required } => {
+                write!(f, "{} is not {} from the other Gyoku at {}", sq(to), required, sq(other))
+            }
+            IllegalReason::LeavesCheck { gyoku, .. } => write!(f, "leaves the Gyoku at {} in check", sq(gyoku)),
+            IllegalReason::DropGivesCheck { at, gyoku } => {
+                write!(f, "a drop at {} checks the Gyoku at {}", sq(at), sq(gyoku))
+            }
+            IllegalReason::DropNextToGyoku { at, gyoku } => {
+                write!(f, "{} is next to the enemy Gyoku at {}", sq(at), sq(gyoku))
+            }
+            IllegalReason::DropOutOfRange { at, gyoku, range } => {
+                write!(f, "{} is more than {} from the Gyoku at {}", sq(at), range, sq(gyoku))
+            }
+        }
+    }
+}
+
+impl std::error::Error for IllegalReason {}
+
+/// `LeavesCheck` if `owner` is in check.
+fn check_reason(state: &GameState, owner: Owner) -> Option<IllegalReason> {
+    if !is_in_check(state, owner) { return None; }
+    let gyoku = state.gyoku(owner).and_then(|gi| state.pieces[gi].pos())?;
+    let attackers = attackers_of(state, gyoku.0, gyoku.1, owner.opponent());
+    Some(IllegalReason::LeavesCheck { gyoku, attackers })
+}
+
+/// Why moving the piece at `pidx` to `to` is illegal; `None` if it is legal.
+pub fn explain_move(state: &mut GameState, pidx: usize, to: (i32, i32)) -> Option<IllegalReason> {
+    let p = &state.pieces[pidx];
+    let Some(from) = p.pos() else { return Some(IllegalReason::Unavailable) };
+    let (face, owner, cube) = (p.face, p.owner, p.cube);
+
+    // Some step or slide must end on the square with nothing in between
+    let mut blocked = None;
+    let mut reached = false;
+    for (dx, dy, is_slide) in get_move_vectors(face, owner) {
+        if !is_slide {
+            reached |= (from.0 + dx, from.1 + dy) == to;
+            continue;
+        }
+        let Some(d) = steps_along(from.0, from.1, dx, dy, to.0, to.1) else { continue };
+        match ray_blocker(state, from.0, from.1, dx, dy).filter(|&b| b < d) {
+            None => reached = true,
+            Some(b) => {
+                let at = (from.0 + dx * b, from.1 + dy * b);
+                let piece = state.board.get(at.0, at.1).expect("blocker is on the board");
+                blocked.get_or_insert(IllegalReason::Blocked { at, piece });
+            }
+        }
+    }
+    if !reached {
+        return Some(blocked.unwrap_or(IllegalReason::Unreachable { face, from, to }));
+    }
+
+    let target = state.board.get(to.0, to.1);
+    if let Some(ti) = target {
+        let t = &state.pieces[ti];
+        if t.owner == owner || (t.cube == Cube::Gyoku && !state.rules.gyoku_capture_wins) {
+            return Some(IllegalReason::Blocked { at: to, piece: ti });
+        }
+    }
+
+    if cube == Cube::Gyoku {
+        if let Some(oi) = state.gyoku(owner.opponent()) {
+            if let Some(other) = state.pieces[oi].pos() {
+                let required = state.rules.gyoku_distance;
+                if target != Some(oi) && cheb_dist(to.0, to.1, other.0, other.1) != required {
+                    return Some(IllegalReason::GyokuDistance { to, other, required });
+                }
+            }
+        }
+    }
+
+    // As in `would_be_legal`
+    let skip_support = target.is_some_and(|ci| {
+        matches!(state.pieces[ci].cube, Cube::Gyoku | Cube::Ki)
+    });
+    let mv = LegalMove { x: to.0, y: to.1, capture: target };
+    let undo = make(state, &Action::Move { piece_idx: pidx, mv, promote_to: None });
+    let reason = if !skip_support && undo.stranded().any(|i| i == pidx) {
+        Some(IllegalReason::Unsupported { at: to })
+    } else {
+        check_reason(state, owner)
+    };
+    unmake(state, undo);
+    reason
+}
+
+/// Why dropping `face` for `owner` at `at` is illegal; `None` if it is legal.
+pub fn explain_drop(state: &mut GameState, owner: Owner, face: Face, at: (i32, i32)) -> Option<IllegalReason> {
+    let Some(gyoku) = state.gyoku(owner).and_then(|gi| state.pieces[gi].pos()) else {
+        return Some(IllegalReason::Unavailable);
+    };
+    let hand_idx = state.pieces.iter()
+        .position(|p| p.owner == owner && !p.on_board() && p.cube == face.cube());
+    let Some(hand_idx) = hand_idx else { return Some(IllegalReason::Unavailable) };
+
+    let range = state.rules.drop_range_for(face.cube() == Cube::Ki);
+    if cheb_dist(at.0, at.1, gyoku.0, gyoku.1) > range {
+        return Some(IllegalReason::DropOutOfRange { at, gyoku, range });
+    }
+    if let Some(piece) = state.piece_at(at.0, at.1) {
+        return Some(IllegalReason::Blocked { at, piece });
+    }
+    let opponent = owner.opponent();
+    let enemy = state.gyoku(opponent).and_then(|oi| state.pieces[oi].pos());
+    if let Some(g) = enemy {
+        if cheb_dist(at.0, at.1, g.0, g.1) <= 1 {
+            return Some(IllegalReason::DropNextToGyoku { at, gyoku: g });
+        }
+    }
+
+    let opp_in_check = is_in_check(state, opponent);
+    let undo = make(state, &Action::Drop { piece_idx: hand_idx, face, pos: at });
+    let reason = match enemy {
+        Some(g) if !opp_in_check && attacks(state, hand_idx, g.0, g.1) => {
+            Some(IllegalReason::DropGivesCheck { at, gyoku: g })
+        }
+        _ => check_reason(state, owner),
+    };
+    unmake(state, undo);
+    reason
+}
+
+/// Valid positions for White Gyoku (exactly the Gyoku distance from Black Gyoku)
 pub fn get_white_gyoku_positions(state: &GameState) -> Vec<(i32, i32)> {
+    let dist = state.rules.gyoku_distance;
     let gi = match state.gyoku(Owner::Black) { Some(i) => i, None => return Vec::new() };
     let g = &state.pieces[gi];
     if !g.on_board() { return Vec::new(); }
     let (bx, by) = (g.x.unwrap(), g.y.unwrap());
     let mut pos = Vec::new();
-    for dx in -2..=2i32 {
-        for dy in -2..=2i32 {
-            if dx.abs().max(dy.abs()) == 2 {
+    for dx in -dist..=dist {
+        for dy in -dist..=dist {
+            if dx.abs().max(dy.abs()) == dist {
                 pos.push((bx + dx, by + dy));
             }
         }
     }
     pos
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::get_all_actions;
+    use crate::zobrist::Zobrist;
+
+    fn position(s: &str) -> GameState {
+        GameState::from_position_string(s).unwrap()
+    }
+
+    #[test]
+    fn slider_far_outside_support_range_gives_check() {
+        let state = position("bGyoku0,0;wGyoku2,2;wHi0,40 -/- b basic");
+        let hi = state.piece_at(0, 40).unwrap();
+        // Nowhere near a cell it could move to, yet the ray is open
+        assert!(cheb_dist(0, 40, 2, 2) > state.rules.reach());
+        assert!(attacks(&state, hi, 0, 0));
+        assert!(is_in_check(&state, Owner::Black));
+    }
+
+    #[test]
+    fn quiet_slide_stops_at_support_reach() {
+        let mut state = position("bGyoku0,0;bHi0,1;wGyoku2,2 -/- b basic");
+        let hi = state.piece_at(0, 1).unwrap();
+        let reach = support_reach(&state, 0, 1);
+        assert_eq!(reach, 2 + state.rules.reach());
+        // Past the reach no cell on an open ray can be supported...
+        for (dx, dy) in [(-1, 0), (1, 0), (0, 1)] {
+            assert_eq!(ray_blocker(&state, 0, 1, dx, dy), None);
+            for d in reach + 1..reach + 20 {
+                assert!(!is_piece_supported(&state, dx * d, 1 + dy * d, Cube::Hi));
+            }
+        }
+        // ...so the generator never walks there
+        let moves = get_legal_moves(&mut state, hi);
+        assert!(!moves.is_empty());
+        assert!(moves.iter().all(|m| cheb_dist(0, 1, m.x, m.y) <= reach));
+    }
+
+    #[test]
+    fn capture_on_a_distant_blocker_is_generated() {
+        let mut state = position("bGyoku0,0;bHi0,1;wGyoku2,2;wKi0,30 -/- b ki");
+        let hi = state.piece_at(0, 1).unwrap();
+        let ki = state.piece_at(0, 30).unwrap();
+        assert_eq!(ray_blocker(&state, 0, 1, 0, 1), Some(29));
+        // Far from Black's Gyoku: taking the Ki excuses the Hi from support
+        assert!(cheb_dist(0, 30, 0, 0) > state.rules.reach());
+        let moves = get_legal_moves(&mut state, hi);
+        assert!(moves.iter().any(|m| (m.x, m.y) == (0, 30) && m.capture == Some(ki)));
+        assert!(!moves.iter().any(|m| m.x == 0 && m.y > 30));
+    }
+
+    #[test]
+    fn unmake_restores_every_legal_action() {
+        let positions = [
+            // Kaku takes the Hi and promotes to Ga or Ken
+            "bGyoku0,0;bKaku1,-1;wGyoku2,2;wHi3,1 -/- b basic",
+            // The Gyoku stepping to (1,0) strands the Hi
+            "bGyoku0,0;bHi-1,0;wGyoku2,2 -/- b basic",
+            "bGyoku0,0;bKi1,0;bHi2,0;wGyoku2,2;wKaku3,3 Kaku/Hi,Ki w ki",
+        ];
+        let (mut promoted, mut stranded) = (false, false);
+        for s in positions {
+            let mut state = position(s);
+            let before = state.clone();
+            let turn = state.turn;
+            let actions = get_all_actions(&mut state, turn);
+            assert!(!actions.is_empty(), "{}", s);
+            for action in actions {
+                let undo = make(&mut state, &action);
+                promoted |= matches!(&action, Action::Move { mv, promote_to: Some(_), .. } if mv.capture.is_some());
+                stranded |= undo.stranded().next().is_some();
+                assert_eq!(state.zobrist, Zobrist::of(&state.pieces), "{} {:?}", s, action);
+                unmake(&mut state, undo);
+                assert_eq!(state.pieces, before.pieces, "{} {:?}", s, action);
+                assert_eq!(state.board, before.board);
+                assert_eq!(state.zobrist, before.zobrist);
+                assert_eq!(state.turn, before.turn);
+                assert_eq!(state.in_check, before.in_check);
+            }
+        }
+        assert!(promoted && stranded);
+    }
+
+    #[test]
+    fn explanations_agree_with_the_generators() {
+        let positions = [
+            "bGyoku0,0;bHi0,1;wGyoku2,2;wKaku2,3 Kaku/Hi b basic",
+            "bGyoku0,0;wGyoku2,-2;wHi1,-2 Kaku/- b basic",
+            "bGyoku0,0;wGyoku2,1 Hi/- b basic",
+            "bGyoku0,0;bKi1,0;bHi2,0;wGyoku2,2;wKaku3,3 Kaku/Hi,Ki w ki",
+            "bGyoku0,0;bKi1,0;bHi-1,1;wGyoku2,2;wKaku3,3 Kaku/Hi,Ki b ki",
+        ];
+        let around = |(x, y): (i32, i32), r: i32| {
+            (x - r..=x + r).flat_map(move |tx| (y - r..=y + r).map(move |ty| (tx, ty)))
+        };
+        for s in positions {
+            let mut state = position(s);
+            let turn = state.turn;
+            let own: Vec<usize> = (0..state.pieces.len())
+                .filter(|&i| state.pieces[i].owner == turn && state.pieces[i].on_board())
+                .collect();
+            for pidx in own {
+                let from = state.pieces[pidx].pos().unwrap();
+                let legal: HashSet<(i32, i32)> = get_legal_moves(&mut state, pidx).iter().map(|m| (m.x, m.y)).collect();
+                for to in around(from, 6) {
+                    let reason = explain_move(&mut state, pidx, to);
+                    assert_eq!(reason.is_none(), legal.contains(&to), "{} {:?} {:?}: {:?}", s, from, to, reason);
+                }
+            }
+            let gyoku = state.pieces[state.gyoku(turn).unwrap()].pos().unwrap();
+            for face in Face::all().filter(|f| f.cube() != Cube::Gyoku) {
+                let legal: HashSet<(i32, i32)> = get_legal_drops(&mut state, turn, face).into_iter().collect();
+                for at in around(gyoku, 4) {
+                    let reason = explain_drop(&mut state, turn, face, at);
+                    assert_eq!(reason.is_none(), legal.contains(&at), "{} {:?} {:?}: {:?}", s, face, at, reason);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn every_illegal_reason_is_reported() {
+        let face = |name| Face::from_name(name).unwrap();
+        let mut state = position("bGyoku0,0;bHi0,1;wGyoku2,2;wKaku2,3 Kaku/Hi b basic");
+        let (gyoku, hi) = (state.piece_at(0, 0).unwrap(), state.piece_at(0, 1).unwrap());
+        let kaku_in_hand = state.hand_pieces_for(Owner::Black)[0];
+        let reason = |r: Option<IllegalReason>| r.expect("illegal");
+
+        assert_eq!(reason(explain_move(&mut state, kaku_in_hand, (1, 1))), IllegalReason::Unavailable);
+        assert_eq!(reason(explain_drop(&mut state, Owner::Black, face("Hi"), (1, 0))), IllegalReason::Unavailable);
+        assert_eq!(
+            reason(explain_move(&mut state, hi, (1, 2))),
+            IllegalReason::Unreachable { face: face("Hi"), from: (0, 1), to: (1, 2) },
+        );
+        assert_eq!(reason(explain_move(&mut state, hi, (0, -1))), IllegalReason::Blocked { at: (0, 0), piece: gyoku });
+        assert_eq!(reason(explain_drop(&mut state, Owner::Black, face("Kaku"), (0, 1))), IllegalReason::Blocked { at: (0, 1), piece: hi });
+        assert_eq!(reason(explain_move(&mut state, hi, (0, 3))), IllegalReason::Unsupported { at: (0, 3) });
+        assert_eq!(
+            reason(explain_move(&mut state, gyoku, (-1, -1))),
+            IllegalReason::GyokuDistance { to: (-1, -1), other: (2, 2), required: 2 },
+        );
+        assert_eq!(
+            reason(explain_drop(&mut state, Owner::Black, face("Kaku"), (3, 3))),
+            IllegalReason::DropOutOfRange { at: (3, 3), gyoku: (0, 0), range: 1 },
+        );
+        assert_eq!(
+            reason(explain_drop(&mut state, Owner::Black, face("Kaku"), (1, 1))),
+            IllegalReason::DropNextToGyoku { at: (1, 1), gyoku: (2, 2) },
+        );
+
+        let mut state = position("bGyoku0,0;wGyoku2,-2;wHi1,-2 -/- b basic");
+        let (gyoku, hi) = (state.piece_at(0, 0).unwrap(), state.piece_at(1, -2).unwrap());
+        assert_eq!(
+            reason(explain_move(&mut state, gyoku, (1, 0))),
+            IllegalReason::LeavesCheck { gyoku: (1, 0), attackers: vec![hi] },
+        );
+
+        let mut state = position("bGyoku0,0;wGyoku2,1 Hi/- b basic");
+        assert_eq!(
+            reason(explain_drop(&mut state, Owner::Black, face("Hi"), (-1, 1))),
+            IllegalReason::DropGivesCheck { at: (-1, 1), gyoku: (2, 1) },
+        );
+    }
+}
@@ -0,0 +1,317 @@
+// Ki Shogi - Rule parameters
+
+use std::fmt;
+
+// ---------------------------------------------------------------------------
+// RuleSet
+// ---------------------------------------------------------------------------
+//
+// Written as a preset name followed by the parameters that differ from it,
+// e.g. `ki,repetition=3,perpetual-check=draw`:
+//
+//     gyoku-distance    Chebyshev distance the Gyoku start at and keep    2
+//     drop-range        drops this close to your own Gyoku                1
+//     ki-drop-range     the same for Ki cubes                             2
+//     support-range     a Gyoku or supported Ki supports pieces this near 1
+//     ki-support-range  a Gyoku supports Ki cubes this near               2
+//     repetition        occurrences of a position that end the game       4
+//     perpetual-check   lose | draw: whether the checking side loses at    lose
+//                       the limit when one side was in check every time
+//     gyoku-capture     win | off: with off a Gyoku cannot be captured     win
+//                       and games end only when a side has no legal action
+//     ply-limit         n | off: plies in a row without a capture, drop    off
+//                       or stranding that end the game
+//     ply-limit-result  draw | material: how a game ended by the ply      draw
+//                       limit is scored; material wins it for the side
+//                       with more face value on the board and in hand
+
+/// The rule parameters of a game, read by every rule function from
+/// `GameState::rules`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RuleSet {
+    /// Play with the Ki cubes.
+    pub use_ki: bool,
+    pub gyoku_distance: i32,
+    pub drop_range: i32,
+    pub ki_drop_range: i32,
+    pub support_range: i32,
+    pub ki_support_range: i32,
+    pub repetition: usize,
+    pub perpetual_check_loses: bool,
+    pub gyoku_capture_wins: bool,
+    /// Plies without a capture, drop or stranding that end the game.
+    pub ply_limit: Option<usize>,
+    /// At the ply limit, win on material instead of drawing.
+    pub ply_limit_material: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuleSetError {
+    /// Not a preset name.
+    UnknownPreset(String),
+    /// An override that is not `key=value` with a known key and value.
+    BadParameter(String),
+    /// The Gyoku would start in contact, each attacking the other.
+    GyokuTooClose(i32),
+    /// A drop or support range below 1 (parameter name and value).
+    RangeTooSmall(&'static str, i32),
+    /// Pieces could be dropped where they are unsupported and stranded at
+    /// once (drop parameter name).
+    DropUnsupported(&'static str),
+    /// A position occurs at least once, so a game needs two occurrences to
+    /// repeat.
+    RepetitionTooSmall(usize),
+    /// A ply limit of 0 would end the game before it starts.
+    PlyLimitZero,
+}
+
+impl fmt::Display for RuleSetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuleSetError::UnknownPreset(s) => write!(f, "unknown rule preset '{}' (basic, ki)", s),
+            RuleSetError::BadParameter(s) => write!(f, "bad rule parameter '{}'", s),
+            RuleSetError::GyokuTooClose(d) => write!(f, "gyoku-distance {} is below 2", d),
+            RuleSetError::RangeTooSmall(name, v) => write!(f, "{} {} is below 1", name, v),
+            RuleSetError::DropUnsupported(name) => {
+                write!(f, "{} reaches past the support range; dropped pieces would be stranded", name)
+            }
+            RuleSetError::RepetitionTooSmall(n) => write!(f, "repetition {} is below 2", n),
+            RuleSetError::PlyLimitZero => f.write_str("ply-limit must be at least 1"),
+        }
+    }
+}
+
+impl std::error::Error for RuleSetError {}
+
+impl Default for RuleSet {
+    fn default() -> Self {
+        Self::STANDARD_BASIC
+    }
+}
+
+impl RuleSet {
+    pub const STANDARD_BASIC: RuleSet = RuleSet {
+        use_ki: false,
+        gyoku_distance: 2,
+        drop_range: 1,
+        ki_drop_range: 2,
+        support_range: 1,
+        ki_support_range: 2,
+        repetition: 4,
+        perpetual_check_loses: true,
+        gyoku_capture_wins: true,
+        ply_limit: None,
+        ply_limit_material: false,
+    };
+
+    pub const STANDARD_KI: RuleSet = RuleSet { use_ki: true, ..RuleSet::STANDARD_BASIC };
+
+    /// The standard rules for the basic or Ki set.
+    pub fn standard(use_ki: bool) -> Self {
+        if use_ki { Self::STANDARD_KI } else { Self::STANDARD_BASIC }
+    }
+
+    pub fn preset(name: &str) -> Option<Self> {
+        match name {
+            "basic" => Some(Self::STANDARD_BASIC),
+            "ki" => Some(Self::STANDARD_KI),
+            _ => None,
+        }
+    }
+
+    pub fn preset_name(&self) -> &'static str {
+        if self.use_ki { "ki" } else { "basic" }
+    }
+
+    /// Drop range for a cube, by whether it is a Ki cube.
+    pub fn drop_range_for(&self, is_ki: bool) -> i32 {
+        if is_ki { self.ki_drop_range } else { self.drop_range }
+    }
+
+    /// How far from a Gyoku a piece can be supported or dropped.
+    pub fn reach(&self) -> i32 {
+        self.support_range.max(self.ki_support_range)
+            .max(self.drop_range)
+            .max(self.ki_drop_range)
+    }
+
+    /// Check that the parameters make a playable game.
+    pub fn validate(&self) -> Result<(), RuleSetError> {
+        if self.gyoku_distance < 2 {
+            return Err(RuleSetError::GyokuTooClose(self.gyoku_distance));
+        }
+        for (name, v) in [
+            ("drop-range", self.drop_range),
+            ("ki-drop-range", self.ki_drop_range),
+            ("support-range", self.support_range),
+            ("ki-support-range", self.ki_support_range),
+        ] {
+            if v < 1 {
+                return Err(RuleSetError::RangeTooSmall(name, v));
+            }
+        }
+        if self.drop_range > self.support_range {
+            return Err(RuleSetError::DropUnsupported("drop-range"));
+        }
+        if self.use_ki && self.ki_drop_range > self.ki_support_range {
+            return Err(RuleSetError::DropUnsupported("ki-drop-range"));
+        }
+        if self.repetition < 2 {
+            return Err(RuleSetError::RepetitionTooSmall(self.repetition));
+        }
+        if self.ply_limit == Some(0) {
+            return Err(RuleSetError::PlyLimitZero);
+        }
+        Ok(())
+    }
+
+    /// Parse `<preset>[,key=value...]` and validate the result.
+    pub fn parse(s: &str) -> Result<Self, RuleSetError> {
+        let (name, rest) = s.split_once(',').unwrap_or((s, ""));
+        let rules = Self::preset(name)
+            .ok_or_else(|| RuleSetError::UnknownPreset(name.to_string()))?
+            .with_overrides(rest)?;
+        rules.validate()?;
+        Ok(rules)
+    }
+
+    /// Apply comma-separated `key=value` overrides. Does not validate.
+    pub fn with_overrides(mut self, s: &str) -> Result<Self, RuleSetError> {
+        for item in s.split(',').filter(|i| !i.is_empty()) {
+            let bad = || RuleSetError::BadParameter(item.to_string());
+            let (key, value) = item.split_once('=').ok_or_else(bad)?;
+            let int = || value.parse::<i32>().map_err(|_| bad());
+            match key {
+                "gyoku-distance" => self.gyoku_distance = int()?,
+                "drop-range" => self.drop_range = int()?,
+                "ki-drop-range" => self.ki_drop_range = int()?,
+                "support-range" => self.support_range = int()?,
+                "ki-support-range" => self.ki_support_range = int()?,
+                "repetition" => self.repetition = value.parse().map_err(|_| bad())?,
+                "perpetual-check" => self.perpetual_check_loses = match value {
+                    "lose" => true,
+                    "draw" => false,
+                    _ => return Err(bad()),
+                },
+                "gyoku-capture" => self.gyoku_capture_wins = match value {
+                    "win" => true,
+                    "off" => false,
+                    _ => return Err(bad()),
+                },
+                "ply-limit" => self.ply_limit = match value {
+                    "off" => None,
+                    _ => Some(value.parse().map_err(|_| bad())?),
+                },
+                "ply-limit-result" => self.ply_limit_material = match value {
+                    "material" => true,
+                    "draw" => false,
+                    _ => return Err(bad()),
+                },
+                _ => return Err(bad()),
+            }
+        }
+        Ok(self)
+    }
+
+    /// The parameters that differ from the standard rules of the same set,
+    /// as `key=value` items joined by commas; empty for standard rules.
+    pub fn overrides(&self) -> String {
+        let std = Self::standard(self.use_ki);
+        let mut items = Vec::new();
+        let mut int = |key: &str, v: i32, s: i32| {
+            if v != s { items.push(format!("{}={}", key, v)); }
+        };
+        int("gyoku-distance", self.gyoku_distance, std.gyoku_distance);
+        int("drop-range", self.drop_range, std.drop_range);
+        int("ki-drop-range", self.ki_drop_range, std.ki_drop_range);
+        int("support-range", self.support_range, std.support_range);
+        int("ki-support-range", self.ki_support_range, std.ki_support_range);
+        if self.repetition != std.repetition {
+            items.push(format!("repetition={}", self.repetition));
+        }
+        if self.perpetual_check_loses != std.perpetual_check_loses {
+            let v = if self.perpetual_check_loses { "lose" } else { "draw" };
+            items.push(format!("perpetual-check={}", v));
+        }
+        if self.gyoku_capture_wins != std.gyoku_capture_wins {
+            let v = if self.gyoku_capture_wins { "win" } else { "off" };
+            items.push(format!("gyoku-capture={}", v));
+        }
+        if self.ply_limit != std.ply_limit {
+            let v = self.ply_limit.map_or("off".to_string(), |n| n.to_string());
+            items.push(format!("ply-limit={}", v));
+        }
+        if self.ply_limit_material != std.ply_limit_material {
+            let v = if self.ply_limit_material { "material" } else { "draw" };
+            items.push(format!("ply-limit-result={}", v));
+        }
+        items.join(",")
+    }
+}
+
+/// `<preset>[,key=value...]`, as read by `parse`.
+impl fmt::Display for RuleSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.preset_name())?;
+        let overrides = self.overrides();
+        if !overrides.is_empty() {
+            write!(f, ",{}", overrides)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_parse_to_the_standard_rules() {
+        assert_eq!(RuleSet::parse("basic"), Ok(RuleSet::STANDARD_BASIC));
+        assert_eq!(RuleSet::parse("ki"), Ok(RuleSet::STANDARD_KI));
+        assert_eq!(RuleSet::parse("shogi"), Err(RuleSetError::UnknownPreset("shogi".to_string())));
+    }
+
+    #[test]
+    fn overrides_change_only_their_parameters() {
+        let rules = RuleSet::parse("ki,repetition=3,perpetual-check=draw,ply-limit=100").unwrap();
+        let expected = RuleSet {
+            repetition: 3,
+            perpetual_check_loses: false,
+            ply_limit: Some(100),
+            ..RuleSet::STANDARD_KI
+        };
+        assert_eq!(rules, expected);
+        assert_eq!(rules.overrides(), "repetition=3,perpetual-check=draw,ply-limit=100");
+    }
+
+    #[test]
+    fn bad_parameters_are_refused() {
+        let bad = |s: &str| RuleSetError::BadParameter(s.to_string());
+        assert_eq!(RuleSet::parse("basic,board-size=9"), Err(bad("board-size=9")));
+        assert_eq!(RuleSet::parse("basic,repetition"), Err(bad("repetition")));
+        assert_eq!(RuleSet::parse("basic,gyoku-capture=lose"), Err(bad("gyoku-capture=lose")));
+        assert_eq!(RuleSet::parse("basic,gyoku-distance=0"), Err(RuleSetError::GyokuTooClose(0)));
+        assert_eq!(RuleSet::parse("basic,drop-range=0"), Err(RuleSetError::RangeTooSmall("drop-range", 0)));
+        assert_eq!(RuleSet::parse("basic,drop-range=2"), Err(RuleSetError::DropUnsupported("drop-range")));
+        assert_eq!(RuleSet::parse("ki,repetition=1"), Err(RuleSetError::RepetitionTooSmall(1)));
+        assert_eq!(RuleSet::parse("ki,ply-limit=0"), Err(RuleSetError::PlyLimitZero));
+        // Overrides alone are not validated
+        assert!(RuleSet::STANDARD_BASIC.with_overrides("gyoku-distance=0").is_ok());
+    }
+
+    #[test]
+    fn display_parses_back() {
+        for s in [
+            "basic",
+            "ki",
+            "basic,gyoku-distance=3,support-range=2",
+            "ki,ki-drop-range=3,ki-support-range=3,repetition=2",
+            "basic,gyoku-capture=off,ply-limit=60,ply-limit-result=material",
+        ] {
+            let rules = RuleSet::parse(s).unwrap();
+            assert_eq!(rules.to_string(), s);
+            assert_eq!(RuleSet::parse(&rules.to_string()), Ok(rules));
+        }
+    }
+}
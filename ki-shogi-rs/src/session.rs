@@ -0,0 +1,96 @@
+// Ki Shogi - Players and settings kept from game to game
+
+use crate::types::Owner;
+use crate::rng::Rng;
+
+// ---------------------------------------------------------------------------
+// AiSide
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AiSide {
+    None,
+    Player(Owner),
+    Both,
+}
+
+impl AiSide {
+    pub fn is_ai(&self, owner: Owner) -> bool {
+        match self {
+            AiSide::None => false,
+            AiSide::Player(o) => *o == owner,
+            AiSide::Both => true,
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Session
+// ---------------------------------------------------------------------------
+
+/// Who plays each side and how the AI plays, the random source and the
+/// position games start from. None of it is part of a game: the rules and
+/// the search never see it, and a reset carries it over to the next game.
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub ai_side: AiSide,
+    pub ai_difficulty: u8,
+    /// The AI resigns when its search scores the position below this;
+    /// None, the default, to play every game out.
+    pub ai_resign_below: Option<i32>,
+    /// The AI accepts a draw offer when it scores the position below this;
+    /// None, the default, to decline every offer.
+    pub ai_draw_below: Option<i32>,
+    /// Seed `rng` was last started from; recorded in game records. A new
+    /// game after a reset draws its seed from the previous one's `rng`.
+    pub seed: u64,
+    /// Source of every random choice: AI tie-breaks, the White Gyoku
+    /// placement and the random side.
+    pub rng: Rng,
+    /// Position string games start from instead of the Gyoku setup.
+    pub start_position: Option<String>,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Session {
+    /// Two humans, seeded from the clock.
+    pub fn new() -> Self {
+        Self::seeded(Rng::time_seed())
+    }
+
+    pub fn seeded(seed: u64) -> Self {
+        Self {
+            ai_side: AiSide::None,
+            ai_difficulty: 2,
+            ai_resign_below: None,
+            ai_draw_below: None,
+            seed,
+            rng: Rng::new(seed),
+            start_position: None,
+        }
+    }
+
+    /// Restart `rng` from `seed`.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = Rng::new(seed);
+    }
+
+    /// Ready for another game with the same players.
+    pub fn reset(&mut self) {
+        let seed = self.rng.next_u64();
+        self.set_seed(seed);
+    }
+
+    /// Ready for the menu to choose the players again.
+    pub fn reset_to_menu(&mut self) {
+        self.reset();
+        self.ai_side = AiSide::None;
+        self.ai_difficulty = 2;
+    }
+}
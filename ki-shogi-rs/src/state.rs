@@ -1,15 +1,19 @@
 // Ki Shogi - Piece and GameState
 
-use std::collections::HashMap;
-use std::time::Instant;
+use std::collections::HashSet;
+use std::fmt;
 use crate::types::{Owner, Cube, Face};
-use crate::lang::t;
+use crate::rules::{cheb_dist, is_in_check};
+use crate::zobrist::Zobrist;
+use crate::board::Board;
+use crate::ruleset::{RuleSet, RuleSetError};
+use crate::notation::{Notation, Stranding};
 
 // ---------------------------------------------------------------------------
 // Piece
 // ---------------------------------------------------------------------------
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Piece {
     pub id: usize,
     pub owner: Owner,
@@ -28,10 +32,6 @@ impl Piece {
         self.x.is_some()
     }
 
-    pub fn key(&self) -> String {
-        format!("{},{}", self.x.unwrap_or(0), self.y.unwrap_or(0))
-    }
-
     pub fn pos(&self) -> Option<(i32, i32)> {
         match (self.x, self.y) {
             (Some(x), Some(y)) => Some((x, y)),
@@ -41,79 +41,89 @@ impl Piece {
 }
 
 // ---------------------------------------------------------------------------
-// Mode
-// ---------------------------------------------------------------------------
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum Mode {
-    Menu,
-    SetupBlackGyoku,
-    SetupWhiteGyoku,
-    Board,
-    Selected,
-    Hand,
-    FaceSelect,
-    DropTarget,
-    Promote,
-    GameOver,
-    Draw,
-    Tutorial,
-    LogBrowse,
-}
-
-// ---------------------------------------------------------------------------
-// AiSide
+// MoveLogEntry
 // ---------------------------------------------------------------------------
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum AiSide {
-    None,
-    Player(Owner),
-    Both,
-}
-
-impl AiSide {
-    pub fn is_ai(&self, owner: Owner) -> bool {
-        match self {
-            AiSide::None => false,
-            AiSide::Player(o) => *o == owner,
-            AiSide::Both => true,
-        }
-    }
+pub enum LogKind {
+    /// A Gyoku placed during setup.
+    Place,
+    Move,
+    Capture,
+    Drop,
+    Declare(Declaration),
 }
 
-// ---------------------------------------------------------------------------
-// MoveLogEntry
-// ---------------------------------------------------------------------------
-
+/// One logged ply as typed data; the text is written from it on demand, in
+/// kanji or ASCII (see `notation`).
 #[derive(Debug, Clone)]
 pub struct MoveLogEntry {
     pub num: usize,
     pub owner: Owner,
-    pub text: String,
-    pub snapshot: Vec<PieceSnapshot>,
+    pub kind: LogKind,
+    /// The piece placed, moved or dropped. The fields below down to
+    /// `promote_to` are None for declarations.
+    pub cube: Option<Cube>,
+    /// The face shown before the ply; for a drop, the face in hand.
     pub face: Option<Face>,
+    /// The face shown after the ply: the face dropped, or a flip or
+    /// promotion when it differs from `face`.
+    pub face_after: Option<Face>,
     pub from: Option<(i32, i32)>,
     pub to: Option<(i32, i32)>,
+    pub captured: Option<Face>,
+    /// The face a capturing piece promoted to, chosen or the only one.
+    pub promote_to: Option<Face>,
+    /// The mover's pieces lost to stranding by this ply.
+    pub stranded: Vec<Stranding>,
+    /// The ply left the opponent in check.
+    pub check: bool,
 }
 
-#[derive(Debug, Clone)]
-pub struct PieceSnapshot {
-    pub id: usize,
-    pub owner: Owner,
-    pub cube: Cube,
-    pub face: Face,
-    pub x: Option<i32>,
-    pub y: Option<i32>,
-}
-
-impl PieceSnapshot {
-    pub fn from_piece(p: &Piece) -> Self {
+impl MoveLogEntry {
+    /// An entry with only the owner and kind set, numbered when logged.
+    pub fn new(owner: Owner, kind: LogKind) -> Self {
         Self {
-            id: p.id, owner: p.owner, cube: p.cube,
-            face: p.face, x: p.x, y: p.y,
+            num: 0, owner, kind, cube: None, face: None, face_after: None, from: None, to: None,
+            captured: None, promote_to: None, stranded: Vec::new(), check: false,
         }
     }
+
+    /// The ply in full notation, without the stranding annotations.
+    pub fn notation(&self) -> Notation {
+        let face = self.face.unwrap_or(Face::Gyoku);
+        let to = self.to.unwrap_or_default();
+        match self.kind {
+            LogKind::Place => Notation::Drop { face, to },
+            LogKind::Drop => Notation::Drop { face: self.face_after.unwrap_or(face), to },
+            LogKind::Move => Notation::Move {
+                face, from: self.from, to, flip: self.face_after.filter(|&f| f != face),
+            },
+            LogKind::Capture => Notation::Capture {
+                face, from: self.from, captured: self.captured, to, promote: self.promote_to,
+            },
+            LogKind::Declare(d) => Notation::Declare(d),
+        }
+    }
+
+    /// Kanji text with the stranding annotations, as in game records:
+    /// 玉(0,0)→(1,0) †飛(0,2).
+    pub fn kanji(&self) -> String {
+        let mut text = self.notation().to_string();
+        for s in &self.stranded {
+            text = format!("{} {}", text, s);
+        }
+        text
+    }
+
+    /// The ASCII transliteration of `kanji`: Gyoku(0,0)-(1,0) !Hi(0,2).
+    pub fn to_ascii(&self) -> String {
+        let mut text = self.notation().to_ascii();
+        for s in &self.stranded {
+            text = format!("{} {}", text, s.to_ascii());
+        }
+        text
+    }
 }
 
 // ---------------------------------------------------------------------------
@@ -127,136 +137,229 @@ pub struct LegalMove {
     pub capture: Option<usize>, // piece index
 }
 
+// ---------------------------------------------------------------------------
+// Action
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone)]
+pub enum Action {
+    Move {
+        piece_idx: usize,
+        mv: LegalMove,
+        promote_to: Option<Face>,
+    },
+    Drop {
+        piece_idx: usize,
+        face: Face,
+        pos: (i32, i32),
+    },
+}
+
+/// A statement by a player in place of a move: resigning, or offering,
+/// accepting or declining a draw.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Declaration {
+    Resign,
+    OfferDraw,
+    AcceptDraw,
+    DeclineDraw,
+}
+
+impl Declaration {
+    pub const ALL: [Declaration; 4] = [
+        Declaration::Resign, Declaration::OfferDraw, Declaration::AcceptDraw, Declaration::DeclineDraw,
+    ];
+
+    /// Whether this answers a draw offer, made by the side not to move.
+    pub fn is_answer(self) -> bool {
+        matches!(self, Declaration::AcceptDraw | Declaration::DeclineDraw)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Phase
+// ---------------------------------------------------------------------------
+
+/// Where the game itself stands, independent of what the UI is showing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// The side to move still has to place its Gyoku.
+    Setup,
+    Play,
+    Over,
+}
+
+// ---------------------------------------------------------------------------
+// GameResult
+// ---------------------------------------------------------------------------
+
+/// Why a game ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EndReason {
+    /// The loser's Gyoku was captured.
+    GyokuCaptured,
+    /// The loser had no legal move or drop on their turn.
+    NoLegalAction,
+    /// Fourfold repetition with the loser giving check every time.
+    PerpetualCheck,
+    /// Fourfold repetition without perpetual check.
+    Sennichite,
+    /// Neither side can win whatever is played (see `dead`).
+    DeadPosition,
+    /// `RuleSet::ply_limit` plies without a capture, drop or stranding; a
+    /// draw, or a win on material under `ply_limit_material`.
+    PlyLimit,
+    Resignation,
+    Timeout,
+    /// Both players agreed to a draw.
+    Agreement,
+}
+
+/// How a finished game ended: who won (if anyone) and why.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GameResult {
+    Win { winner: Owner, reason: EndReason },
+    Draw { reason: EndReason },
+}
+
+impl GameResult {
+    pub fn winner(&self) -> Option<Owner> {
+        match self {
+            GameResult::Win { winner, .. } => Some(*winner),
+            GameResult::Draw { .. } => None,
+        }
+    }
+
+    pub fn reason(&self) -> EndReason {
+        match self {
+            GameResult::Win { reason, .. } | GameResult::Draw { reason } => *reason,
+        }
+    }
+
+    pub fn is_draw(&self) -> bool {
+        matches!(self, GameResult::Draw { .. })
+    }
+}
+
 // ---------------------------------------------------------------------------
 // GameState
 // ---------------------------------------------------------------------------
 
+/// The position: pieces, hands, side to move and what the rules track
+/// alongside them. Everything the rules, the search and hashing need, and
+/// nothing else: the move log and repetition counts are kept by `Game`,
+/// the players by `Session` and what the terminal shows by `UiState`.
 #[derive(Debug, Clone)]
 pub struct GameState {
     pub pieces: Vec<Piece>,
     pub turn: Owner,
-    pub use_ki: bool,
-    pub mode: Mode,
-    pub cursor: (i32, i32),
-    pub selected: Option<usize>,
-    pub legal_moves: Vec<LegalMove>,
-    pub hand_pieces: Vec<usize>,   // indices into pieces
-    pub hand_index: usize,
-    pub drop_faces: Vec<Face>,
-    pub face_index: usize,
-    pub drop_targets: Vec<(i32, i32)>,
-    pub promote_choices: Vec<Face>,
-    pub promote_index: usize,
-    pub pending_log_info: Option<PendingLogInfo>,
-    pub message: String,
-    pub winner: Option<Owner>,
+    /// Rule parameters, including the piece set.
+    pub rules: RuleSet,
+    pub phase: Phase,
+    pub result: Option<GameResult>,
     pub in_check: bool,
-    pub ai_side: AiSide,
-    pub ai_difficulty: u8,
-    pub menu_step: u8,
-    pub tutorial_page: usize,
-    pub move_log: Vec<MoveLogEntry>,
-    pub log_index: usize,
-    pub move_num: usize,
-    pub position_history: HashMap<String, Vec<PositionRecord>>,
-    pub paused: bool,
-    pub pre_log_mode: Option<Mode>,
-    pub want_ai: bool,
-    pub ai_think_start: Option<Instant>,
-    pub last_move_from: Option<(i32, i32)>,
-    pub last_move_to: Option<(i32, i32)>,
+    /// The side whose draw offer awaits an answer. Play stops until the
+    /// other side accepts or declines.
+    pub draw_offer: Option<Owner>,
+    /// Plies since the last capture, drop or stranding, for the ply limit.
+    pub quiet_plies: usize,
+    /// `board` and `zobrist` index `pieces`. Anything that changes a piece
+    /// brackets the change with `unindex_piece` / `index_piece`.
+    pub board: Board,
+    pub zobrist: Zobrist,
 }
 
-#[derive(Debug, Clone)]
-pub struct PendingLogInfo {
-    pub owner: Owner,
-    pub cap_base: String,
-    pub face: Face,
-    pub from: (i32, i32),
-    pub to: (i32, i32),
-}
-
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct PositionRecord {
     pub in_check: bool,
 }
 
+impl Default for GameState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl GameState {
     pub fn new() -> Self {
         Self {
             pieces: Vec::new(),
             turn: Owner::Black,
-            use_ki: false,
-            mode: Mode::Menu,
-            cursor: (0, 0),
-            selected: None,
-            legal_moves: Vec::new(),
-            hand_pieces: Vec::new(),
-            hand_index: 0,
-            drop_faces: Vec::new(),
-            face_index: 0,
-            drop_targets: Vec::new(),
-            promote_choices: Vec::new(),
-            promote_index: 0,
-            pending_log_info: None,
-            message: String::new(),
-            winner: None,
+            rules: RuleSet::STANDARD_BASIC,
+            phase: Phase::Setup,
+            result: None,
             in_check: false,
-            ai_side: AiSide::None,
-            ai_difficulty: 2,
-            menu_step: 1,
-            tutorial_page: 0,
-            move_log: Vec::new(),
-            log_index: 0,
-            move_num: 0,
-            position_history: HashMap::new(),
-            paused: false,
-            pre_log_mode: None,
-            want_ai: false,
-            ai_think_start: None,
-            last_move_from: None,
-            last_move_to: None,
+            draw_offer: None,
+            quiet_plies: 0,
+            board: Board::default(),
+            zobrist: Zobrist::default(),
         }
     }
 
-    pub fn start_game(&mut self, use_ki: bool, ai_side: AiSide) {
-        self.use_ki = use_ki;
-        self.ai_side = ai_side;
+    /// Just `pieces` under `rules`, indexed, with Black to move: a position
+    /// to probe.
+    pub fn from_pieces(pieces: Vec<Piece>, rules: RuleSet) -> Self {
+        let mut state = Self::new();
+        state.pieces = pieces;
+        state.rules = rules;
+        state.reindex();
+        state
+    }
+
+    /// Put out a fresh piece set with Black to place its Gyoku first.
+    pub fn init_game(&mut self, use_ki: bool) {
+        self.rules.use_ki = use_ki;
+        self.pieces.clear();
         self.init_pieces();
-        self.mode = Mode::SetupBlackGyoku;
-        self.message = t("place_black_gyoku").to_string();
+        self.reindex();
+        self.turn = Owner::Black;
+        self.phase = Phase::Setup;
+        self.draw_offer = None;
+        self.quiet_plies = 0;
     }
 
     fn init_pieces(&mut self) {
         let mut id = 0usize;
         for &owner in &[Owner::Black, Owner::White] {
             self.pieces.push(Piece::new(id, owner, Cube::Gyoku, Face::Gyoku)); id += 1;
-            self.pieces.push(Piece::new(id, owner, Cube::Hi, Face::Hi)); id += 1;
-            self.pieces.push(Piece::new(id, owner, Cube::Kaku, Face::Kaku)); id += 1;
-            if self.use_ki {
-                self.pieces.push(Piece::new(id, owner, Cube::Ki, Face::Ki)); id += 1;
+            self.pieces.push(Piece::new(id, owner, Cube::Hi, Face::base(Cube::Hi))); id += 1;
+            self.pieces.push(Piece::new(id, owner, Cube::Kaku, Face::base(Cube::Kaku))); id += 1;
+            if self.rules.use_ki {
+                self.pieces.push(Piece::new(id, owner, Cube::Ki, Face::base(Cube::Ki))); id += 1;
             }
         }
     }
 
-    pub fn build_board_map(&self) -> HashMap<String, usize> {
-        let mut map = HashMap::new();
-        for (i, p) in self.pieces.iter().enumerate() {
-            if p.on_board() {
-                map.insert(p.key(), i);
-            }
+    pub fn piece_at(&self, x: i32, y: i32) -> Option<usize> {
+        self.board.get(x, y)
+    }
+
+    pub fn owner_at(&self, x: i32, y: i32) -> Option<Owner> {
+        self.board.get(x, y).map(|i| self.pieces[i].owner)
+    }
+
+    /// Take piece `i` out of the board map and hash before changing it.
+    pub fn unindex_piece(&mut self, i: usize) {
+        let p = &self.pieces[i];
+        if let Some(pos) = p.pos() {
+            self.board.remove(pos);
         }
-        map
+        self.zobrist.remove(p);
     }
 
-    pub fn piece_at(&self, x: i32, y: i32) -> Option<usize> {
-        let key = format!("{},{}", x, y);
-        for (i, p) in self.pieces.iter().enumerate() {
-            if p.on_board() && p.key() == key {
-                return Some(i);
-            }
+    /// Put piece `i` back into the board map and hash after changing it.
+    pub fn index_piece(&mut self, i: usize) {
+        let p = &self.pieces[i];
+        if let Some(pos) = p.pos() {
+            self.board.insert(pos, i);
         }
-        None
+        self.zobrist.add(p);
+    }
+
+    /// Rebuild the board map and hash after replacing `pieces` wholesale.
+    pub fn reindex(&mut self) {
+        self.board = Board::from_pieces(&self.pieces);
+        self.zobrist = Zobrist::of(&self.pieces);
     }
 
     pub fn gyoku(&self, owner: Owner) -> Option<usize> {
@@ -277,25 +380,428 @@ impl GameState {
             .collect()
     }
 
+    /// Hash of the position for repetition: pieces relative to the Black
+    /// Gyoku, hands and side to move.
+    pub fn position_key(&self) -> u64 {
+        let origin = self.gyoku(Owner::Black).and_then(|i| self.pieces[i].pos());
+        self.zobrist.key(origin.unwrap_or((0, 0)), self.turn)
+    }
+
     pub fn switch_turn(&mut self) {
         self.turn = self.turn.opponent();
     }
+}
+
+// ---------------------------------------------------------------------------
+// Validation
+// ---------------------------------------------------------------------------
+//
+// The rules keep a state consistent by construction; `validate` checks that
+// they did. Game runs it after every ply in debug builds, and the position
+// and record loaders run it on what they load. `UiState::validate` adds
+// the UI's hand list.
+
+/// An inconsistency found by `GameState::validate`. Pieces are named by id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    /// Two or more pieces on one cell.
+    SharedCell(i32, i32),
+    /// A piece with only one of `x` and `y` set.
+    HalfPlaced(usize),
+    /// A Gyoku off the board while the game is in play.
+    GyokuInHand(Owner),
+    /// A piece showing a face of another cube.
+    ForeignFace(usize, Face),
+    /// Two or more pieces with one id.
+    DuplicateId(usize),
+    /// The two Gyoku on the board are not the rules' Gyoku distance apart
+    /// (distance found, distance required).
+    GyokuDistance(i32, i32),
+    /// A `UiState::hand_pieces` entry that is not the index of a piece in
+    /// hand.
+    HandIndex(usize),
+}
 
-    pub fn take_snapshot(&self) -> Vec<PieceSnapshot> {
-        self.pieces.iter().map(PieceSnapshot::from_piece).collect()
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::SharedCell(x, y) => write!(f, "two pieces on ({},{})", x, y),
+            Violation::HalfPlaced(id) => write!(f, "piece {} has only one coordinate", id),
+            Violation::GyokuInHand(o) => write!(f, "{:?} Gyoku off the board in play", o),
+            Violation::ForeignFace(id, face) => {
+                write!(f, "piece {} shows {}, not a face of its cube", id, face.name())
+            }
+            Violation::DuplicateId(id) => write!(f, "more than one piece with id {}", id),
+            Violation::GyokuDistance(d, want) => {
+                write!(f, "Gyoku are distance {} apart, must be {}", d, want)
+            }
+            Violation::HandIndex(i) => write!(f, "hand entry {} is not a piece in hand", i),
+        }
     }
+}
+
+/// Every violation in `pieces` under `rules` and `phase`.
+fn violations(pieces: &[Piece], rules: &RuleSet, phase: Phase) -> Vec<Violation> {
+    let mut found = Vec::new();
+    let mut cells = HashSet::new();
+    let mut ids = HashSet::new();
+    for p in pieces {
+        if p.x.is_some() != p.y.is_some() {
+            found.push(Violation::HalfPlaced(p.id));
+        }
+        if let Some((x, y)) = p.pos() {
+            let shared = Violation::SharedCell(x, y);
+            if !cells.insert((x, y)) && !found.contains(&shared) {
+                found.push(shared);
+            }
+        }
+        if !Face::cube_faces(p.cube).contains(&p.face) {
+            found.push(Violation::ForeignFace(p.id, p.face));
+        }
+        let duplicate = Violation::DuplicateId(p.id);
+        if !ids.insert(p.id) && !found.contains(&duplicate) {
+            found.push(duplicate);
+        }
+    }
+
+    let gyoku = |owner: Owner| pieces.iter().find(|p| p.owner == owner && p.cube == Cube::Gyoku);
+    if phase == Phase::Play {
+        for owner in [Owner::Black, Owner::White] {
+            if gyoku(owner).is_some_and(|p| !p.on_board()) {
+                found.push(Violation::GyokuInHand(owner));
+            }
+        }
+    }
+    let black = gyoku(Owner::Black).and_then(|p| p.pos());
+    let white = gyoku(Owner::White).and_then(|p| p.pos());
+    if let (Some((bx, by)), Some((wx, wy))) = (black, white) {
+        let dist = cheb_dist(bx, by, wx, wy);
+        if dist != rules.gyoku_distance {
+            found.push(Violation::GyokuDistance(dist, rules.gyoku_distance));
+        }
+    }
+    found
+}
+
+impl GameState {
+    /// Check the pieces, hands and Gyoku against each other and the rules,
+    /// reporting every violation found.
+    pub fn validate(&self) -> Result<(), Vec<Violation>> {
+        let found = violations(&self.pieces, &self.rules, self.phase);
+        if found.is_empty() { Ok(()) } else { Err(found) }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Position strings
+// ---------------------------------------------------------------------------
+//
+// A position is four space-separated fields:
+//
+//     <board> <hands> <side> <set>
+//
+//   board  pieces separated by ';', each <owner><face><x>,<y>
+//          e.g. bGyoku0,0;bCho1,1;wGyoku2,2
+//   hands  Black's hand, '/', White's hand; faces separated by ',',
+//          '-' for an empty hand, e.g. Kaku/Hi,Ki
+//   side   'b' or 'w' to move
+//   set    'basic' or 'ki', then any rule parameters that differ from
+//          its standard rules, as `--rules` takes them: ki,repetition=3
+//
+// Faces are written with their ASCII name (`Face::name`) and may also be
+// typed as kanji: b玉0,0;w玉2,2 -/- b basic. Each side needs its Gyoku on
+// the board; other cubes may be missing (at most two of each in total) to
+// allow study positions.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PositionError {
+    /// Wrong number of space-separated fields.
+    FieldCount(usize),
+    /// A board piece that is not <owner><face><x>,<y>.
+    BadPiece(String),
+    /// A face name that is neither a kanji nor an ASCII face name.
+    BadFace(String),
+    BadSide(String),
+    BadSet(String),
+    /// Rule parameters after the set that do not parse or validate.
+    BadRules(RuleSetError),
+    /// Hands field without exactly one '/'.
+    BadHands(String),
+    /// Two pieces on one cell.
+    Occupied(i32, i32),
+    GyokuInHand(Owner),
+    MissingGyoku(Owner),
+    /// More pieces of a cube than the set contains (two of every cube,
+    /// one Gyoku per side).
+    TooMany(Cube),
+    /// A Ki cube in a position without the Ki set.
+    KiWithoutKiSet,
+    /// The two Gyoku are not exactly the rules' Gyoku distance apart
+    /// (distance found, distance required).
+    GyokuDistance(i32, i32),
+    /// The side not to move is in check, which play never leaves.
+    OpponentInCheck(Owner),
+    /// A position the checks above let through that fails validation.
+    Inconsistent(Vec<Violation>),
+}
 
-    pub fn reset(&mut self) {
-        let use_ki = self.use_ki;
-        let ai_side = self.ai_side;
-        let ai_diff = self.ai_difficulty;
-        *self = Self::new();
-        self.use_ki = use_ki;
-        self.ai_side = ai_side;
-        self.ai_difficulty = ai_diff;
+impl fmt::Display for PositionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PositionError::FieldCount(n) => write!(f, "expected 4 fields, found {}", n),
+            PositionError::BadPiece(s) => write!(f, "malformed piece '{}'", s),
+            PositionError::BadFace(s) => write!(f, "unknown face '{}'", s),
+            PositionError::BadSide(s) => write!(f, "side to move must be 'b' or 'w', not '{}'", s),
+            PositionError::BadSet(s) => write!(f, "piece set must be 'basic' or 'ki', not '{}'", s),
+            PositionError::BadRules(e) => write!(f, "invalid rules: {}", e),
+            PositionError::BadHands(s) => write!(f, "malformed hands '{}'", s),
+            PositionError::Occupied(x, y) => write!(f, "two pieces on ({},{})", x, y),
+            PositionError::GyokuInHand(o) => write!(f, "{:?} Gyoku in hand", o),
+            PositionError::MissingGyoku(o) => write!(f, "{:?} Gyoku missing from the board", o),
+            PositionError::TooMany(c) => write!(f, "too many {:?} cubes", c),
+            PositionError::KiWithoutKiSet => write!(f, "Ki cube in a basic-set position"),
+            PositionError::GyokuDistance(d, want) => {
+                write!(f, "Gyoku are distance {} apart, must be {}", d, want)
+            }
+            PositionError::OpponentInCheck(o) => write!(f, "{:?} is in check but not to move", o),
+            PositionError::Inconsistent(found) => write_violations(f, found),
+        }
+    }
+}
+
+impl std::error::Error for PositionError {}
+
+/// The violations separated by "; ".
+pub(crate) fn write_violations(f: &mut fmt::Formatter<'_>, found: &[Violation]) -> fmt::Result {
+    for (i, v) in found.iter().enumerate() {
+        if i > 0 { f.write_str("; ")?; }
+        write!(f, "{}", v)?;
+    }
+    Ok(())
+}
+
+fn owner_char(owner: Owner) -> char {
+    match owner {
+        Owner::Black => 'b',
+        Owner::White => 'w',
+    }
+}
+
+fn parse_face(s: &str) -> Result<Face, PositionError> {
+    Face::from_name(s).ok_or_else(|| PositionError::BadFace(s.to_string()))
+}
+
+/// <owner><face><x>,<y>
+fn parse_board_piece(tok: &str) -> Result<(Owner, Face, i32, i32), PositionError> {
+    let bad = || PositionError::BadPiece(tok.to_string());
+    let mut chars = tok.chars();
+    let owner = match chars.next() {
+        Some('b') => Owner::Black,
+        Some('w') => Owner::White,
+        _ => return Err(bad()),
+    };
+    let rest = chars.as_str();
+    let split = rest.find(|c: char| c == '-' || c.is_ascii_digit()).ok_or_else(bad)?;
+    let (name, coords) = rest.split_at(split);
+    let face = parse_face(name)?;
+    let (x, y) = coords.split_once(',').ok_or_else(bad)?;
+    let x = x.parse().map_err(|_| bad())?;
+    let y = y.parse().map_err(|_| bad())?;
+    Ok((owner, face, x, y))
+}
+
+fn parse_hand(s: &str) -> Result<Vec<Face>, PositionError> {
+    if s == "-" { return Ok(Vec::new()); }
+    s.split(',').map(parse_face).collect()
+}
+
+impl GameState {
+    /// Encode the pieces, hands, side to move, piece set and rules as a
+    /// position string (see above).
+    pub fn to_position_string(&self) -> String {
+        let board: Vec<String> = self.pieces.iter()
+            .filter(|p| p.on_board())
+            .map(|p| format!("{}{}{},{}", owner_char(p.owner), p.face.name(), p.x.unwrap(), p.y.unwrap()))
+            .collect();
+        let hand = |owner: Owner| {
+            let faces: Vec<&str> = self.hand_pieces_for(owner).iter()
+                .map(|&i| self.pieces[i].face.name())
+                .collect();
+            if faces.is_empty() { "-".to_string() } else { faces.join(",") }
+        };
+        format!(
+            "{} {}/{} {} {}",
+            board.join(";"),
+            hand(Owner::Black),
+            hand(Owner::White),
+            owner_char(self.turn),
+            self.rules,
+        )
+    }
+
+    /// A state in play from a position string.
+    pub fn from_position_string(s: &str) -> Result<Self, PositionError> {
+        let mut state = Self::new();
+        state.load_position(s)?;
+        Ok(state)
+    }
+
+    /// Replace the position (pieces, hands, side to move, piece set) with
+    /// the given one. Rule parameters it lists override the current rules;
+    /// the others are left alone. Nothing is changed on error.
+    pub fn load_position(&mut self, s: &str) -> Result<(), PositionError> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        if fields.len() != 4 {
+            return Err(PositionError::FieldCount(fields.len()));
+        }
+        let (board, hands, side, set) = (fields[0], fields[1], fields[2], fields[3]);
+
+        let turn = match side {
+            "b" => Owner::Black,
+            "w" => Owner::White,
+            _ => return Err(PositionError::BadSide(side.to_string())),
+        };
+        let (set, overrides) = set.split_once(',').unwrap_or((set, ""));
+        let use_ki = match set {
+            "basic" => false,
+            "ki" => true,
+            _ => return Err(PositionError::BadSet(set.to_string())),
+        };
+        let rules = RuleSet { use_ki, ..self.rules }
+            .with_overrides(overrides)
+            .and_then(|r| r.validate().map(|_| r))
+            .map_err(PositionError::BadRules)?;
+
+        let mut pieces: Vec<Piece> = Vec::new();
+        let mut occupied = HashSet::new();
+        for tok in board.split(';').filter(|t| !t.is_empty()) {
+            let (owner, face, x, y) = parse_board_piece(tok)?;
+            if !occupied.insert((x, y)) {
+                return Err(PositionError::Occupied(x, y));
+            }
+            let mut p = Piece::new(pieces.len(), owner, face.cube(), face);
+            p.x = Some(x);
+            p.y = Some(y);
+            pieces.push(p);
+        }
+        let (black_hand, white_hand) = hands.split_once('/')
+            .filter(|(_, w)| !w.contains('/'))
+            .ok_or_else(|| PositionError::BadHands(hands.to_string()))?;
+        for (owner, hand) in [(Owner::Black, black_hand), (Owner::White, white_hand)] {
+            for face in parse_hand(hand)? {
+                if face.cube() == Cube::Gyoku {
+                    return Err(PositionError::GyokuInHand(owner));
+                }
+                pieces.push(Piece::new(pieces.len(), owner, face.cube(), face));
+            }
+        }
+
+        // Piece counts against the set
+        for p in &pieces {
+            if p.cube == Cube::Ki && !use_ki {
+                return Err(PositionError::KiWithoutKiSet);
+            }
+            let same = |q: &&Piece| q.cube == p.cube && (p.cube != Cube::Gyoku || q.owner == p.owner);
+            let limit = if p.cube == Cube::Gyoku { 1 } else { 2 };
+            if pieces.iter().filter(same).count() > limit {
+                return Err(PositionError::TooMany(p.cube));
+            }
+        }
+        let gyoku_pos = |owner: Owner| {
+            pieces.iter()
+                .find(|p| p.owner == owner && p.cube == Cube::Gyoku)
+                .and_then(|p| p.pos())
+                .ok_or(PositionError::MissingGyoku(owner))
+        };
+        let (bx, by) = gyoku_pos(Owner::Black)?;
+        let (wx, wy) = gyoku_pos(Owner::White)?;
+        let dist = cheb_dist(bx, by, wx, wy);
+        if dist != rules.gyoku_distance {
+            return Err(PositionError::GyokuDistance(dist, rules.gyoku_distance));
+        }
+        let found = violations(&pieces, &rules, Phase::Play);
+        if !found.is_empty() {
+            return Err(PositionError::Inconsistent(found));
+        }
+        let probe = Self::from_pieces(pieces, rules);
+        if is_in_check(&probe, turn.opponent()) {
+            return Err(PositionError::OpponentInCheck(turn.opponent()));
+        }
+        let pieces = probe.pieces;
+
+        self.pieces = pieces;
+        self.reindex();
+        self.turn = turn;
+        self.rules = rules;
+        self.phase = Phase::Play;
+        self.result = None;
+        self.draw_offer = None;
+        self.quiet_plies = 0;
+        self.in_check = is_in_check(self, turn);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn position_with_the_side_not_to_move_in_check_is_refused() {
+        let mut state = GameState::new();
+        let err = state.load_position("bGyoku0,0;wGyoku2,2;bHi2,5 -/- b basic").unwrap_err();
+        assert!(matches!(err, PositionError::OpponentInCheck(Owner::White)), "{}", err);
+        assert!(state.pieces.is_empty());
+        // The side to move may be in check
+        state.load_position("bGyoku0,0;wGyoku2,2;bHi2,5 -/- w basic").unwrap();
+        assert!(state.in_check);
+    }
+
+    #[test]
+    fn log_entries_keep_their_text() {
+        let face = |name| Face::from_name(name).unwrap();
+        let entry = |kind, name, after, from, to| {
+            let mut e = MoveLogEntry::new(Owner::Black, kind);
+            e.cube = Some(face(name).cube());
+            e.face = Some(face(name));
+            e.face_after = Some(face(after));
+            e.from = from;
+            e.to = Some(to);
+            e
+        };
+        let quiet = entry(LogKind::Move, "Hi", "Cho", Some((0, 1)), (0, 3));
+        let mut capture = entry(LogKind::Capture, "Kaku", "Ga", Some((2, 2)), (3, 3));
+        capture.captured = Some(face("Hi"));
+        capture.promote_to = Some(face("Ga"));
+        let drop = entry(LogKind::Drop, "Ki", "Ki", None, (1, -1));
+        let mut stranding = entry(LogKind::Move, "Gyoku", "Gyoku", Some((0, 0)), (1, 0));
+        stranding.stranded.push(Stranding { face: face("Hi"), at: (0, 2) });
+        stranding.stranded.push(Stranding { face: face("Myou"), at: (-1, 1) });
+        let declaration = MoveLogEntry::new(Owner::White, LogKind::Declare(Declaration::OfferDraw));
+
+        let expected = [
+            ("飛(0,1)→(0,3)=猪", "Hi(0,1)-(0,3)=Cho"),
+            ("角(2,2)×飛(3,3)→瓦", "Kaku(2,2)xHi(3,3)-Ga"),
+            ("麒↓(1,-1)", "Ki*(1,-1)"),
+            ("玉(0,0)→(1,0) †飛(0,2) †猫(-1,1)", "Gyoku(0,0)-(1,0) !Hi(0,2) !Myou(-1,1)"),
+            ("提和", "offer-draw"),
+        ];
+        for (e, (kanji, ascii)) in [quiet, capture, drop, stranding, declaration].iter().zip(expected) {
+            assert_eq!(e.kanji(), kanji);
+            assert_eq!(e.to_ascii(), ascii);
+        }
     }
 
-    pub fn reset_to_menu(&mut self) {
-        *self = Self::new();
+    #[test]
+    fn position_strings_keep_house_rules() {
+        let s = "bGyoku0,0;bHi0,1;wGyoku3,3 Kaku/- w ki,gyoku-distance=3,repetition=3";
+        let state = GameState::from_position_string(s).unwrap();
+        let rules = RuleSet { gyoku_distance: 3, repetition: 3, ..RuleSet::STANDARD_KI };
+        assert_eq!(state.rules, rules);
+        assert_eq!(state.to_position_string(), s);
+        assert_eq!(GameState::from_position_string(&state.to_position_string()).unwrap().rules, rules);
+
+        let err = GameState::from_position_string("bGyoku0,0;wGyoku2,2 -/- b ki,repetition=1").unwrap_err();
+        assert_eq!(err, PositionError::BadRules(RuleSetError::RepetitionTooSmall(1)));
     }
 }
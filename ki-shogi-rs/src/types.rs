@@ -1,5 +1,8 @@
 // Ki Shogi - Type definitions, constants, movement data
 
+use std::fmt;
+use crate::pieceset::{self, FaceDef};
+
 // ---------------------------------------------------------------------------
 // Owner
 // ---------------------------------------------------------------------------
@@ -51,173 +54,89 @@ pub enum Cube {
 // Face
 // ---------------------------------------------------------------------------
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub enum Face {
-    Gyoku,
-    Hi,
-    Cho,
-    Han,
-    Chuu,
-    Ou,
-    Shu,
-    Kaku,
-    Myou,
-    Hon,
-    Ga,
-    Zou,
-    Ken,
-    Ki,
-    Hou,
-    Ro,
-    Ja,
-    Ba,
-    Ryuu,
+/// A face of a cube, as an index into the active piece set (see
+/// `pieceset`). Faces are numbered cube by cube, so the Gyoku is face 0.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Face(u8);
+
+impl fmt::Debug for Face {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
 }
 
 impl Face {
+    /// The face of the Gyoku cube.
+    #[allow(non_upper_case_globals)]
+    pub const Gyoku: Face = Face(0);
+
+    pub(crate) fn from_index(i: usize) -> Face {
+        Face(i as u8)
+    }
+
+    /// Position in the active piece set.
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+
+    fn def(&self) -> &'static FaceDef {
+        pieceset::active().face(*self)
+    }
+
+    /// Every face, cube by cube.
+    pub fn all() -> impl Iterator<Item = Face> {
+        (0..pieceset::active().len()).map(Face::from_index)
+    }
+
+    /// ASCII transliteration of the kanji, used in typed notation.
+    pub fn name(&self) -> &'static str {
+        &self.def().name
+    }
+
+    /// Look a face up by kanji or by ASCII name (case-insensitive).
+    pub fn from_name(s: &str) -> Option<Face> {
+        Face::all().find(|f| f.kanji() == s || f.name().eq_ignore_ascii_case(s))
+    }
+
     /// The kanji character for this face.
     pub fn kanji(&self) -> &'static str {
-        match self {
-            Face::Gyoku => "玉",
-            Face::Hi    => "飛",
-            Face::Cho   => "猪",
-            Face::Han   => "反",
-            Face::Chuu  => "仲",
-            Face::Ou    => "横",
-            Face::Shu   => "竪",
-            Face::Kaku  => "角",
-            Face::Myou  => "猫",
-            Face::Hon   => "奔",
-            Face::Ga    => "瓦",
-            Face::Zou   => "雑",
-            Face::Ken   => "犬",
-            Face::Ki    => "麒",
-            Face::Hou   => "鳳",
-            Face::Ro    => "驢",
-            Face::Ja    => "蛇",
-            Face::Ba    => "馬",
-            Face::Ryuu  => "龍",
-        }
+        &self.def().kanji
     }
 
     /// The English name for this face.
     pub fn english(&self) -> &'static str {
-        match self {
-            Face::Gyoku => "Jewel",
-            Face::Hi    => "Flying",
-            Face::Cho   => "Boar",
-            Face::Han   => "Reverse",
-            Face::Chuu  => "Between",
-            Face::Ou    => "Side",
-            Face::Shu   => "Vertical",
-            Face::Kaku  => "Horns",
-            Face::Myou  => "Cat",
-            Face::Hon   => "Flee",
-            Face::Ga    => "Tile",
-            Face::Zou   => "Misc",
-            Face::Ken   => "Dog",
-            Face::Ki    => "Unicorn",
-            Face::Hou   => "Phoenix",
-            Face::Ro    => "Donkey",
-            Face::Ja    => "Snake",
-            Face::Ba    => "Horse",
-            Face::Ryuu  => "Dragon",
-        }
+        &self.def().english
     }
 
     /// Which cube this face belongs to.
     pub fn cube(&self) -> Cube {
-        match self {
-            Face::Gyoku => Cube::Gyoku,
-            Face::Hi | Face::Cho | Face::Han | Face::Chuu | Face::Ou | Face::Shu => Cube::Hi,
-            Face::Kaku | Face::Myou | Face::Hon | Face::Ga | Face::Zou | Face::Ken => Cube::Kaku,
-            Face::Ki | Face::Hou | Face::Ro | Face::Ja | Face::Ba | Face::Ryuu => Cube::Ki,
-        }
+        self.def().cube
     }
 
     /// The opposite face on the same cube. Gyoku has no opposite.
     pub fn opposite(&self) -> Option<Face> {
-        match self {
-            Face::Gyoku => None,
-            Face::Hi    => Some(Face::Cho),
-            Face::Cho   => Some(Face::Hi),
-            Face::Han   => Some(Face::Chuu),
-            Face::Chuu  => Some(Face::Han),
-            Face::Ou    => Some(Face::Shu),
-            Face::Shu   => Some(Face::Ou),
-            Face::Kaku  => Some(Face::Myou),
-            Face::Myou  => Some(Face::Kaku),
-            Face::Hon   => Some(Face::Ga),
-            Face::Ga    => Some(Face::Hon),
-            Face::Zou   => Some(Face::Ken),
-            Face::Ken   => Some(Face::Zou),
-            Face::Ki    => Some(Face::Hou),
-            Face::Hou   => Some(Face::Ki),
-            Face::Ro    => Some(Face::Ja),
-            Face::Ja    => Some(Face::Ro),
-            Face::Ba    => Some(Face::Ryuu),
-            Face::Ryuu  => Some(Face::Ba),
-        }
+        self.def().opposite
     }
 
     /// Promotion choices when this face captures. Gyoku cannot promote.
     pub fn promotions(&self) -> &'static [Face] {
-        match self {
-            Face::Gyoku => &[],
-            Face::Hi    => &[Face::Chuu],
-            Face::Cho   => &[Face::Ou, Face::Shu],
-            Face::Han   => &[Face::Shu],
-            Face::Chuu  => &[Face::Cho, Face::Han, Face::Ou],
-            Face::Ou    => &[Face::Hi],
-            Face::Shu   => &[Face::Hi],
-            Face::Kaku  => &[Face::Ga, Face::Ken],
-            Face::Myou  => &[Face::Kaku],
-            Face::Hon   => &[Face::Kaku],
-            Face::Ga    => &[Face::Hon, Face::Myou],
-            Face::Zou   => &[Face::Kaku],
-            Face::Ken   => &[Face::Zou, Face::Myou],
-            Face::Ki    => &[Face::Ryuu, Face::Ba],
-            Face::Hou   => &[Face::Ryuu, Face::Ba],
-            Face::Ro    => &[Face::Ki, Face::Hou],
-            Face::Ja    => &[Face::Ki, Face::Hou],
-            Face::Ba    => &[Face::Ja, Face::Ro],
-            Face::Ryuu  => &[Face::Ja, Face::Ro],
-        }
+        &self.def().promotions
     }
 
     /// All faces belonging to a given cube.
     pub fn cube_faces(cube: Cube) -> &'static [Face] {
-        match cube {
-            Cube::Gyoku => &[Face::Gyoku],
-            Cube::Hi    => &[Face::Hi, Face::Cho, Face::Han, Face::Chuu, Face::Ou, Face::Shu],
-            Cube::Kaku  => &[Face::Kaku, Face::Myou, Face::Hon, Face::Ga, Face::Zou, Face::Ken],
-            Cube::Ki    => &[Face::Ki, Face::Hou, Face::Ro, Face::Ja, Face::Ba, Face::Ryuu],
-        }
+        pieceset::active().cube_faces(cube)
+    }
+
+    /// The face a cube is dropped with at the start of a game and turns
+    /// back to when stranded.
+    pub fn base(cube: Cube) -> Face {
+        Face::cube_faces(cube)[0]
     }
 
     /// Heuristic value for AI evaluation.
     pub fn face_value(&self) -> i32 {
-        match self {
-            Face::Gyoku => 0,
-            Face::Hi    => 80,
-            Face::Cho   => 30,
-            Face::Han   => 50,
-            Face::Chuu  => 15,
-            Face::Ou    => 55,
-            Face::Shu   => 55,
-            Face::Kaku  => 80,
-            Face::Myou  => 30,
-            Face::Hon   => 60,
-            Face::Ga    => 20,
-            Face::Zou   => 60,
-            Face::Ken   => 20,
-            Face::Ki    => 45,
-            Face::Hou   => 45,
-            Face::Ro    => 25,
-            Face::Ja    => 25,
-            Face::Ba    => 20,
-            Face::Ryuu  => 20,
-        }
+        self.def().value
     }
 }
 
@@ -240,11 +159,15 @@ pub struct MoveComponent {
 }
 
 // ---------------------------------------------------------------------------
-// Direction categories (internal)
+// Direction categories
 // ---------------------------------------------------------------------------
 
+/// Movement directions relative to the owner, as written in piece set
+/// files: one cell forward, back, left or right (FO BO LO RO), forward or
+/// backward diagonals (FD BD), all orthogonals or diagonals (O D), two-cell
+/// jumps (JO JD JFO JBO JBD) and knight jumps (FK BK).
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-enum DirCategory {
+pub enum DirCategory {
     FO,
     BO,
     LO,
@@ -262,6 +185,25 @@ enum DirCategory {
     BK,
 }
 
+impl DirCategory {
+    pub fn from_name(s: &str) -> Option<DirCategory> {
+        use DirCategory::*;
+        Some(match s {
+            "FO" => FO, "BO" => BO, "LO" => LO, "RO" => RO,
+            "FD" => FD, "BD" => BD, "O" => O, "D" => D,
+            "JO" => JO, "JD" => JD, "JFO" => JFO, "JBO" => JBO, "JBD" => JBD,
+            "FK" => FK, "BK" => BK,
+            _ => return None,
+        })
+    }
+
+    /// Whether every direction is one cell, so it can slide.
+    pub fn is_single(self) -> bool {
+        use DirCategory::*;
+        matches!(self, FO | BO | LO | RO | FD | BD | O | D)
+    }
+}
+
 /// Resolve a direction category into concrete (dx, dy) vectors for the given
 /// owner.
 fn resolve_dirs(cat: DirCategory, owner: Owner) -> Vec<(i32, i32)> {
@@ -286,31 +228,9 @@ fn resolve_dirs(cat: DirCategory, owner: Owner) -> Vec<(i32, i32)> {
     }
 }
 
-/// Internal: the movement rule for each face, expressed as (MoveMode, &[DirCategory]).
-fn face_move_spec(face: Face) -> &'static [(MoveMode, &'static [DirCategory])] {
-    use DirCategory::*;
-    use MoveMode::*;
-    match face {
-        Face::Gyoku => &[(Step,  &[O, D])],
-        Face::Hi    => &[(Slide, &[O])],
-        Face::Cho   => &[(Step,  &[O])],
-        Face::Han   => &[(Slide, &[FO, BO])],
-        Face::Chuu  => &[(Step,  &[FO, BO])],
-        Face::Ou    => &[(Step,  &[FO, BO]), (Slide, &[LO, RO])],
-        Face::Shu   => &[(Step,  &[LO, RO]), (Slide, &[FO, BO])],
-        Face::Kaku  => &[(Slide, &[D])],
-        Face::Myou  => &[(Step,  &[D])],
-        Face::Hon   => &[(Slide, &[BO, FD])],
-        Face::Ga    => &[(Step,  &[BO, FD])],
-        Face::Zou   => &[(Slide, &[BD, FO])],
-        Face::Ken   => &[(Step,  &[BD, FO])],
-        Face::Ki    => &[(Step,  &[D, JO])],
-        Face::Hou   => &[(Step,  &[O, JD])],
-        Face::Ro    => &[(Step,  &[LO, RO, JFO, JBO])],
-        Face::Ja    => &[(Step,  &[LO, RO, JFO, JBD])],
-        Face::Ba    => &[(Step,  &[FK, BK])],
-        Face::Ryuu  => &[(Step,  &[JD])],
-    }
+/// Internal: the movement rule for a face, from the active piece set.
+fn face_move_spec(face: Face) -> &'static [(MoveMode, Vec<DirCategory>)] {
+    &face.def().moves
 }
 
 /// Returns all movement vectors for a face owned by the given player.
@@ -322,7 +242,8 @@ fn face_move_spec(face: Face) -> &'static [(MoveMode, &'static [DirCategory])] {
 pub fn get_move_components(face: Face, owner: Owner) -> Vec<MoveComponent> {
     let spec = face_move_spec(face);
     let mut result = Vec::new();
-    for &(mode, cats) in spec {
+    for (mode, cats) in spec {
+        let mode = *mode;
         let mut dirs = Vec::new();
         for &cat in cats {
             dirs.extend(resolve_dirs(cat, owner));
@@ -335,8 +256,8 @@ pub fn get_move_components(face: Face, owner: Owner) -> Vec<MoveComponent> {
 pub fn get_move_vectors(face: Face, owner: Owner) -> Vec<(i32, i32, bool)> {
     let spec = face_move_spec(face);
     let mut result = Vec::new();
-    for &(mode, cats) in spec {
-        let is_slide = mode == MoveMode::Slide;
+    for (mode, cats) in spec {
+        let is_slide = *mode == MoveMode::Slide;
         for &cat in cats {
             for (dx, dy) in resolve_dirs(cat, owner) {
                 result.push((dx, dy, is_slide));
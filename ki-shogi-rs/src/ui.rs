@@ -0,0 +1,217 @@
+// Ki Shogi - Terminal UI state
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Instant;
+use crate::types::Face;
+use crate::state::{GameState, LegalMove, Violation};
+use crate::session::{AiSide, Session};
+use crate::event::GameEvent;
+use crate::game::Game;
+use crate::notation::Stranding;
+use crate::lang::{get_lang, t, tn, Lang};
+
+// ---------------------------------------------------------------------------
+// Mode
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Menu,
+    SetupBlackGyoku,
+    SetupWhiteGyoku,
+    Board,
+    Selected,
+    Hand,
+    FaceSelect,
+    DropTarget,
+    Promote,
+    GameOver,
+    Draw,
+    Tutorial,
+    LogBrowse,
+    /// The side to move is asked to confirm resigning.
+    ConfirmResign,
+    /// The side not to move is asked to answer a draw offer.
+    DrawOffer,
+}
+
+// ---------------------------------------------------------------------------
+// UiState
+// ---------------------------------------------------------------------------
+
+/// The game being played, its players and what the terminal UI shows of
+/// it: menus, cursor, selections in progress and highlights. The game never
+/// looks at any of this, so the AI works on `game` alone; the UI follows it
+/// through the events it queues.
+#[derive(Debug)]
+pub struct UiState {
+    pub game: Game,
+    pub session: Session,
+    events: Rc<RefCell<Vec<GameEvent>>>,
+    pub mode: Mode,
+    pub cursor: (i32, i32),
+    pub selected: Option<usize>,
+    pub legal_moves: Vec<LegalMove>,
+    pub hand_pieces: Vec<usize>,   // indices into pieces
+    pub hand_index: usize,
+    pub drop_faces: Vec<Face>,
+    pub face_index: usize,
+    pub drop_targets: Vec<(i32, i32)>,
+    pub promote_choices: Vec<Face>,
+    pub promote_index: usize,
+    pub pending_move: Option<LegalMove>,
+    pub message: String,
+    pub menu_step: u8,
+    pub tutorial_page: usize,
+    pub log_index: usize,
+    pub paused: bool,
+    pub pre_log_mode: Option<Mode>,
+    pub want_ai: bool,
+    pub ai_think_start: Option<Instant>,
+    pub last_move_from: Option<(i32, i32)>,
+    pub last_move_to: Option<(i32, i32)>,
+    /// Squares emptied by stranding in the last ply, highlighted until the
+    /// next one.
+    pub last_stranded: Vec<(i32, i32)>,
+    /// Record file last saved or loaded, reopened with O.
+    pub record_path: Option<String>,
+}
+
+impl UiState {
+    /// The main menu, over the given game and players.
+    pub fn new(game: Game, session: Session) -> Self {
+        let mut ui = Self {
+            game,
+            session,
+            events: Rc::default(),
+            mode: Mode::Menu,
+            cursor: (0, 0),
+            selected: None,
+            legal_moves: Vec::new(),
+            hand_pieces: Vec::new(),
+            hand_index: 0,
+            drop_faces: Vec::new(),
+            face_index: 0,
+            drop_targets: Vec::new(),
+            promote_choices: Vec::new(),
+            promote_index: 0,
+            pending_move: None,
+            message: String::new(),
+            menu_step: 1,
+            tutorial_page: 0,
+            log_index: 0,
+            paused: false,
+            pre_log_mode: None,
+            want_ai: false,
+            ai_think_start: None,
+            last_move_from: None,
+            last_move_to: None,
+            last_stranded: Vec::new(),
+            record_path: None,
+        };
+        ui.subscribe();
+        ui
+    }
+
+    /// Replace the game, following its events from now on.
+    pub fn set_game(&mut self, game: Game) {
+        self.game = game;
+        self.events.borrow_mut().clear();
+        self.subscribe();
+    }
+
+    fn subscribe(&mut self) {
+        let events = Rc::clone(&self.events);
+        self.game.subscribe(move |event: &GameEvent, _: &_| events.borrow_mut().push(event.clone()));
+    }
+
+    /// Update the highlights and status message from the events the game
+    /// queued since the last call.
+    pub fn follow_events(&mut self) {
+        let events = std::mem::take(&mut *self.events.borrow_mut());
+        let mut stranded = Vec::new();
+        for event in events {
+            match event {
+                GameEvent::Moved { from, to, .. } => {
+                    self.last_move_from = Some(from);
+                    self.last_move_to = Some(to);
+                    stranded.clear();
+                }
+                GameEvent::Dropped { at, .. } => {
+                    self.last_move_from = None;
+                    self.last_move_to = Some(at);
+                    stranded.clear();
+                }
+                GameEvent::Stranded { face, at, .. } => stranded.push(Stranding { face, at }),
+                GameEvent::RepetitionWarning { perpetual_check } => {
+                    self.message = if perpetual_check {
+                        t("perpetual_check_warning").to_string()
+                    } else {
+                        tn("sennichite_warning", self.game.state.rules.repetition - 1)
+                    };
+                }
+                GameEvent::Undone => {
+                    let last = self.game.move_log().last();
+                    self.last_move_from = last.and_then(|e| e.from);
+                    self.last_move_to = last.and_then(|e| e.to);
+                    stranded = last.map(|e| e.stranded.clone()).unwrap_or_default();
+                }
+                _ => {}
+            }
+        }
+        self.last_stranded = stranded.iter().map(|s| s.at).collect();
+        if !stranded.is_empty() && self.message.is_empty() {
+            let names: Vec<String> = stranded.iter().map(|s| match get_lang() {
+                Lang::Zh => s.to_string(),
+                Lang::En => s.to_ascii(),
+            }).collect();
+            self.message = format!("{}{}", t("stranded"), names.join(" "));
+        }
+    }
+
+    /// A fresh game with Black to place its Gyoku. Nothing from the
+    /// previous one can be undone.
+    pub fn start_game(&mut self, use_ki: bool, ai_side: AiSide) {
+        let mut state = std::mem::take(&mut self.game.state);
+        self.session.ai_side = ai_side;
+        state.init_game(use_ki);
+        self.set_game(Game::from_state(state));
+        self.mode = Mode::SetupBlackGyoku;
+        self.message = t("place_black_gyoku").to_string();
+    }
+
+    /// Back to the main menu for a new game with the same players.
+    pub fn reset(&mut self) {
+        self.session.reset();
+        self.clear();
+    }
+
+    pub fn reset_to_menu(&mut self) {
+        self.session.reset_to_menu();
+        self.clear();
+    }
+
+    /// Everything but the rules, the players and the record path back to
+    /// the menu.
+    fn clear(&mut self) {
+        let mut state = GameState::new();
+        state.rules = self.game.state.rules;
+        let session = self.session.clone();
+        let record_path = self.record_path.take();
+        *self = Self::new(Game::from_state(state), session);
+        self.record_path = record_path;
+    }
+
+    /// `GameState::validate`, plus the hand list against the pieces.
+    pub fn validate(&self) -> Result<(), Vec<Violation>> {
+        let pieces = &self.game.state.pieces;
+        let mut found = self.game.state.validate().err().unwrap_or_default();
+        for &i in &self.hand_pieces {
+            if pieces.get(i).is_none_or(|p| p.on_board()) {
+                found.push(Violation::HandIndex(i));
+            }
+        }
+        if found.is_empty() { Ok(()) } else { Err(found) }
+    }
+}
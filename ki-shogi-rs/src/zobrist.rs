@@ -0,0 +1,187 @@
+// Ki Shogi - Incremental position hashing
+
+use crate::types::{Face, Owner};
+use crate::state::Piece;
+
+// ---------------------------------------------------------------------------
+// Translation-invariant Zobrist hashing
+// ---------------------------------------------------------------------------
+//
+// On an infinite board a position is the same wherever it stands, so the
+// hash has to ignore translation, and classic XOR-per-square keys cannot do
+// that incrementally: moving the Black Gyoku would move the origin of every
+// other key. Instead each board piece contributes
+//
+//     key(owner, face) * A^x * B^y        (mod P, P = 2^61 - 1)
+//
+// and the board sum is kept incrementally in absolute coordinates. Shifting
+// every piece by (dx, dy) multiplies the sum by A^dx * B^dy, so multiplying
+// by A^-bx * B^-by, with (bx, by) the Black Gyoku, gives the same value
+// for every translation of the position. Hands are a plain Zobrist sum of
+// one key per (owner, cube) in hand, and the side to move is XORed in last.
+
+const P: u64 = (1 << 61) - 1;
+
+/// splitmix64, for fixed pseudo-random keys.
+const fn mix(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+const fn mul(a: u64, b: u64) -> u64 {
+    let m = a as u128 * b as u128;
+    let r = (m as u64 & P) + (m >> 61) as u64;
+    if r >= P { r - P } else { r }
+}
+
+const fn pow(mut base: u64, mut exp: u64) -> u64 {
+    let mut r = 1;
+    while exp > 0 {
+        if exp & 1 == 1 { r = mul(r, base); }
+        base = mul(base, base);
+        exp >>= 1;
+    }
+    r
+}
+
+const A: u64 = mix(1) % P;
+const B: u64 = mix(2) % P;
+const A_INV: u64 = pow(A, P - 2);
+const B_INV: u64 = pow(B, P - 2);
+const SIDE: u64 = mix(3);
+
+const CUBES: usize = 4;
+
+/// Board key per owner and face, nonzero below P. Computed rather than
+/// tabled, since the number of faces depends on the piece set.
+fn board_key(owner: Owner, face: Face) -> u64 {
+    mix(1000 + 256 * owner as u64 + face.index() as u64) % (P - 1) + 1
+}
+
+/// Hand keys per owner and cube.
+const HAND_KEYS: [[u64; CUBES]; 2] = {
+    let mut keys = [[0; CUBES]; 2];
+    let mut i = 0;
+    while i < 2 * CUBES {
+        keys[i / CUBES][i % CUBES] = mix(200 + i as u64);
+        i += 1;
+    }
+    keys
+};
+
+/// A^n (or B^n) for a signed exponent.
+fn signed_pow(base: u64, inv: u64, n: i32) -> u64 {
+    if n >= 0 { pow(base, n as u64) } else { pow(inv, n.unsigned_abs() as u64) }
+}
+
+/// Running hash of the pieces; see `key` for the position key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Zobrist {
+    /// Sum of board terms in absolute coordinates, mod P.
+    board: u64,
+    /// Wrapping sum of hand keys.
+    hand: u64,
+}
+
+impl Zobrist {
+    /// Hash from scratch.
+    pub fn of(pieces: &[Piece]) -> Self {
+        let mut z = Self::default();
+        for p in pieces {
+            z.add(p);
+        }
+        z
+    }
+
+    fn board_term(p: &Piece, x: i32, y: i32) -> u64 {
+        let key = board_key(p.owner, p.face);
+        mul(key, mul(signed_pow(A, A_INV, x), signed_pow(B, B_INV, y)))
+    }
+
+    /// Count `p` as it currently is (on the board or in hand).
+    pub fn add(&mut self, p: &Piece) {
+        match p.pos() {
+            Some((x, y)) => {
+                let sum = self.board + Self::board_term(p, x, y);
+                self.board = if sum >= P { sum - P } else { sum };
+            }
+            None => self.hand = self.hand.wrapping_add(HAND_KEYS[p.owner as usize][p.cube as usize]),
+        }
+    }
+
+    /// Stop counting `p`; call before changing it, then `add` it again.
+    pub fn remove(&mut self, p: &Piece) {
+        match p.pos() {
+            Some((x, y)) => {
+                let sum = self.board + P - Self::board_term(p, x, y);
+                self.board = if sum >= P { sum - P } else { sum };
+            }
+            None => self.hand = self.hand.wrapping_sub(HAND_KEYS[p.owner as usize][p.cube as usize]),
+        }
+    }
+
+    /// The position key with the Black Gyoku at `origin` and `turn` to
+    /// move. Equal for positions that differ only by translation.
+    pub fn key(&self, origin: (i32, i32), turn: Owner) -> u64 {
+        let shift = mul(signed_pow(A, A_INV, -origin.0), signed_pow(B, B_INV, -origin.1));
+        let side = if turn == Owner::White { SIDE } else { 0 };
+        // Spread the 61-bit board value over all 64 bits
+        mix(mul(self.board, shift)) ^ self.hand ^ side
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Cube;
+    use crate::state::GameState;
+    use crate::rules::make;
+    use crate::ai::get_all_actions;
+    use crate::rng::Rng;
+
+    fn key(s: &str) -> u64 {
+        GameState::from_position_string(s).unwrap().position_key()
+    }
+
+    #[test]
+    fn translation_leaves_the_key_unchanged() {
+        let state = GameState::from_position_string("bGyoku0,0;bHi0,1;bKi-1,0;wGyoku2,2;wKaku3,3 -/Hi,Kaku b ki").unwrap();
+        let key = state.position_key();
+        for (dx, dy) in [(1, 0), (0, -1), (-7, 12), (300, -4000)] {
+            let mut pieces = state.pieces.clone();
+            for p in pieces.iter_mut().filter(|p| p.on_board()) {
+                p.x = p.x.map(|x| x + dx);
+                p.y = p.y.map(|y| y + dy);
+            }
+            assert_eq!(Zobrist::of(&pieces).key((dx, dy), state.turn), key, "({}, {})", dx, dy);
+        }
+    }
+
+    #[test]
+    fn incremental_hash_matches_a_rebuild() {
+        let mut state = GameState::from_position_string("bGyoku0,0;bHi0,1;wGyoku2,2;wKaku2,3 Kaku/Hi,Ki w ki").unwrap();
+        let mut rng = Rng::new(7);
+        for _ in 0..200 {
+            let turn = state.turn;
+            let actions = get_all_actions(&mut state, turn);
+            // Taking a Gyoku ends the game
+            let gyoku_on_board = state.pieces.iter().filter(|p| p.cube == Cube::Gyoku && p.on_board()).count();
+            if actions.is_empty() || gyoku_on_board < 2 {
+                break;
+            }
+            make(&mut state, &actions[rng.below(actions.len())]);
+            assert_eq!(state.zobrist, Zobrist::of(&state.pieces));
+        }
+    }
+
+    #[test]
+    fn hands_and_side_to_move_change_the_key() {
+        let board = "bGyoku0,0;bHi0,1;wGyoku2,2;wKaku2,3";
+        let base = key(&format!("{} Kaku/Hi b basic", board));
+        assert_ne!(key(&format!("{} Kaku/Hi w basic", board)), base);
+        assert_ne!(key(&format!("{} -/Kaku,Hi b basic", board)), base);
+        assert_ne!(key(&format!("{} Kaku,Hi/- b basic", board)), base);
+    }
+}